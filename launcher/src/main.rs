@@ -1,77 +1,89 @@
-use std::{path::Path, sync::Mutex, time::Instant};
+use std::{sync::Mutex, time::Instant};
 
-use prism::{Camera, Key, PrismRenderer, Vertex, glm::{self, Mat4, Vec2, Vec3, Vec4}};
+use prism::{Camera, Key, PrismRenderer, backend::Screen, config::GraphicsApi, layout::ScreenLayout, material::Material, mesh::Geometry, model::{ModelData, ModelInstance}, post::{PostEffect, PostProcessStack, ToneMapOperator}, shader, sprite::{NineSlice, Sprite, SpriteBatch}, atlas::TextureRegion, font::{Font, TextAlign, TextOptions}, texture::TextureDescriptor, uniform_block::{CameraBlock, Light, LightsBlock}, glm::{Mat4, Vec2, Vec3, Vec4}, Rect};
 
 fn main() {
     let mut ctx = PrismRenderer::new();
 
+    // e.g. PRISM_LAYOUT=stacked to get both screens in one window on a desktop
+    if let Ok(layout) = std::env::var("PRISM_LAYOUT") {
+        match layout.parse::<ScreenLayout>() {
+            Ok(layout) => ctx.set_layout(layout).expect("Layout is set before init"),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    // e.g. PRISM_GRAPHICS_API=gles to try the GLES path on a desktop
+    if let Ok(api) = std::env::var("PRISM_GRAPHICS_API") {
+        match api.parse::<GraphicsApi>() {
+            Ok(api) => {
+                let mut config = ctx.get_config().clone();
+                config.graphics_api = api;
+                ctx.set_config(config).expect("Config is set before init");
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
     match ctx.init() {
         Ok(()) => {
-            let vertices = vec![
-                Vertex { position: Vec3::new(-0.5, -0.5, -0.5), normal: Vec3::new(0.0, 0.0, -1.0), tex_coords: Vec2::new(0.0, 0.0) },
-                Vertex { position: Vec3::new(0.5, -0.5, -0.5), normal: Vec3::new(0.0, 0.0, -1.0), tex_coords: Vec2::new(1.0, 0.0) },
-                Vertex { position: Vec3::new(0.5, 0.5, -0.5), normal: Vec3::new(0.0, 0.0, -1.0), tex_coords: Vec2::new(1.0, 1.0) },
-                Vertex { position: Vec3::new(0.5, 0.5, -0.5), normal: Vec3::new(0.0, 0.0, -1.0), tex_coords: Vec2::new(1.0, 1.0) },
-                Vertex { position: Vec3::new(-0.5, 0.5, -0.5), normal: Vec3::new(0.0, 0.0, -1.0), tex_coords: Vec2::new(0.0, 1.0) },
-                Vertex { position: Vec3::new(-0.5, -0.5, -0.5), normal: Vec3::new(0.0, 0.0, -1.0), tex_coords: Vec2::new(0.0, 0.0) },
-                
-                Vertex { position: Vec3::new(-0.5, -0.5, 0.5), normal: Vec3::new(0.0, 0.0, 1.0), tex_coords: Vec2::new(0.0, 0.0) },
-                Vertex { position: Vec3::new(0.5, -0.5, 0.5), normal: Vec3::new(0.0, 0.0, 1.0), tex_coords: Vec2::new(1.0, 0.0) },
-                Vertex { position: Vec3::new(0.5, 0.5, 0.5), normal: Vec3::new(0.0, 0.0, 1.0), tex_coords: Vec2::new(1.0, 1.0) },
-                Vertex { position: Vec3::new(0.5, 0.5, 0.5), normal: Vec3::new(0.0, 0.0, 1.0), tex_coords: Vec2::new(1.0, 1.0) },
-                Vertex { position: Vec3::new(-0.5, 0.5, 0.5), normal: Vec3::new(0.0, 0.0, 1.0), tex_coords: Vec2::new(0.0, 1.0) },
-                Vertex { position: Vec3::new(-0.5, -0.5, 0.5), normal: Vec3::new(0.0, 0.0, 1.0), tex_coords: Vec2::new(0.0, 0.0) },
-
-                Vertex { position: Vec3::new(-0.5, 0.5, 0.5), normal: Vec3::new(-1.0, 0.0, 0.0), tex_coords: Vec2::new(1.0, 0.0) },
-                Vertex { position: Vec3::new(-0.5, 0.5, -0.5), normal: Vec3::new(-1.0, 0.0, 0.0), tex_coords: Vec2::new(1.0, 1.0) },
-                Vertex { position: Vec3::new(-0.5, -0.5, -0.5), normal: Vec3::new(-1.0, 0.0, 0.0), tex_coords: Vec2::new(0.0, 1.0) },
-                Vertex { position: Vec3::new(-0.5, -0.5, -0.5), normal: Vec3::new(-1.0, 0.0, 0.0), tex_coords: Vec2::new(0.0, 1.0) },
-                Vertex { position: Vec3::new(-0.5, -0.5, 0.5), normal: Vec3::new(-1.0, 0.0, 0.0), tex_coords: Vec2::new(0.0, 0.0) },
-                Vertex { position: Vec3::new(-0.5, 0.5, 0.5), normal: Vec3::new(-1.0, 0.0, 0.0), tex_coords: Vec2::new(1.0, 0.0) },
-
-                Vertex { position: Vec3::new(0.5, 0.5, 0.5), normal: Vec3::new(1.0, 0.0, 0.0), tex_coords: Vec2::new(1.0, 0.0) },
-                Vertex { position: Vec3::new(0.5, 0.5, -0.5), normal: Vec3::new(1.0, 0.0, 0.0), tex_coords: Vec2::new(1.0, 1.0) },
-                Vertex { position: Vec3::new(0.5, -0.5, -0.5), normal: Vec3::new(1.0, 0.0, 0.0), tex_coords: Vec2::new(0.0, 1.0) },
-                Vertex { position: Vec3::new(0.5, -0.5, -0.5), normal: Vec3::new(1.0, 0.0, 0.0), tex_coords: Vec2::new(0.0, 1.0) },
-                Vertex { position: Vec3::new(0.5, -0.5, 0.5), normal: Vec3::new(1.0, 0.0, 0.0), tex_coords: Vec2::new(0.0, 0.0) },
-                Vertex { position: Vec3::new(0.5, 0.5, 0.5), normal: Vec3::new(1.0, 0.0, 0.0), tex_coords: Vec2::new(1.0, 0.0) },
-
-                Vertex { position: Vec3::new(-0.5, -0.5, -0.5), normal: Vec3::new(0.0, -1.0, 0.0), tex_coords: Vec2::new(0.0, 1.0) },
-                Vertex { position: Vec3::new(0.5, -0.5, -0.5), normal: Vec3::new(0.0, -1.0, 0.0), tex_coords: Vec2::new(1.0, 1.0) },
-                Vertex { position: Vec3::new(0.5, -0.5, 0.5), normal: Vec3::new(0.0, -1.0, 0.0), tex_coords: Vec2::new(1.0, 0.0) },
-                Vertex { position: Vec3::new(0.5, -0.5, 0.5), normal: Vec3::new(0.0, -1.0, 0.0), tex_coords: Vec2::new(1.0, 0.0) },
-                Vertex { position: Vec3::new(-0.5, -0.5, 0.5), normal: Vec3::new(0.0, -1.0, 0.0), tex_coords: Vec2::new(0.0, 0.0) },
-                Vertex { position: Vec3::new(-0.5, -0.5, -0.5), normal: Vec3::new(0.0, -1.0, 0.0), tex_coords: Vec2::new(0.0, 1.0) },
-                
-                Vertex { position: Vec3::new(-0.5, 0.5, -0.5), normal: Vec3::new(0.0, 1.0, 0.0), tex_coords: Vec2::new(0.0, 1.0) },
-                Vertex { position: Vec3::new(0.5, 0.5, -0.5), normal: Vec3::new(0.0, 1.0, 0.0), tex_coords: Vec2::new(1.0, 1.0) },
-                Vertex { position: Vec3::new(0.5, 0.5, 0.5), normal: Vec3::new(0.0, 1.0, 0.0), tex_coords: Vec2::new(1.0, 0.0) },
-                Vertex { position: Vec3::new(0.5, 0.5, 0.5), normal: Vec3::new(0.0, 1.0, 0.0), tex_coords: Vec2::new(1.0, 0.0) },
-                Vertex { position: Vec3::new(-0.5, 0.5, 0.5), normal: Vec3::new(0.0, 1.0, 0.0), tex_coords: Vec2::new(0.0, 0.0) },
-                Vertex { position: Vec3::new(-0.5, 0.5, -0.5), normal: Vec3::new(0.0, 1.0, 0.0), tex_coords: Vec2::new(0.0, 1.0) },
-            ];
-
-            let indices = vec![
-                0, 1, 2, 3, 4, 5,
-                6, 7, 8, 9, 10, 11,
-                12, 13, 14, 15, 16, 17,
-                18, 19, 20, 21, 22, 23,
-                24, 25, 26, 27, 28, 29,
-                30, 31, 32, 33, 34, 35,
-            ];
-
-            let texture = ctx.create_texture(&Path::new("/Users/alula/Desktop/shiota-os/launcher/src/texture.png"));
-
-            let mut triangle_mesh = ctx.create_mesh(
-                vertices,
-                indices,
-                vec![texture],
-            );
+            // e.g. PRISM_POST=1 to run the upper screen through bloom, tone mapping and FXAA
+            if std::env::var("PRISM_POST").is_ok() {
+                let stack = PostProcessStack::new()
+                    .with(PostEffect::Bloom { threshold: 0.8, intensity: 0.6, passes: 3 })
+                    .with(PostEffect::ToneMapping { operator: ToneMapOperator::Aces, exposure: 1.0 })
+                    .with(PostEffect::Fxaa);
+                if let Err(e) = ctx.set_post_processing(Screen::Upper, stack) {
+                    eprintln!("{}", e);
+                }
+            }
+
+            let texture = ctx
+                .create_texture_from_memory(include_bytes!("texture.png"), &TextureDescriptor::default())
+                .expect("Failed to load the launcher texture");
+
+            let panel = NineSlice::uniform(TextureRegion::new(texture.clone()), 16);
+            let mut sprites = SpriteBatch::new();
+
+            // e.g. PRISM_FONT=/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf to label the panel
+            let mut font = std::env::var("PRISM_FONT").ok().and_then(|path| match Font::from_file(std::path::Path::new(&path), 18.0) {
+                Ok(font) => Some(font),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    None
+                }
+            });
+            let label = font.as_mut().map(|font| {
+                let options = TextOptions { max_width: Some(136.0), align: TextAlign::Center, ..TextOptions::default() };
+                ctx.layout_text(font, "Prism", &options).expect("Failed to lay out the launcher label")
+            });
 
-            let vertex_shader_src = include_str!("shaders/vertex.vert");
-            let fragment_shader_src = include_str!("shaders/fragment.frag");
+            let mut triangle_mesh = ctx.create_mesh_from_geometry(Geometry::cube(1.0), vec![texture]);
 
-            let mut shader = ctx.create_shader_from_source(vertex_shader_src, fragment_shader_src).unwrap();
+            // Debug builds load prism's built-in shaders from the source tree and reload them on save.
+            let shader = if cfg!(debug_assertions) {
+                let shader_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../prism/src/shaders");
+                ctx.set_shader_hot_reload(true);
+                ctx.create_shader_from_files(&shader_dir.join("phong.vert"), &shader_dir.join("phong.frag")).unwrap()
+            }
+            else {
+                ctx.create_shader_from_source(shader::PHONG_VERTEX, shader::PHONG_FRAGMENT).unwrap()
+            };
+
+            let mut material = Material::new(&shader);
+            material.set_phong(Vec3::new(0.725, 0.949, 1.0), Vec3::new(0.745, 0.949, 1.0), Vec3::new(0.5, 0.5, 0.5), 32.0);
+
+            // e.g. PRISM_MODEL=assets/helmet.glb to draw an imported model next to the cube
+            let mut model_instances: Vec<ModelInstance> = match std::env::var("PRISM_MODEL") {
+                Ok(path) => match ModelData::load(std::path::Path::new(&path)).and_then(|data| ctx.create_model(&data, &shader)) {
+                    Ok(model) => model.instances(Mat4::from_translation(Vec3::new(4.0, 0.0, 0.0))),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        Vec::new()
+                    }
+                },
+                Err(_) => Vec::new(),
+            };
 
             let mut camera = Camera::new(
                 Vec3::new(0.0, 0.0, 8.0),
@@ -80,7 +92,14 @@ fn main() {
                 0.0f32.to_radians(),
             );
 
-            let projection_transform = glm::Mat4::perspective_rh(45.0f32.to_radians(), 800.0 / 480.0, 0.1, 100.0);
+            let projection_transform = camera.get_projection_matrix(ctx.aspect_ratio(Screen::Upper));
+            let lights = LightsBlock::new(vec![
+                Light::point(Vec3::new(1.2, 1.0, 8.0), Vec3::new(0.2, 0.2, 0.2), Vec3::new(1.0, 1.0, 1.0), Vec3::new(1.0, 1.0, 1.0)),
+                Light::spot(Vec3::new(-6.0, 0.0, 2.0), Vec3::new(1.0, 0.0, -0.3), 15.0f32.to_radians(), 25.0f32.to_radians(), Vec3::ZERO, Vec3::new(1.0, 0.3, 0.2), Vec3::new(1.0, 0.3, 0.2))
+                    .with_attenuation(1.0, 0.045, 0.0075),
+            ]);
+
+            let mut screenshot_key_held = false;
 
             while !ctx.should_close() {
                 let color = Vec4::new(0.0, 0.0, 0.0, 1.0);
@@ -109,30 +128,61 @@ fn main() {
                 ctx.handle_events();
                 ctx.begin_upper_screen();
                 ctx.clear_screen(color);
-                shader.set_uniform_mat4("view", camera.get_view_matrix());
-                shader.set_uniform_mat4("projection", projection_transform);
-                shader.set_uniform_vec3("viewPos", camera.get_position());
-                shader.set_uniform_vec3("material.ambient", Vec3::new(0.725, 0.949, 1.0));
-                shader.set_uniform_vec3("material.diffuse", Vec3::new(0.745, 0.949, 1.0));
-                shader.set_uniform_vec3("material.specular", Vec3::new(0.5, 0.5, 0.5));
-                shader.set_uniform_float("material.shininess", 32.0);
-                shader.set_uniform_vec3("light.position", Vec3::new(1.2, 1.0, 8.0));
-                shader.set_uniform_vec3("light.ambient", Vec3::new(0.2, 0.2, 0.2));
-                shader.set_uniform_vec3("light.diffuse", Vec3::new(1.0, 1.0, 1.0));
-                shader.set_uniform_vec3("light.specular", Vec3::new(1.0, 1.0, 1.0));
-                ctx.draw_mesh(&triangle_mesh, &mut shader);
+                ctx.set_camera_block(&CameraBlock { view: camera.get_view_matrix(), projection: projection_transform, view_position: camera.get_position() });
+                ctx.set_lights_block(&lights);
+                ctx.draw_mesh(&triangle_mesh, &mut material);
+                for instance in &mut model_instances {
+                    ctx.draw_mesh(&instance.mesh, &mut instance.material);
+                }
                 ctx.end_upper_screen();
                 ctx.begin_lower_screen();
                 ctx.clear_screen(color);
-                shader.set_uniform_mat4("view", camera.get_view_matrix());
-                shader.set_uniform_mat4("projection", projection_transform);
-                shader.set_uniform_vec3("viewPos", camera.get_position());
-                ctx.draw_mesh(&triangle_mesh, &mut shader);
+                ctx.set_camera_block(&CameraBlock { view: camera.get_view_matrix(), projection: projection_transform, view_position: camera.get_position() });
+                ctx.set_lights_block(&lights);
+                ctx.draw_mesh(&triangle_mesh, &mut material);
+
+                sprites.clear();
+                sprites.draw_nine_slice(&panel, Rect::new(8.0, 8.0, 160.0, 64.0), Vec4::ONE, 0);
+                sprites.draw_rect(Rect::new(20.0, 56.0, 136.0, 6.0), Vec4::new(0.2, 0.8, 0.3, 0.8), 1);
+                sprites.draw(&Sprite::colored(Vec2::splat(24.0), Vec4::new(1.0, 0.6, 0.1, 1.0))
+                    .at(Vec2::new(40.0, 32.0))
+                    .with_origin(Vec2::splat(0.5))
+                    .rotated(ctx.get_time() as f32)
+                    .layer(1));
+                if let Some(label) = &label {
+                    sprites.draw_text(label, Vec2::new(20.0, 20.0), Vec4::ONE, 2);
+                }
+                ctx.draw_sprite_batch(&sprites);
                 ctx.end_lower_screen();
+
+                if ctx.key_pressed(Key::F12) {
+                    if !screenshot_key_held {
+                        save_screenshots(&mut ctx);
+                    }
+                    screenshot_key_held = true;
+                }
+                else {
+                    screenshot_key_held = false;
+                }
             }
         }
         Err(e) => {
             eprintln!("Failed to initialize OpenGL2DRenderer: {}", e);
         }
     }
-}
\ No newline at end of file
+}
+
+fn save_screenshots(ctx: &mut PrismRenderer) {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    for (screen, name) in [(Screen::Upper, "upper"), (Screen::Lower, "lower")] {
+        let path = format!("screenshot_{}_{}.png", timestamp, name);
+        match ctx.capture_screen(screen).save(&path) {
+            Ok(()) => println!("Saved screenshot to {}", path),
+            Err(e) => eprintln!("Failed to save screenshot {}: {}", path, e),
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+
+use image::RgbaImage;
+
+use crate::{Rect, Texture, texture::{self, TextureDescriptor}};
+
+const SIDECAR_HEADER: &str = "prism-atlas 1";
+
+/// Where a packed image ended up, in pixels of its page with a top-left origin.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PackedRegion {
+    pub name: String,
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Packs many small images into as few atlas pages as fit within `max_size`.
+pub struct AtlasBuilder {
+    max_size: u32,
+    padding: u32,
+    bleed: u32,
+    images: Vec<(String, RgbaImage)>,
+}
+
+impl AtlasBuilder {
+    pub fn new(max_size: u32) -> Self {
+        Self {
+            max_size,
+            padding: 1,
+            bleed: 0,
+            images: Vec::new(),
+        }
+    }
+
+    /// Transparent pixels left between neighbouring images.
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Pixels each image's border is extruded by, so linear filtering and mipmaps at its
+    /// edges do not pick up colour from its neighbours.
+    pub fn bleed(mut self, bleed: u32) -> Self {
+        self.bleed = bleed;
+        self
+    }
+
+    pub fn add_image(&mut self, name: &str, image: RgbaImage) -> Result<(), String> {
+        if self.images.iter().any(|(existing, _)| existing == name) {
+            return Err(format!("Atlas already contains an image named \"{}\"", name));
+        }
+
+        if image.width() == 0 || image.height() == 0 {
+            return Err(format!("Atlas image \"{}\" has no pixels", name));
+        }
+
+        // Padding goes on both sides of the cell, like the bleed.
+        let margin = 2 * (self.bleed + self.padding);
+        if image.width() + margin > self.max_size || image.height() + margin > self.max_size {
+            return Err(format!(
+                "Atlas image \"{}\" is {}x{}, which does not fit a {}x{} page with its padding and bleed",
+                name, image.width(), image.height(), self.max_size, self.max_size
+            ));
+        }
+
+        self.images.push((name.to_string(), image));
+        Ok(())
+    }
+
+    /// Loads an image file, named after its file stem.
+    pub fn add_file(&mut self, path: &Path) -> Result<(), String> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .ok_or_else(|| format!("{} has no file name", path.to_string_lossy()))?;
+        self.add_image(&name, texture::load_file(path)?)
+    }
+
+    /// Packs the images onto shelves, tallest first, opening a new page whenever the current
+    /// one is full. Pages are cropped to the area used.
+    pub fn build(mut self) -> Result<PackedAtlas, String> {
+        if self.images.is_empty() {
+            return Err("Atlas has no images".to_string());
+        }
+
+        self.images.sort_by(|(a_name, a), (b_name, b)| b.height().cmp(&a.height()).then(b.width().cmp(&a.width())).then(a_name.cmp(b_name)));
+
+        let border = self.bleed;
+        let spacing = self.padding;
+        let mut placements: Vec<(usize, u32, u32)> = Vec::with_capacity(self.images.len());
+        let mut page_sizes: Vec<(u32, u32)> = Vec::new();
+
+        let (mut page, mut shelf_x, mut shelf_y, mut shelf_height) = (0, spacing, spacing, 0);
+        page_sizes.push((0, 0));
+
+        for (_, image) in &self.images {
+            let (cell_width, cell_height) = (image.width() + 2 * border, image.height() + 2 * border);
+
+            // `add_image` made sure every image fits an empty shelf and page, so those are never
+            // left behind.
+            if shelf_x > spacing && shelf_x + cell_width + spacing > self.max_size {
+                shelf_y += shelf_height + spacing;
+                shelf_x = spacing;
+                shelf_height = 0;
+            }
+
+            if shelf_y > spacing && shelf_y + cell_height + spacing > self.max_size {
+                page += 1;
+                page_sizes.push((0, 0));
+                shelf_x = spacing;
+                shelf_y = spacing;
+                shelf_height = 0;
+            }
+
+            placements.push((page, shelf_x + border, shelf_y + border));
+
+            let size = &mut page_sizes[page];
+            size.0 = size.0.max(shelf_x + cell_width + spacing);
+            size.1 = size.1.max(shelf_y + cell_height + spacing);
+
+            shelf_x += cell_width + spacing;
+            shelf_height = shelf_height.max(cell_height);
+        }
+
+        let mut pages: Vec<RgbaImage> = page_sizes.iter().map(|&(width, height)| RgbaImage::new(width, height)).collect();
+        let mut regions = Vec::with_capacity(self.images.len());
+
+        for ((name, image), (page, x, y)) in self.images.into_iter().zip(placements) {
+            extrude(&mut pages[page], &image, x, y, border);
+            regions.push(PackedRegion { name, page, x, y, width: image.width(), height: image.height() });
+        }
+
+        Ok(PackedAtlas { pages, regions })
+    }
+}
+
+/// Copies `image` to (`x`, `y`) and repeats its outermost pixels `border` times around it.
+fn extrude(page: &mut RgbaImage, image: &RgbaImage, x: u32, y: u32, border: u32) {
+    let (width, height) = image.dimensions();
+    let (x, y, border) = (x as i64, y as i64, border as i64);
+
+    for dy in -border..height as i64 + border {
+        for dx in -border..width as i64 + border {
+            let source_x = dx.clamp(0, width as i64 - 1) as u32;
+            let source_y = dy.clamp(0, height as i64 - 1) as u32;
+            page.put_pixel((x + dx) as u32, (y + dy) as u32, *image.get_pixel(source_x, source_y));
+        }
+    }
+}
+
+/// Atlas pages and region layout on the CPU, ready to be uploaded or saved.
+pub struct PackedAtlas {
+    pages: Vec<RgbaImage>,
+    regions: Vec<PackedRegion>,
+}
+
+impl PackedAtlas {
+    pub fn pages(&self) -> &[RgbaImage] {
+        &self.pages
+    }
+
+    pub fn regions(&self) -> &[PackedRegion] {
+        &self.regions
+    }
+
+    /// Writes the sidecar description to `path` and each page as a PNG next to it, named
+    /// after the sidecar's stem (`ui.atlas` gets `ui_0.png`, `ui_1.png`, ...).
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .ok_or_else(|| format!("{} has no file name", path.to_string_lossy()))?;
+
+        let mut sidecar = format!("{}\n", SIDECAR_HEADER);
+        for (i, page) in self.pages.iter().enumerate() {
+            let file_name = format!("{}_{}.png", stem, i);
+            page.save(page_path(path, &file_name))
+                .map_err(|e| format!("Failed to save atlas page {}: {}", file_name, e))?;
+            sidecar.push_str(&format!("page {}\n", file_name));
+        }
+
+        // The name goes last so that it may contain spaces.
+        for region in &self.regions {
+            sidecar.push_str(&format!(
+                "region {} {} {} {} {} {}\n",
+                region.page, region.x, region.y, region.width, region.height, region.name
+            ));
+        }
+
+        std::fs::write(path, sidecar).map_err(|e| format!("Failed to write {}: {}", path.to_string_lossy(), e))
+    }
+
+    /// Loads an atlas written by `save`. Page files are resolved relative to the sidecar.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let sidecar = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.to_string_lossy(), e))?;
+        let error = |line: usize, message: &str| format!("{}:{}: {}", path.to_string_lossy(), line + 1, message);
+
+        let mut lines = sidecar.lines().enumerate();
+        match lines.next() {
+            Some((_, header)) if header.trim() == SIDECAR_HEADER => {}
+            _ => return Err(error(0, &format!("expected \"{}\" header", SIDECAR_HEADER))),
+        }
+
+        let mut pages = Vec::new();
+        let mut regions = Vec::new();
+
+        for (number, line) in lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.split_once(' ') {
+                Some(("page", file_name)) => pages.push(texture::load_file(&page_path(path, file_name.trim()))?),
+                Some(("region", fields)) => {
+                    let fields: Vec<&str> = fields.splitn(6, ' ').collect();
+                    if fields.len() != 6 {
+                        return Err(error(number, "expected \"region <page> <x> <y> <width> <height> <name>\""));
+                    }
+
+                    let mut numbers = [0u32; 5];
+                    for (value, field) in numbers.iter_mut().zip(&fields) {
+                        *value = field.parse().map_err(|_| error(number, &format!("\"{}\" is not a number", field)))?;
+                    }
+
+                    let [page, x, y, width, height] = numbers;
+                    regions.push(PackedRegion { name: fields[5].to_string(), page: page as usize, x, y, width, height });
+                }
+                _ => return Err(error(number, &format!("unknown entry \"{}\"", line))),
+            }
+        }
+
+        for region in &regions {
+            let page = pages.get(region.page).ok_or_else(|| format!("Atlas region \"{}\" refers to missing page {}", region.name, region.page))?;
+            if region.x + region.width > page.width() || region.y + region.height > page.height() {
+                return Err(format!("Atlas region \"{}\" lies outside page {}", region.name, region.page));
+            }
+        }
+
+        Ok(Self { pages, regions })
+    }
+}
+
+pub(crate) fn page_path(sidecar: &Path, file_name: &str) -> PathBuf {
+    match sidecar.parent() {
+        Some(directory) => directory.join(file_name),
+        None => PathBuf::from(file_name),
+    }
+}
+
+/// A rectangle of a texture, addressed by its UV coordinates.
+#[derive(Clone)]
+pub struct TextureRegion {
+    texture: Texture,
+    uv: Rect,
+    width: u32,
+    height: u32,
+}
+
+impl TextureRegion {
+    /// The whole of `texture`.
+    pub fn new(texture: Texture) -> Self {
+        let (width, height) = (texture.width(), texture.height());
+        Self { texture, uv: Rect::new(0.0, 0.0, 1.0, 1.0), width, height }
+    }
+
+    /// A rectangle in pixels with a top-left origin. `flipped` must match the
+    /// `flip_vertically` the texture was created with.
+    pub fn from_pixels(texture: Texture, x: u32, y: u32, width: u32, height: u32, flipped: bool) -> Self {
+        let (texture_width, texture_height) = (texture.width() as f32, texture.height() as f32);
+        let v = if flipped { texture_height - (y + height) as f32 } else { y as f32 };
+
+        Self {
+            uv: Rect::new(x as f32 / texture_width, v / texture_height, width as f32 / texture_width, height as f32 / texture_height),
+            texture,
+            width,
+            height,
+        }
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// UV rectangle of the region; `origin` is its lowest UV corner.
+    pub fn uv(&self) -> Rect {
+        self.uv
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// Uploaded atlas pages with their regions looked up by name.
+pub struct TextureAtlas {
+    pages: Vec<Texture>,
+    regions: HashMap<String, TextureRegion>,
+}
+
+impl TextureAtlas {
+    pub(crate) fn new(pages: Vec<Texture>, packed: &PackedAtlas, descriptor: &TextureDescriptor) -> Self {
+        let regions = packed
+            .regions
+            .iter()
+            .map(|region| {
+                let texture = pages[region.page].clone();
+                let texture_region = TextureRegion::from_pixels(texture, region.x, region.y, region.width, region.height, descriptor.flip_vertically);
+                (region.name.clone(), texture_region)
+            })
+            .collect();
+
+        Self { pages, regions }
+    }
+
+    pub fn region(&self, name: &str) -> Option<&TextureRegion> {
+        self.regions.get(name)
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = (&str, &TextureRegion)> {
+        self.regions.iter().map(|(name, region)| (name.as_str(), region))
+    }
+
+    pub fn pages(&self) -> &[Texture] {
+        &self.pages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn solid(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba([value, value, value, 255]))
+    }
+
+    #[test]
+    fn image_must_fit_with_padding_on_both_sides() {
+        let mut builder = AtlasBuilder::new(16).padding(1).bleed(1);
+        assert!(builder.add_image("fits", solid(12, 12, 1)).is_ok());
+        assert!(builder.add_image("too_wide", solid(13, 4, 1)).is_err());
+        assert!(builder.add_image("too_tall", solid(4, 13, 1)).is_err());
+
+        // Used to be accepted, leaving page 0 empty and page 1 larger than the maximum.
+        assert!(AtlasBuilder::new(1024).add_image("edge", solid(1023, 1023, 1)).is_err());
+    }
+
+    #[test]
+    fn largest_image_starts_the_first_page() {
+        let mut builder = AtlasBuilder::new(1024);
+        builder.add_image("big", solid(1022, 1022, 1)).unwrap();
+        builder.add_image("small", solid(4, 4, 2)).unwrap();
+        let atlas = builder.build().unwrap();
+
+        assert_eq!(atlas.pages().len(), 2);
+        for page in atlas.pages() {
+            assert!(page.width() > 0 && page.width() <= 1024);
+            assert!(page.height() > 0 && page.height() <= 1024);
+        }
+
+        let big = &atlas.regions()[0];
+        assert_eq!((big.name.as_str(), big.page, big.x, big.y), ("big", 0, 1, 1));
+        assert_eq!(atlas.pages()[0].dimensions(), (1024, 1024));
+    }
+
+    #[test]
+    fn regions_do_not_overlap_and_keep_their_pixels() {
+        let mut builder = AtlasBuilder::new(32).padding(2).bleed(1);
+        for i in 0..12u8 {
+            builder.add_image(&format!("image_{}", i), solid(5 + i as u32 % 3, 6, i + 1)).unwrap();
+        }
+        let atlas = builder.build().unwrap();
+
+        let regions = atlas.regions();
+        for (i, a) in regions.iter().enumerate() {
+            let page = &atlas.pages()[a.page];
+            assert!(a.x + a.width <= page.width() && a.y + a.height <= page.height());
+            let value = a.name["image_".len()..].parse::<u8>().unwrap() + 1;
+            assert_eq!(page.get_pixel(a.x, a.y).0[0], value);
+            assert_eq!(page.get_pixel(a.x + a.width - 1, a.y + a.height - 1).0[0], value);
+
+            for b in &regions[i + 1..] {
+                let apart = a.page != b.page || a.x + a.width <= b.x || b.x + b.width <= a.x || a.y + a.height <= b.y || b.y + b.height <= a.y;
+                assert!(apart, "{} overlaps {}", a.name, b.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use glam::Vec4;
+use image::RgbaImage;
+
+use crate::{Shader, Texture, Vertex, atlas::{PackedAtlas, TextureAtlas}, material::Material, mesh::Mesh, shader, texture::{self, TextureDescriptor}, uniform_block::{CameraBlock, LightsBlock}};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Screen {
+    Upper,
+    Lower,
+}
+
+pub trait RenderBackend {
+    fn create_mesh(&mut self, vertices: Vec<Vertex>, indices: Vec<u32>, textures: Vec<Texture>) -> Mesh;
+
+    /// Creates a texture from an image stored top row first. The other texture constructors
+    /// decode into an image and end up here.
+    fn create_texture_from_image(&mut self, image: RgbaImage, descriptor: &TextureDescriptor) -> Result<Texture, String>;
+
+    fn create_texture(&mut self, path: &Path) -> Result<Texture, String> {
+        self.create_texture_with_descriptor(path, &TextureDescriptor::default())
+    }
+
+    fn create_texture_with_descriptor(&mut self, path: &Path, descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        self.create_texture_from_image(texture::load_file(path)?, descriptor)
+    }
+
+    fn create_texture_from_memory(&mut self, bytes: &[u8], descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        self.create_texture_from_image(texture::load_memory(bytes)?, descriptor)
+    }
+
+    fn create_texture_from_rgba(&mut self, width: u32, height: u32, pixels: &[u8], descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        self.create_texture_from_image(texture::rgba_image(width, height, pixels)?, descriptor)
+    }
+
+    /// Uploads every page of a packed atlas with the same descriptor.
+    fn create_texture_atlas(&mut self, packed: &PackedAtlas, descriptor: &TextureDescriptor) -> Result<TextureAtlas, String> {
+        let pages = packed
+            .pages()
+            .iter()
+            .map(|page| self.create_texture_from_image(page.clone(), descriptor))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(TextureAtlas::new(pages, packed, descriptor))
+    }
+
+    fn create_shader(&mut self, vertex_src: &str, fragment_src: &str) -> Result<Shader, String>;
+
+    fn create_shader_from_files(&mut self, vertex_path: &Path, fragment_path: &Path) -> Result<Shader, String> {
+        let (vertex_src, fragment_src) = shader::read_sources(vertex_path, fragment_path)?;
+        self.create_shader(&vertex_src, &fragment_src)
+    }
+
+    fn draw_mesh(&mut self, mesh: &Mesh, material: &mut Material);
+
+    fn set_camera_block(&mut self, block: &CameraBlock);
+
+    fn set_lights_block(&mut self, block: &LightsBlock);
+
+    fn clear_screen(&mut self, color: Vec4);
+
+    fn begin_screen(&mut self, screen: Screen);
+
+    fn end_screen(&mut self, screen: Screen);
+
+    fn capture_screen(&mut self, screen: Screen) -> RgbaImage;
+}
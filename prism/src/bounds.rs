@@ -0,0 +1,185 @@
+use glam::{Mat3, Mat4, Vec3, Vec4};
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// The smallest box around `points`, or `None` if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self::new(first, first), |aabb, point| Self::new(aabb.min.min(point), aabb.max.max(point))))
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Half the size along each axis.
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+
+    /// The box around this one once transformed by `matrix`, which may be looser than the
+    /// box around the transformed contents.
+    pub fn transformed(&self, matrix: Mat4) -> Self {
+        let center = matrix.transform_point3(self.center());
+        let axes = Mat3::from_mat4(matrix);
+        let extents = self.extents();
+        let half = axes.x_axis.abs() * extents.x + axes.y_axis.abs() * extents.y + axes.z_axis.abs() * extents.z;
+        Self::new(center - half, center + half)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn new(center: Vec3, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    pub fn from_aabb(aabb: &Aabb) -> Self {
+        Self::new(aabb.center(), aabb.extents().length())
+    }
+
+    /// The sphere around this one once transformed by `matrix`; non-uniform scale makes it
+    /// as large as the largest axis.
+    pub fn transformed(&self, matrix: Mat4) -> Self {
+        let axes = Mat3::from_mat4(matrix);
+        let scale = axes.x_axis.length().max(axes.y_axis.length()).max(axes.z_axis.length());
+        Self::new(matrix.transform_point3(self.center), self.radius * scale)
+    }
+}
+
+/// The points `p` with `normal.dot(p) + distance >= 0`, i.e. in front of the plane.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// From the coefficients of `ax + by + cz + d = 0`, normalized.
+    fn from_coefficients(coefficients: Vec4) -> Self {
+        let length = coefficients.truncate().length();
+        Self { normal: coefficients.truncate() / length, distance: coefficients.w / length }
+    }
+
+    /// Positive in front of the plane, negative behind it.
+    pub fn signed_distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+/// The volume a camera sees, as six planes facing inwards: left, right, bottom, top, near
+/// and far.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of `projection * view`. The near plane assumes depth from -1 to 1,
+    /// which for projections with depth from 0 to 1 only moves it closer to the camera.
+    pub fn from_matrix(view_projection: Mat4) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_projection.row(i));
+        Self {
+            planes: [w + x, w - x, w + y, w - y, w + z, w - z].map(Plane::from_coefficients),
+        }
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// Whether any of `sphere` may be visible. Spheres near a corner of the frustum can pass
+    /// without being inside it.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    /// Whether any of `aabb` may be visible, with the same leeway at the corners.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal.
+            let corner = Vec3::select(plane.normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Looking down -Z from the origin with a 90 degree field of view, seeing from 0.1 to 100.
+    fn frustum() -> Frustum {
+        let projection = Mat4::perspective_rh(90f32.to_radians(), 1.0, 0.1, 100.0);
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        Frustum::from_matrix(projection * view)
+    }
+
+    fn cube(center: Vec3, half: f32) -> Aabb {
+        Aabb::new(center - Vec3::splat(half), center + Vec3::splat(half))
+    }
+
+    #[test]
+    fn boxes_in_view_intersect() {
+        let frustum = frustum();
+        assert!(frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, -10.0), 1.0)));
+        // Only partly inside the left plane.
+        assert!(frustum.intersects_aabb(&cube(Vec3::new(-10.5, 0.0, -10.0), 1.0)));
+        // Around the camera, so no corner is inside but the box is.
+        assert!(frustum.intersects_aabb(&cube(Vec3::ZERO, 500.0)));
+    }
+
+    #[test]
+    fn boxes_out_of_view_do_not_intersect() {
+        let frustum = frustum();
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, 10.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(0.0, 0.0, -200.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(-13.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&cube(Vec3::new(0.0, 13.0, -10.0), 1.0)));
+    }
+
+    #[test]
+    fn planes_face_inwards() {
+        let frustum = frustum();
+        assert!(frustum.contains_point(Vec3::new(0.0, 0.0, -1.0)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, 1.0)));
+        for plane in &frustum.planes {
+            assert!((plane.normal.length() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn transformed_box_covers_rotated_corners() {
+        let aabb = Aabb::from_points([Vec3::new(-1.0, -2.0, -3.0), Vec3::new(1.0, 2.0, 3.0)]).unwrap();
+        let matrix = Mat4::from_translation(Vec3::X * 5.0) * Mat4::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        let transformed = aabb.transformed(matrix);
+
+        assert!((transformed.min - Vec3::new(3.0, -1.0, -3.0)).length() < 1e-5);
+        assert!((transformed.max - Vec3::new(7.0, 1.0, 3.0)).length() < 1e-5);
+        assert_eq!(Aabb::from_points([]), None);
+    }
+}
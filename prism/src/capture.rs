@@ -0,0 +1,132 @@
+use std::{fs::File, path::PathBuf, time::{Duration, Instant}};
+
+use image::{Delay, Frame, RgbaImage, codecs::gif::GifEncoder};
+
+use crate::backend::Screen;
+
+pub enum CaptureOutput {
+    /// Writes `frame_000000.png`, `frame_000001.png`, ... into the directory.
+    PngSequence(PathBuf),
+    /// Writes every captured frame into a single animated GIF.
+    Gif(PathBuf),
+}
+
+enum CaptureWriter {
+    PngSequence(PathBuf),
+    /// A GIF frame's delay is how long it stays up, which is only known once the next frame
+    /// arrives, so each frame waits in `pending` with the time it was captured.
+    Gif { encoder: GifEncoder<File>, pending: Option<(RgbaImage, Instant)>, last_delay: Duration },
+}
+
+pub(crate) struct CaptureSession {
+    pub(crate) screen: Screen,
+    every_nth: u32,
+    frame_counter: u32,
+    frames_written: u32,
+    writer: CaptureWriter,
+    error: Option<String>,
+}
+
+impl CaptureSession {
+    pub(crate) fn new(screen: Screen, every_nth: u32, output: CaptureOutput) -> Result<Self, String> {
+        if every_nth == 0 {
+            return Err("Capture interval must be at least 1".to_string());
+        }
+
+        let writer = match output {
+            CaptureOutput::PngSequence(dir) => {
+                std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+                CaptureWriter::PngSequence(dir)
+            }
+            CaptureOutput::Gif(path) => {
+                let file = File::create(&path).map_err(|e| e.to_string())?;
+                let mut encoder = GifEncoder::new_with_speed(file, 10);
+                encoder.set_repeat(image::codecs::gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+                CaptureWriter::Gif { encoder, pending: None, last_delay: Duration::ZERO }
+            }
+        };
+
+        Ok(Self {
+            screen,
+            every_nth,
+            frame_counter: 0,
+            frames_written: 0,
+            writer,
+            error: None,
+        })
+    }
+
+    /// Advances the frame counter, returning whether this frame should be captured.
+    pub(crate) fn next_frame(&mut self) -> bool {
+        let capture = self.error.is_none() && self.frame_counter.is_multiple_of(self.every_nth);
+        self.frame_counter += 1;
+        capture
+    }
+
+    pub(crate) fn write_frame(&mut self, image: RgbaImage) {
+        let now = Instant::now();
+
+        let result = match &mut self.writer {
+            CaptureWriter::PngSequence(dir) => {
+                image.save(dir.join(format!("frame_{:06}.png", self.frames_written))).map(|()| 1).map_err(|e| e.to_string())
+            }
+            CaptureWriter::Gif { encoder, pending, last_delay } => match pending.replace((image, now)) {
+                Some((previous, captured)) => {
+                    *last_delay = now - captured;
+                    encode_gif_frame(encoder, previous, *last_delay).map(|()| 1)
+                }
+                None => Ok(0),
+            },
+        };
+
+        self.record(result);
+    }
+
+    fn record(&mut self, result: Result<u32, String>) {
+        match result {
+            Ok(written) => self.frames_written += written,
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// Writes the frame still waiting for its delay, shown as long as the one before it.
+    pub(crate) fn finish(mut self) -> Result<u32, String> {
+        if let CaptureWriter::Gif { encoder, pending, last_delay } = &mut self.writer
+            && let Some((image, _)) = pending.take().filter(|_| self.error.is_none())
+        {
+            let result = encode_gif_frame(encoder, image, *last_delay).map(|()| 1);
+            self.record(result);
+        }
+
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.frames_written),
+        }
+    }
+}
+
+fn encode_gif_frame(encoder: &mut GifEncoder<File>, image: RgbaImage, shown_for: Duration) -> Result<(), String> {
+    let delay = Delay::from_saturating_duration(shown_for);
+    encoder.encode_frame(Frame::from_parts(image, 0, 0, delay)).map_err(|e| e.to_string())
+}
+
+/// Reads the currently bound read framebuffer into a top-down RGBA image.
+pub(crate) fn read_pixels(width: u32, height: u32) -> RgbaImage {
+    let mut data = vec![0u8; (width * height * 4) as usize];
+
+    unsafe {
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as i32,
+            height as i32,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            data.as_mut_ptr() as *mut _,
+        );
+    }
+
+    let image = RgbaImage::from_raw(width, height, data).expect("Pixel buffer should match the framebuffer size");
+    image::imageops::flip_vertical(&image)
+}
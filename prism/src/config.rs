@@ -0,0 +1,156 @@
+use std::str::FromStr;
+
+use crate::{Rect, backend::Screen, layout::ScreenLayout};
+
+/// How a screen's render resolution is fitted into the window or display showing it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScalingMode {
+    /// Largest whole-number scale that fits, centered. Keeps pixel art crisp.
+    Integer,
+    /// Largest scale that keeps the aspect ratio, centered with black bars.
+    Letterbox,
+    /// Fills the whole area, ignoring the aspect ratio.
+    Stretch,
+}
+
+impl ScalingMode {
+    /// Where content of `content_size` is drawn inside an area of `available_size`.
+    pub fn fit(&self, content_size: (u32, u32), available_size: (u32, u32)) -> Rect {
+        let (cw, ch) = (content_size.0 as f32, content_size.1 as f32);
+        let (aw, ah) = (available_size.0 as f32, available_size.1 as f32);
+
+        let scale = match self {
+            ScalingMode::Stretch => return Rect::new(0.0, 0.0, aw, ah),
+            ScalingMode::Letterbox => (aw / cw).min(ah / ch),
+            ScalingMode::Integer => (aw / cw).min(ah / ch).floor().max(1.0),
+        };
+
+        let (width, height) = (cw * scale, ch * scale);
+        Rect::new(((aw - width) / 2.0).floor(), ((ah - height) / 2.0).floor(), width, height)
+    }
+}
+
+/// Which kind of GL context the renderer creates.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GraphicsApi {
+    /// Desktop GL 3.3 core, falling back to GLES 3.0 where that is unavailable.
+    Auto,
+    /// Desktop GL 3.3 core.
+    OpenGl,
+    /// GLES 3.0, as on the Raspberry Pi's VideoCore GPUs.
+    OpenGlEs,
+}
+
+impl GraphicsApi {
+    /// The context types to try, in order.
+    pub(crate) fn candidates(&self) -> &'static [GraphicsApi] {
+        match self {
+            GraphicsApi::Auto => &[GraphicsApi::OpenGl, GraphicsApi::OpenGlEs],
+            GraphicsApi::OpenGl => &[GraphicsApi::OpenGl],
+            GraphicsApi::OpenGlEs => &[GraphicsApi::OpenGlEs],
+        }
+    }
+}
+
+impl FromStr for GraphicsApi {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(GraphicsApi::Auto),
+            "gl" | "opengl" => Ok(GraphicsApi::OpenGl),
+            "gles" | "opengl_es" => Ok(GraphicsApi::OpenGlEs),
+            _ => Err(format!("Unknown graphics API: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScreenConfig {
+    /// Render resolution of the screen.
+    pub width: u32,
+    pub height: u32,
+    /// Index into the connected monitors; `None` for a window.
+    pub fullscreen_monitor: Option<usize>,
+    /// Preferred refresh rate when fullscreen; `None` uses the monitor's current rate.
+    pub refresh_rate: Option<u32>,
+    pub scaling: ScalingMode,
+}
+
+impl Default for ScreenConfig {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 480,
+            fullscreen_monitor: None,
+            refresh_rate: None,
+            scaling: ScalingMode::Letterbox,
+        }
+    }
+}
+
+/// Shadow map settings, see `PrismRenderer::begin_shadow_pass`.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowConfig {
+    /// Width and height of the square shadow map in texels.
+    pub resolution: u32,
+    /// Depth offset against shadow acne on surfaces facing the light.
+    pub depth_bias: f32,
+    /// Extra offset for surfaces at a grazing angle to the light, where acne is worst.
+    pub slope_bias: f32,
+    /// Shadow map texels averaged on each side of a sample for soft edges; 0 gives hard edges.
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            resolution: 1024,
+            depth_bias: 0.005,
+            slope_bias: 0.05,
+            pcf_radius: 1,
+        }
+    }
+}
+
+/// Display settings for `PrismRenderer`. In composited layouts the single window takes its
+/// fullscreen, refresh rate and scaling settings from the upper screen.
+#[derive(Clone, Debug)]
+pub struct PrismConfig {
+    pub upper: ScreenConfig,
+    pub lower: ScreenConfig,
+    pub layout: ScreenLayout,
+    pub graphics_api: GraphicsApi,
+    pub shadows: ShadowConfig,
+    pub vsync: bool,
+    /// Frames per second `handle_events` paces to; `None` leaves pacing to vsync.
+    pub target_frame_rate: Option<f32>,
+}
+
+impl PrismConfig {
+    pub fn screen(&self, screen: Screen) -> &ScreenConfig {
+        match screen {
+            Screen::Upper => &self.upper,
+            Screen::Lower => &self.lower,
+        }
+    }
+
+    pub fn screen_size(&self, screen: Screen) -> (u32, u32) {
+        let config = self.screen(screen);
+        (config.width, config.height)
+    }
+}
+
+impl Default for PrismConfig {
+    fn default() -> Self {
+        Self {
+            upper: ScreenConfig::default(),
+            lower: ScreenConfig::default(),
+            layout: ScreenLayout::SeparateWindows,
+            graphics_api: GraphicsApi::Auto,
+            shadows: ShadowConfig::default(),
+            vsync: true,
+            target_frame_rate: None,
+        }
+    }
+}
@@ -0,0 +1,636 @@
+use std::{collections::HashMap, path::Path};
+
+use ab_glyph::{Font as _, FontArc, GlyphId, PxScale, ScaleFont};
+use glam::Vec2;
+use image::RgbaImage;
+
+use crate::{Rect, Texture, atlas::{TextureRegion, page_path}, texture};
+
+/// Side of the textures outline glyphs are cached in.
+pub(crate) const GLYPH_PAGE_SIZE: u32 = 512;
+// Keeps linear filtering from picking up a neighbouring glyph.
+const GLYPH_PADDING: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Debug)]
+pub struct TextOptions {
+    /// Wraps lines longer than this many pixels, between words or CJK characters. Words that
+    /// do not fit on a line of their own are broken between characters.
+    pub max_width: Option<f32>,
+    /// Lines are aligned within `max_width`, or within the widest line without it.
+    pub align: TextAlign,
+    /// Multiplies the font's line height.
+    pub line_spacing: f32,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        Self { max_width: None, align: TextAlign::Left, line_spacing: 1.0 }
+    }
+}
+
+/// A glyph of one of a font's faces; for bitmap fonts the id is the character.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct GlyphKey {
+    face: usize,
+    id: u32,
+}
+
+/// A glyph's image and where its top-left corner sits relative to the pen on the baseline.
+#[derive(Clone)]
+pub(crate) struct CachedGlyph {
+    pub(crate) region: TextureRegion,
+    pub(crate) offset: Vec2,
+}
+
+/// Coverage of an outline glyph as white RGBA, bottom row first like the pages it goes into.
+pub(crate) struct GlyphBitmap {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) pixels: Vec<u8>,
+    pub(crate) offset: Vec2,
+}
+
+/// A glyph cache texture, filled shelf by shelf from the top.
+pub(crate) struct GlyphPage {
+    pub(crate) texture: Texture,
+    cursor_x: u32,
+    cursor_y: u32,
+    shelf_height: u32,
+}
+
+impl GlyphPage {
+    pub(crate) fn new(texture: Texture) -> Self {
+        Self { texture, cursor_x: GLYPH_PADDING, cursor_y: GLYPH_PADDING, shelf_height: 0 }
+    }
+
+    /// Top-left corner of a free `width`x`height` rectangle, if the page has one left.
+    pub(crate) fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let size = self.texture.width();
+        if self.cursor_x + width + GLYPH_PADDING > size {
+            self.cursor_x = GLYPH_PADDING;
+            self.cursor_y += self.shelf_height + GLYPH_PADDING;
+            self.shelf_height = 0;
+        }
+
+        if self.cursor_x + width + GLYPH_PADDING > size || self.cursor_y + height + GLYPH_PADDING > size {
+            return None;
+        }
+
+        let corner = (self.cursor_x, self.cursor_y);
+        self.cursor_x += width + GLYPH_PADDING;
+        self.shelf_height = self.shelf_height.max(height);
+        Some(corner)
+    }
+}
+
+struct BitmapGlyph {
+    region: Option<TextureRegion>,
+    offset: Vec2,
+    advance: f32,
+}
+
+enum Faces {
+    Outline { faces: Vec<FontArc>, scale: PxScale },
+    Bitmap { glyphs: HashMap<char, BitmapGlyph>, kerning: HashMap<(char, char), f32>, line_height: f32, base: f32 },
+}
+
+/// A TrueType/OpenType font at one pixel size, or a bitmap font. Outline glyphs are
+/// rasterized the first time `PrismRenderer::layout_text` meets them and cached in texture
+/// pages owned by the font, so drop fonts before the renderer like any other texture.
+///
+/// Characters missing from a font are looked up in its fallbacks in order; load a CJK font
+/// as a fallback to show Chinese, Japanese or Korean next to a Latin UI font.
+pub struct Font {
+    faces: Faces,
+    pub(crate) glyphs: HashMap<GlyphKey, Option<CachedGlyph>>,
+    pub(crate) pages: Vec<GlyphPage>,
+}
+
+impl Font {
+    /// Loads a `.ttf`, `.otf` or the first font of a `.ttc`. `size` is in pixels, from the
+    /// highest ascender to the lowest descender.
+    pub fn from_file(path: &Path, size: f32) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Failed to read font {}: {}", path.to_string_lossy(), e))?;
+        Self::from_memory(data, size)
+    }
+
+    pub fn from_memory(data: Vec<u8>, size: f32) -> Result<Self, String> {
+        if size.is_nan() || size <= 0.0 {
+            return Err(format!("Font size must be positive, got {}", size));
+        }
+
+        let face = load_face(data)?;
+        Ok(Self {
+            faces: Faces::Outline { faces: vec![face], scale: PxScale::from(size) },
+            glyphs: HashMap::new(),
+            pages: Vec::new(),
+        })
+    }
+
+    /// Adds a font to look characters up in when this one lacks them. Bitmap fonts cannot
+    /// have fallbacks.
+    pub fn add_fallback_file(&mut self, path: &Path) -> Result<(), String> {
+        let data = std::fs::read(path).map_err(|e| format!("Failed to read font {}: {}", path.to_string_lossy(), e))?;
+        self.add_fallback_memory(data)
+    }
+
+    pub fn add_fallback_memory(&mut self, data: Vec<u8>) -> Result<(), String> {
+        match &mut self.faces {
+            Faces::Outline { faces, .. } => {
+                faces.push(load_face(data)?);
+                Ok(())
+            }
+            Faces::Bitmap { .. } => Err("Bitmap fonts cannot have fallback fonts".to_string()),
+        }
+    }
+
+    pub(crate) fn from_bitmap(data: &BitmapFontData, pages: &[Texture]) -> Self {
+        let glyphs = data.chars.iter().map(|bitmap| {
+            let region = (bitmap.width > 0 && bitmap.height > 0)
+                .then(|| TextureRegion::from_pixels(pages[bitmap.page].clone(), bitmap.x, bitmap.y, bitmap.width, bitmap.height, true));
+            let glyph = BitmapGlyph {
+                region,
+                offset: Vec2::new(bitmap.x_offset, bitmap.y_offset - data.base),
+                advance: bitmap.x_advance,
+            };
+            (bitmap.id, glyph)
+        });
+
+        Self {
+            faces: Faces::Bitmap { glyphs: glyphs.collect(), kerning: data.kerning.clone(), line_height: data.line_height, base: data.base },
+            glyphs: HashMap::new(),
+            pages: Vec::new(),
+        }
+    }
+
+    pub fn is_bitmap(&self) -> bool {
+        matches!(self.faces, Faces::Bitmap { .. })
+    }
+
+    /// Distance between the baselines of consecutive lines.
+    pub fn line_height(&self) -> f32 {
+        match &self.faces {
+            Faces::Outline { faces, scale } => {
+                let face = faces[0].as_scaled(*scale);
+                face.height() + face.line_gap()
+            }
+            Faces::Bitmap { line_height, .. } => *line_height,
+        }
+    }
+
+    /// Distance from the top of a line to its baseline.
+    pub fn ascent(&self) -> f32 {
+        match &self.faces {
+            Faces::Outline { faces, scale } => faces[0].as_scaled(*scale).ascent(),
+            Faces::Bitmap { base, .. } => *base,
+        }
+    }
+
+    /// Size of the text as `PrismRenderer::layout_text` would lay it out.
+    pub fn measure(&self, text: &str, options: &TextOptions) -> Vec2 {
+        self.place(text, options).1
+    }
+
+    /// The glyph drawn for `c`: the first face that has it, or the primary face's
+    /// placeholder glyph.
+    fn key(&self, c: char) -> GlyphKey {
+        match &self.faces {
+            Faces::Outline { faces, .. } => faces
+                .iter()
+                .enumerate()
+                .find_map(|(face, font)| {
+                    let id = font.glyph_id(c);
+                    (id.0 != 0).then_some(GlyphKey { face, id: id.0 as u32 })
+                })
+                .unwrap_or(GlyphKey { face: 0, id: 0 }),
+            Faces::Bitmap { glyphs, .. } => {
+                let c = if glyphs.contains_key(&c) { c } else { '?' };
+                GlyphKey { face: 0, id: c as u32 }
+            }
+        }
+    }
+
+    fn advance(&self, key: GlyphKey) -> f32 {
+        match &self.faces {
+            Faces::Outline { faces, scale } => faces[key.face].as_scaled(*scale).h_advance(GlyphId(key.id as u16)),
+            Faces::Bitmap { glyphs, .. } => bitmap_char(key).and_then(|c| glyphs.get(&c)).map_or(0.0, |glyph| glyph.advance),
+        }
+    }
+
+    fn kern(&self, previous: GlyphKey, next: GlyphKey) -> f32 {
+        if previous.face != next.face {
+            return 0.0;
+        }
+
+        match &self.faces {
+            Faces::Outline { faces, scale } => faces[next.face].as_scaled(*scale).kern(GlyphId(previous.id as u16), GlyphId(next.id as u16)),
+            Faces::Bitmap { kerning, .. } => match (bitmap_char(previous), bitmap_char(next)) {
+                (Some(previous), Some(next)) => kerning.get(&(previous, next)).copied().unwrap_or(0.0),
+                _ => 0.0,
+            },
+        }
+    }
+
+    /// Width of `chars` laid out on one line, with kerning between them.
+    fn run_width(&self, chars: &[char]) -> f32 {
+        let mut width = 0.0;
+        let mut previous = None;
+        for &c in chars {
+            let key = self.key(c);
+            if let Some(previous) = previous {
+                width += self.kern(previous, key);
+            }
+            width += self.advance(key);
+            previous = Some(key);
+        }
+        width
+    }
+
+    /// Splits text into lines at newlines and, with a `max_width`, wherever a line gets too
+    /// long. Spaces a line was wrapped at are dropped.
+    fn break_lines(&self, text: &str, max_width: Option<f32>) -> Vec<Vec<char>> {
+        let mut lines = Vec::new();
+
+        for paragraph in text.split('\n') {
+            let paragraph: Vec<char> = paragraph.chars().filter(|&c| c != '\r').map(|c| if c == '\t' { ' ' } else { c }).collect();
+            let max_width = match max_width {
+                Some(max_width) => max_width,
+                None => {
+                    lines.push(paragraph);
+                    continue;
+                }
+            };
+
+            let mut line: Vec<char> = Vec::new();
+            let mut width = 0.0;
+            let mut wrapped = false;
+
+            for unit in break_units(&paragraph) {
+                let is_space = unit[0].is_whitespace();
+                let unit_width = self.run_width(unit);
+
+                if !line.is_empty() && !is_space && width + unit_width > max_width {
+                    trim_end(&mut line);
+                    lines.push(std::mem::take(&mut line));
+                    width = 0.0;
+                    wrapped = true;
+                }
+
+                if line.is_empty() && is_space && wrapped {
+                    continue;
+                }
+
+                if !is_space && unit_width > max_width {
+                    for &c in unit {
+                        let advance = self.advance(self.key(c));
+                        if !line.is_empty() && width + advance > max_width {
+                            lines.push(std::mem::take(&mut line));
+                            width = 0.0;
+                        }
+                        line.push(c);
+                        width += advance;
+                    }
+                }
+                else {
+                    line.extend_from_slice(unit);
+                    width += unit_width;
+                }
+            }
+
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    /// Pen positions of the glyphs of the laid out text, with a top-left origin, and the
+    /// size of the text.
+    pub(crate) fn place(&self, text: &str, options: &TextOptions) -> (Vec<(GlyphKey, Vec2)>, Vec2) {
+        let lines = self.break_lines(text, options.max_width);
+        let line_advance = self.line_height() * options.line_spacing;
+
+        let mut placed = Vec::new();
+        let mut line_widths = Vec::with_capacity(lines.len());
+        for (row, line) in lines.iter().enumerate() {
+            let baseline = self.ascent() + row as f32 * line_advance;
+            let mut pen = 0.0;
+            let mut width: f32 = 0.0;
+            let mut previous = None;
+
+            for &c in line {
+                let key = self.key(c);
+                if let Some(previous) = previous {
+                    pen += self.kern(previous, key);
+                }
+                placed.push((key, Vec2::new(pen, baseline)));
+                pen += self.advance(key);
+                // Trailing spaces do not count towards alignment.
+                if !c.is_whitespace() {
+                    width = pen;
+                }
+                previous = Some(key);
+            }
+
+            line_widths.push((placed.len() - line.len(), width));
+        }
+
+        let widest = line_widths.iter().map(|&(_, width)| width).fold(0.0, f32::max);
+        let box_width = options.max_width.unwrap_or(widest);
+        let factor = match options.align {
+            TextAlign::Left => 0.0,
+            TextAlign::Center => 0.5,
+            TextAlign::Right => 1.0,
+        };
+
+        for (row, &(first, width)) in line_widths.iter().enumerate() {
+            let shift = (box_width - width) * factor;
+            for (_, pen) in &mut placed[first..first + lines[row].len()] {
+                pen.x += shift;
+            }
+        }
+
+        let height = match lines.len() {
+            0 => 0.0,
+            count => (count - 1) as f32 * line_advance + self.line_height(),
+        };
+
+        (placed, Vec2::new(widest, height))
+    }
+
+    /// The cached glyph for `key`, or `None` if it has not been rasterized yet. Glyphs
+    /// without pixels, such as spaces, are cached as `Some(None)`.
+    pub(crate) fn cached(&self, key: GlyphKey) -> Option<Option<CachedGlyph>> {
+        match &self.faces {
+            Faces::Bitmap { glyphs, .. } => Some(bitmap_char(key).and_then(|c| glyphs.get(&c)).and_then(|glyph| {
+                glyph.region.clone().map(|region| CachedGlyph { region, offset: glyph.offset })
+            })),
+            Faces::Outline { .. } => self.glyphs.get(&key).cloned(),
+        }
+    }
+
+    pub(crate) fn rasterize(&self, key: GlyphKey) -> Option<GlyphBitmap> {
+        let (faces, scale) = match &self.faces {
+            Faces::Outline { faces, scale } => (faces, *scale),
+            Faces::Bitmap { .. } => return None,
+        };
+
+        let outline = faces[key.face].outline_glyph(GlyphId(key.id as u16).with_scale(scale))?;
+        let bounds = outline.px_bounds();
+        let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        outline.draw(|x, y, coverage| {
+            let i = (((height - 1 - y) * width + x) * 4) as usize;
+            pixels[i..i + 4].copy_from_slice(&[255, 255, 255, (coverage.clamp(0.0, 1.0) * 255.0).round() as u8]);
+        });
+
+        Some(GlyphBitmap { width, height, pixels, offset: Vec2::new(bounds.min.x, bounds.min.y) })
+    }
+}
+
+fn load_face(data: Vec<u8>) -> Result<FontArc, String> {
+    FontArc::try_from_vec(data).map_err(|e| format!("Failed to load font: {}", e))
+}
+
+fn bitmap_char(key: GlyphKey) -> Option<char> {
+    char::from_u32(key.id)
+}
+
+fn trim_end(line: &mut Vec<char>) {
+    while line.last().is_some_and(|c| c.is_whitespace()) {
+        line.pop();
+    }
+}
+
+/// Scripts written without spaces between words, which may wrap between any two characters.
+fn breaks_anywhere(c: char) -> bool {
+    matches!(c as u32,
+        0x3000..=0x30FF     // CJK punctuation, hiragana and katakana
+        | 0x3400..=0x4DBF   // CJK extension A
+        | 0x4E00..=0x9FFF   // CJK unified ideographs
+        | 0xF900..=0xFAFF   // CJK compatibility ideographs
+        | 0xFF00..=0xFFEF   // Full and half width forms
+        | 0x20000..=0x3FFFF // CJK extensions B and later
+    )
+}
+
+/// Pieces a line may only be wrapped between: runs of spaces, words and single CJK characters.
+fn break_units(chars: &[char]) -> Vec<&[char]> {
+    let mut units = Vec::new();
+    let mut start = 0;
+
+    for i in 1..=chars.len() {
+        let ends = i == chars.len()
+            || breaks_anywhere(chars[i - 1])
+            || breaks_anywhere(chars[i])
+            || chars[i - 1].is_whitespace() != chars[i].is_whitespace();
+        if ends {
+            units.push(&chars[start..i]);
+            start = i;
+        }
+    }
+
+    units
+}
+
+/// Text laid out by `PrismRenderer::layout_text`, ready to be drawn with
+/// `SpriteBatch::draw_text` as often as needed.
+#[derive(Clone)]
+pub struct TextLayout {
+    pub(crate) glyphs: Vec<(TextureRegion, Rect)>,
+    pub(crate) size: Vec2,
+}
+
+impl TextLayout {
+    /// Width of the widest line and height of all lines, in pixels.
+    pub fn size(&self) -> Vec2 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+}
+
+struct BitmapChar {
+    id: char,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    x_offset: f32,
+    y_offset: f32,
+    x_advance: f32,
+    page: usize,
+}
+
+/// An AngelCode BMFont description in its text format, with its page images loaded.
+/// Upload it with `PrismRenderer::create_bitmap_font`.
+pub struct BitmapFontData {
+    pages: Vec<RgbaImage>,
+    chars: Vec<BitmapChar>,
+    kerning: HashMap<(char, char), f32>,
+    line_height: f32,
+    base: f32,
+}
+
+impl BitmapFontData {
+    /// Loads a `.fnt` file. Page files are resolved relative to it.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.to_string_lossy(), e))?;
+        let error = |line: usize, message: &str| format!("{}:{}: {}", path.to_string_lossy(), line + 1, message);
+
+        let mut page_files: Vec<(usize, String)> = Vec::new();
+        let mut chars = Vec::new();
+        let mut kerning = HashMap::new();
+        let mut common = None;
+
+        for (number, line) in source.lines().enumerate() {
+            let (tag, fields) = match parse_bmfont_line(line) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+
+            let number_field = |name: &str| -> Result<f32, String> {
+                let value = fields.get(name).ok_or_else(|| error(number, &format!("missing \"{}\"", name)))?;
+                value.parse().map_err(|_| error(number, &format!("\"{}\" is not a number", value)))
+            };
+            let character = |name: &str| -> Result<char, String> {
+                let id = number_field(name)?;
+                char::from_u32(id as u32).ok_or_else(|| error(number, &format!("{} is not a character", id)))
+            };
+
+            match tag {
+                "common" => common = Some((number_field("lineHeight")?, number_field("base")?)),
+                "page" => {
+                    let file = fields.get("file").ok_or_else(|| error(number, "missing \"file\""))?;
+                    page_files.push((number_field("id")? as usize, file.clone()));
+                }
+                "char" => chars.push(BitmapChar {
+                    id: character("id")?,
+                    x: number_field("x")? as u32,
+                    y: number_field("y")? as u32,
+                    width: number_field("width")? as u32,
+                    height: number_field("height")? as u32,
+                    x_offset: number_field("xoffset")?,
+                    y_offset: number_field("yoffset")?,
+                    x_advance: number_field("xadvance")?,
+                    page: number_field("page")? as usize,
+                }),
+                "kerning" => {
+                    kerning.insert((character("first")?, character("second")?), number_field("amount")?);
+                }
+                _ => {}
+            }
+        }
+
+        let (line_height, base) = common.ok_or_else(|| format!("{} has no \"common\" line", path.to_string_lossy()))?;
+
+        page_files.sort_by_key(|&(id, _)| id);
+        let mut pages = Vec::with_capacity(page_files.len());
+        for (i, (id, file)) in page_files.iter().enumerate() {
+            if *id != i {
+                return Err(format!("{} is missing page {}", path.to_string_lossy(), i));
+            }
+            pages.push(texture::load_file(&page_path(path, file))?);
+        }
+
+        for bitmap in &chars {
+            let page = pages.get(bitmap.page).ok_or_else(|| format!("Character {:?} refers to missing page {}", bitmap.id, bitmap.page))?;
+            if bitmap.x + bitmap.width > page.width() || bitmap.y + bitmap.height > page.height() {
+                return Err(format!("Character {:?} lies outside page {}", bitmap.id, bitmap.page));
+            }
+        }
+
+        Ok(Self { pages, chars, kerning, line_height, base })
+    }
+
+    pub fn pages(&self) -> &[RgbaImage] {
+        &self.pages
+    }
+}
+
+/// The tag and `key=value` fields of a line, with quotes removed from values.
+fn parse_bmfont_line(line: &str) -> Option<(&str, HashMap<String, String>)> {
+    let line = line.trim();
+    let (tag, mut rest) = line.split_once(' ').unwrap_or((line, ""));
+    if tag.is_empty() {
+        return None;
+    }
+
+    let mut fields = HashMap::new();
+    loop {
+        rest = rest.trim_start();
+        let (key, after) = match rest.split_once('=') {
+            Some(split) => split,
+            None => break,
+        };
+
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => after.split_once(' ').unwrap_or((after, "")),
+        };
+
+        fields.insert(key.trim().to_string(), value.to_string());
+        rest = remaining;
+    }
+
+    Some((tag, fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bitmap font without pixels whose glyphs are all 10 pixels wide.
+    fn monospace() -> Font {
+        let glyphs = "abcdefghijklmnopqrstuvwxyz ?日本語のテキスト"
+            .chars()
+            .map(|c| (c, BitmapGlyph { region: None, offset: Vec2::ZERO, advance: 10.0 }))
+            .collect();
+
+        Font {
+            faces: Faces::Bitmap { glyphs, kerning: HashMap::new(), line_height: 12.0, base: 10.0 },
+            glyphs: HashMap::new(),
+            pages: Vec::new(),
+        }
+    }
+
+    fn lines(text: &str, max_width: Option<f32>) -> Vec<String> {
+        monospace().break_lines(text, max_width).into_iter().map(|line| line.into_iter().collect()).collect()
+    }
+
+    #[test]
+    fn wraps_between_words_and_drops_the_spaces() {
+        assert_eq!(lines("hello world", Some(60.0)), ["hello", "world"]);
+        assert_eq!(lines("ab  cd ef", Some(50.0)), ["ab", "cd ef"]);
+        assert_eq!(lines("hello world", None), ["hello world"]);
+    }
+
+    #[test]
+    fn breaks_words_longer_than_a_line() {
+        assert_eq!(lines("abcdefghij", Some(35.0)), ["abc", "def", "ghi", "j"]);
+        assert_eq!(lines("ab abcdefgh", Some(40.0)), ["ab", "abcd", "efgh"]);
+    }
+
+    #[test]
+    fn wraps_between_any_cjk_characters() {
+        assert_eq!(lines("日本語のテキスト", Some(30.0)), ["日本語", "のテキ", "スト"]);
+        assert_eq!(lines("ab日本", Some(30.0)), ["ab日", "本"]);
+    }
+
+    #[test]
+    fn keeps_newlines_and_empty_lines() {
+        assert_eq!(lines("a\r\n\nb\tc", Some(100.0)), ["a", "", "b c"]);
+    }
+}
@@ -0,0 +1,263 @@
+use std::{collections::{BTreeMap, HashMap}, path::{Path, PathBuf}};
+
+// Guards against includes that include each other.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// GLSL dialect shaders are compiled as; picks the `#version` header prepended to every source.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GlslProfile {
+    Gl33Core,
+    Gles30,
+}
+
+impl GlslProfile {
+    fn header(&self) -> &'static str {
+        match self {
+            GlslProfile::Gl33Core => "#version 330 core\n",
+            GlslProfile::Gles30 => "#version 300 es\n",
+        }
+    }
+
+    /// Statements every shader starts with. They go after the source's leading directives,
+    /// as `#extension` must come before anything that is not a directive.
+    fn prologue(&self) -> Option<&'static str> {
+        match self {
+            GlslProfile::Gl33Core => None,
+            // GLES has no default float precision in fragment shaders.
+            GlslProfile::Gles30 => Some("precision highp float;\nprecision highp int;\n"),
+        }
+    }
+
+    fn define(&self) -> &'static str {
+        match self {
+            GlslProfile::Gl33Core => "PRISM_GL_CORE",
+            GlslProfile::Gles30 => "PRISM_GLES",
+        }
+    }
+}
+
+/// `#define`s a shader variant is compiled with. Also the key variants are cached by.
+#[derive(Clone, Default, PartialEq, Eq, Hash, Debug)]
+pub struct ShaderDefines {
+    defines: BTreeMap<String, String>,
+}
+
+impl ShaderDefines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines `name` as `1`, for use with both `#ifdef` and `#if`.
+    pub fn define(mut self, name: &str) -> Self {
+        self.defines.insert(name.to_string(), "1".to_string());
+        self
+    }
+
+    pub fn set(mut self, name: &str, value: impl ToString) -> Self {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn remove(mut self, name: &str) -> Self {
+        self.defines.remove(name);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defines.is_empty()
+    }
+}
+
+/// A preprocessed shader stage ready to compile.
+pub(crate) struct PreprocessedSource {
+    pub(crate) source: String,
+    /// Names of the GLSL source strings referred to by `#line`, so compiler messages such as
+    /// `1(12)` can be traced back to a file.
+    pub(crate) names: Vec<String>,
+    /// Files on disk the source was assembled from, for hot reloading.
+    pub(crate) files: Vec<PathBuf>,
+}
+
+impl PreprocessedSource {
+    /// Appended to compiler errors when the source came from more than one file.
+    pub(crate) fn legend(&self) -> String {
+        if self.names.len() < 2 {
+            return String::new();
+        }
+
+        let names: Vec<String> = self.names.iter().enumerate().map(|(i, name)| format!("{} = {}", i, name)).collect();
+        format!("\n(source strings: {})", names.join(", "))
+    }
+}
+
+/// Prepends the `#version` header and defines, and resolves `#include "name"`. Includes are
+/// looked up next to the including file first, then among the registered include snippets.
+/// Each file is included at most once; `#version` lines in the sources are dropped.
+pub(crate) fn preprocess(
+    source: &str,
+    name: &str,
+    path: Option<&Path>,
+    profile: GlslProfile,
+    defines: &ShaderDefines,
+    includes: &HashMap<String, String>,
+) -> Result<PreprocessedSource, String> {
+    let mut output = PreprocessedSource { source: String::new(), names: Vec::new(), files: Vec::new() };
+
+    output.source.push_str(profile.header());
+    output.source.push_str(&format!("#define {} 1\n", profile.define()));
+    for (define, value) in &defines.defines {
+        output.source.push_str(&format!("#define {} {}\n", define, value));
+    }
+
+    if let Some(path) = path {
+        output.files.push(path.to_path_buf());
+    }
+
+    let mut prologue = profile.prologue();
+    expand(source, name, path, includes, &mut output, &mut prologue, 0)?;
+    if let Some(prologue) = prologue {
+        output.source.push_str(prologue);
+    }
+    Ok(output)
+}
+
+/// Whether `line` is blank, a comment or a directive, which may all come before `#extension`.
+fn is_directive_or_comment(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || line.starts_with('#') || line.starts_with("//") || line.starts_with("/*") || line.starts_with('*')
+}
+
+fn expand(
+    source: &str,
+    name: &str,
+    path: Option<&Path>,
+    includes: &HashMap<String, String>,
+    output: &mut PreprocessedSource,
+    prologue: &mut Option<&'static str>,
+    depth: usize,
+) -> Result<(), String> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("{}: includes are nested more than {} deep", name, MAX_INCLUDE_DEPTH));
+    }
+
+    let index = output.names.len();
+    output.names.push(name.to_string());
+    output.source.push_str(&format!("#line 1 {}\n", index));
+
+    for (number, line) in source.lines().enumerate() {
+        let directive = line.trim_start();
+
+        if directive.starts_with("#version") {
+            output.source.push('\n');
+            continue;
+        }
+
+        let include = match directive.strip_prefix("#include") {
+            Some(include) => include.trim(),
+            None => {
+                if !is_directive_or_comment(line)
+                    && let Some(prologue) = prologue.take()
+                {
+                    output.source.push_str(prologue);
+                    output.source.push_str(&format!("#line {} {}\n", number + 1, index));
+                }
+                output.source.push_str(line);
+                output.source.push('\n');
+                continue;
+            }
+        };
+
+        let include_name = include
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+            .or_else(|| include.strip_prefix('<').and_then(|rest| rest.strip_suffix('>')))
+            .ok_or_else(|| format!("{}:{}: expected #include \"name\"", name, number + 1))?;
+
+        let on_disk = path
+            .and_then(|path| path.parent())
+            .map(|directory| directory.join(include_name))
+            .filter(|candidate| candidate.is_file());
+
+        match on_disk {
+            Some(include_path) => {
+                if !output.files.contains(&include_path) {
+                    let include_source = std::fs::read_to_string(&include_path)
+                        .map_err(|e| format!("{}:{}: failed to read {}: {}", name, number + 1, include_path.to_string_lossy(), e))?;
+                    output.files.push(include_path.clone());
+                    expand(&include_source, include_name, Some(&include_path), includes, output, prologue, depth + 1)?;
+                }
+            }
+            None => match includes.get(include_name) {
+                Some(include_source) => {
+                    if !output.names.iter().any(|included| included == include_name) {
+                        expand(include_source, include_name, None, includes, output, prologue, depth + 1)?;
+                    }
+                }
+                None => return Err(format!("{}:{}: cannot find include \"{}\"", name, number + 1, include_name)),
+            },
+        }
+
+        // Back to the including file, on the line after the include.
+        output.source.push_str(&format!("#line {} {}\n", number + 2, index));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocess_with(source: &str, profile: GlslProfile, includes: &[(&str, &str)]) -> Result<PreprocessedSource, String> {
+        let includes = includes.iter().map(|(name, source)| (name.to_string(), source.to_string())).collect();
+        preprocess(source, "main", None, profile, &ShaderDefines::new().set("COUNT", 4), &includes)
+    }
+
+    #[test]
+    fn header_and_defines_come_first() {
+        let output = preprocess_with("void main() {}", GlslProfile::Gl33Core, &[]).unwrap();
+        let lines: Vec<&str> = output.source.lines().collect();
+        assert_eq!(lines[..4], ["#version 330 core", "#define PRISM_GL_CORE 1", "#define COUNT 4", "#line 1 0"]);
+    }
+
+    #[test]
+    fn includes_are_expanded_once_with_line_mapping() {
+        let source = "#include \"common\"\n#include \"common\"\nvoid main() {}\n";
+        let output = preprocess_with(source, GlslProfile::Gl33Core, &[("common", "float a;\nfloat b;")]).unwrap();
+
+        let body: Vec<&str> = output.source.lines().skip_while(|line| !line.starts_with("#line")).collect();
+        assert_eq!(body, ["#line 1 0", "#line 1 1", "float a;", "float b;", "#line 2 0", "#line 3 0", "void main() {}"]);
+        assert_eq!(output.names, ["main", "common"]);
+        assert!(output.legend().contains("1 = common"));
+    }
+
+    #[test]
+    fn missing_include_reports_its_line() {
+        let error = preprocess_with("\n#include \"nowhere\"", GlslProfile::Gl33Core, &[]).err().unwrap();
+        assert_eq!(error, "main:2: cannot find include \"nowhere\"");
+    }
+
+    #[test]
+    fn source_version_is_dropped() {
+        let output = preprocess_with("#version 450\nvoid main() {}", GlslProfile::Gl33Core, &[]).unwrap();
+        assert_eq!(output.source.matches("#version").count(), 1);
+    }
+
+    #[test]
+    fn gles_precision_follows_extensions() {
+        let source = "// Needs the extension.\n#extension GL_OES_standard_derivatives : enable\n\nvoid main() {}\n";
+        let output = preprocess_with(source, GlslProfile::Gles30, &[]).unwrap();
+
+        let extension = output.source.find("#extension").unwrap();
+        let precision = output.source.find("precision highp float;").unwrap();
+        assert!(extension < precision);
+        // Line numbers pick up again where the code starts.
+        assert!(output.source.contains("precision highp int;\n#line 4 0\nvoid main() {}"));
+    }
+
+    #[test]
+    fn gles_precision_is_kept_without_code() {
+        let output = preprocess_with("#define ONLY_DEFINES 1", GlslProfile::Gles30, &[]).unwrap();
+        assert!(output.source.ends_with("precision highp float;\nprecision highp int;\n"));
+    }
+}
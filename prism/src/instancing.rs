@@ -0,0 +1,68 @@
+use glam::{Mat4, Vec4};
+
+use crate::resource::{GpuObject, Handle, ResourceRef};
+
+/// Floats per instance: the transform's columns, then the colour.
+pub(crate) const INSTANCE_FLOATS: usize = 20;
+
+/// One copy of a mesh in an instanced draw. `transform` takes the place of the mesh's model
+/// matrix, and the built-in shaders multiply the mesh's colour by `color`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Instance {
+    pub transform: Mat4,
+    pub color: Vec4,
+}
+
+impl Instance {
+    pub fn new(transform: Mat4) -> Self {
+        Self { transform, color: Vec4::ONE }
+    }
+
+    pub fn with_color(transform: Mat4, color: Vec4) -> Self {
+        Self { transform, color }
+    }
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self::new(Mat4::IDENTITY)
+    }
+}
+
+/// Per-instance data on the GPU, drawn with `PrismRenderer::draw_mesh_instanced`. Keep one
+/// around for instances that rarely move, e.g. grass, and refill it with
+/// `PrismRenderer::update_instance_buffer` when they do.
+pub struct InstanceBuffer {
+    pub(crate) resource: ResourceRef,
+    pub(crate) len: usize,
+}
+
+impl InstanceBuffer {
+    pub fn handle(&self) -> Handle {
+        self.resource.handle()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn raw_id(&self) -> u32 {
+        match self.resource.object() {
+            Some(GpuObject::Buffer(id)) => id,
+            _ => 0,
+        }
+    }
+}
+
+pub(crate) fn pack(instances: &[Instance]) -> Vec<f32> {
+    let mut data = Vec::with_capacity(instances.len() * INSTANCE_FLOATS);
+    for instance in instances {
+        data.extend_from_slice(&instance.transform.to_cols_array());
+        data.extend_from_slice(&instance.color.to_array());
+    }
+    data
+}
@@ -0,0 +1,161 @@
+use std::str::FromStr;
+
+use glam::Vec2;
+
+use crate::{Rect, backend::Screen};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScreenLayout {
+    /// One window per screen, as on the device.
+    SeparateWindows,
+    /// Both screens composited into one window, upper above lower.
+    Stacked,
+    /// Both screens composited into one window, upper left of lower.
+    SideBySide,
+    /// Only the upper screen is shown; the lower screen is still rendered offscreen.
+    UpperOnly,
+}
+
+impl ScreenLayout {
+    pub fn is_composited(&self) -> bool {
+        *self != ScreenLayout::SeparateWindows
+    }
+
+    /// The screen whose `end_*_screen` call presents the composited window.
+    pub(crate) fn presenting_screen(&self) -> Screen {
+        match self {
+            ScreenLayout::UpperOnly => Screen::Upper,
+            _ => Screen::Lower,
+        }
+    }
+
+    /// Size of the composited window and where each screen sits inside it, in screen pixels
+    /// with a top-left origin. Screens that are not shown have no region.
+    pub fn regions(&self, upper_size: (u32, u32), lower_size: (u32, u32)) -> ((u32, u32), Option<Rect>, Option<Rect>) {
+        let (uw, uh) = upper_size;
+        let (lw, lh) = lower_size;
+
+        match self {
+            ScreenLayout::SeparateWindows => (
+                upper_size,
+                Some(Rect::new(0.0, 0.0, uw as f32, uh as f32)),
+                Some(Rect::new(0.0, 0.0, lw as f32, lh as f32)),
+            ),
+            ScreenLayout::Stacked => {
+                let width = uw.max(lw);
+                (
+                    (width, uh + lh),
+                    Some(Rect::new((width - uw) as f32 / 2.0, 0.0, uw as f32, uh as f32)),
+                    Some(Rect::new((width - lw) as f32 / 2.0, uh as f32, lw as f32, lh as f32)),
+                )
+            }
+            ScreenLayout::SideBySide => {
+                let height = uh.max(lh);
+                (
+                    (uw + lw, height),
+                    Some(Rect::new(0.0, (height - uh) as f32 / 2.0, uw as f32, uh as f32)),
+                    Some(Rect::new(uw as f32, (height - lh) as f32 / 2.0, lw as f32, lh as f32)),
+                )
+            }
+            ScreenLayout::UpperOnly => (
+                upper_size,
+                Some(Rect::new(0.0, 0.0, uw as f32, uh as f32)),
+                None,
+            ),
+        }
+    }
+
+    /// Maps a point in framebuffer pixels to pixels of `screen`, if the point lands on it.
+    /// `content` is where the layout was drawn inside the window.
+    pub(crate) fn map_point(&self, screen: Screen, point: Vec2, content: Rect, upper_size: (u32, u32), lower_size: (u32, u32)) -> Option<Vec2> {
+        let (total, upper, lower) = self.regions(upper_size, lower_size);
+        let (region, size) = match screen {
+            Screen::Upper => (upper?, upper_size),
+            Screen::Lower => (lower?, lower_size),
+        };
+
+        // Separate windows each hold exactly one screen.
+        let total = if *self == ScreenLayout::SeparateWindows { size } else { total };
+        let point = (point - content.origin()) * Vec2::new(total.0 as f32, total.1 as f32) / content.size();
+        if !region.contains(point) {
+            return None;
+        }
+
+        Some((point - region.origin()) * Vec2::new(size.0 as f32, size.1 as f32) / region.size())
+    }
+}
+
+impl FromStr for ScreenLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "separate" | "separate_windows" => Ok(ScreenLayout::SeparateWindows),
+            "stacked" => Ok(ScreenLayout::Stacked),
+            "side_by_side" => Ok(ScreenLayout::SideBySide),
+            "upper_only" => Ok(ScreenLayout::UpperOnly),
+            _ => Err(format!("Unknown screen layout: {}", s)),
+        }
+    }
+}
+
+/// Framebuffer a screen renders into at its configured resolution before being presented.
+pub(crate) struct OffscreenTarget {
+    pub(crate) fbo: u32,
+    color: u32,
+    depth: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl OffscreenTarget {
+    pub(crate) fn create(width: u32, height: u32) -> Result<Self, String> {
+        let mut fbo = 0;
+        let mut color = 0;
+        let mut depth = 0;
+
+        unsafe {
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+
+            gl::GenRenderbuffers(1, &mut color);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, color);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, width as i32, height as i32);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, color);
+
+            gl::GenRenderbuffers(1, &mut depth);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, depth);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, width as i32, height as i32);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, depth);
+
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+            let target = Self { fbo, color, depth, width, height };
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                target.delete();
+                return Err(format!("Offscreen framebuffer is incomplete (status 0x{:X})", status));
+            }
+
+            Ok(target)
+        }
+    }
+
+    pub(crate) fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, self.width as i32, self.height as i32);
+        }
+    }
+
+    /// Must be called with the owning context current.
+    pub(crate) fn delete(&self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteRenderbuffers(1, &self.color);
+            gl::DeleteRenderbuffers(1, &self.depth);
+        }
+    }
+}
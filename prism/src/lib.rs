@@ -1,24 +1,101 @@
-use std::{ffi::{CStr, CString}, str::FromStr, time::Instant};
 
-use glam::{Mat4, Vec3};
+use std::collections::{HashMap, hash_map::Entry};
+
+use glam::{Mat4, Vec2, Vec3};
 use glfw::{Context, PWindow};
+use image::RgbaImage;
 
 pub use glam as glm;
-pub use glfw::Key;
-
-use crate::mesh::Mesh;
+pub use glfw::{Key, MouseButton};
+
+use crate::atlas::{PackedAtlas, TextureAtlas, TextureRegion};
+use crate::backend::{RenderBackend, Screen};
+use crate::bounds::Frustum;
+use crate::capture::{CaptureOutput, CaptureSession};
+use crate::config::{GraphicsApi, PrismConfig, ScreenConfig};
+use crate::layout::{OffscreenTarget, ScreenLayout};
+use crate::material::{BlendMode, Material, RenderState, StateCache};
+use crate::mesh::{Geometry, Mesh};
+use crate::post::{PostEffect, PostPrograms, PostProcessStack, ScreenPost};
+use crate::render_target::{ColorFormat, RenderTarget, RenderTargetDescriptor};
+use crate::resource::{GpuObject, ResourceRef, ResourceRegistry, SharedRegistry};
+use crate::glsl::{GlslProfile, ShaderDefines};
+use crate::instancing::{INSTANCE_FLOATS, Instance, InstanceBuffer};
+use crate::shader::{AttributeInfo, ShaderSource, ShaderVariants, ShaderWatcher, UniformInfo};
+use crate::shadow::ShadowMap;
+use crate::font::{BitmapFontData, CachedGlyph, Font, GLYPH_PAGE_SIZE, GlyphKey, GlyphPage, TextLayout, TextOptions};
+use crate::model::{Model, ModelData};
+use crate::sprite::SpriteBatch;
+use crate::vertex_layout::{BufferUsage, INSTANCE_COLOR, INSTANCE_TRANSFORM, VertexBuffer};
+use crate::texture::{FilterMode, TextureDescriptor};
+use crate::time::FrameClock;
+use crate::uniform_block::{CameraBlock, Light, LightsBlock, ShadowBlock, UniformBlock, UniformBuffer};
+pub mod atlas;
+pub mod backend;
+pub mod bounds;
+pub mod capture;
+pub mod config;
+pub mod font;
+pub mod glsl;
+pub mod instancing;
+pub mod layout;
+pub mod material;
 pub mod mesh;
+pub mod model;
+pub mod post;
+pub mod render_target;
+pub mod resource;
+pub mod shader;
+pub mod shadow;
+pub mod software;
+pub mod sprite;
+pub mod texture;
+pub mod time;
+pub mod uniform_block;
+pub mod vertex_layout;
+
+pub use crate::shader::Shader;
+pub use crate::texture::Texture;
 
 struct PrismWindow {
     window: Option<PWindow>,
     events: Option<glfw::GlfwReceiver<(f64, glfw::WindowEvent)>>,
 }
 
+/// Prism sticks to the GL 3.3 core subset that GLES 3.0 also has, so both contexts share the
+/// same code paths; only the shader `#version` header differs.
+fn set_context_hints(glfw: &mut glfw::Glfw, api: GraphicsApi) {
+    match api {
+        GraphicsApi::OpenGlEs => {
+            glfw.window_hint(glfw::WindowHint::ClientApi(glfw::ClientApiHint::OpenGlEs));
+            glfw.window_hint(glfw::WindowHint::ContextVersion(3, 0));
+            glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Any));
+        }
+        _ => {
+            glfw.window_hint(glfw::WindowHint::ClientApi(glfw::ClientApiHint::OpenGl));
+            glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
+            glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
+        }
+    }
+}
+
 impl PrismWindow {
-    fn create(glfw: &mut glfw::Glfw, title: &str, width: u32, height: u32) -> Result<Self, String> {
-        let (window, events) = glfw
-            .create_window(width, height, title, glfw::WindowMode::Windowed)
-            .ok_or_else(|| "Failed to create GLFW window".to_string())?;
+    /// Fullscreen windows take the monitor's current resolution; the screen is scaled into it.
+    fn create(glfw: &mut glfw::Glfw, title: &str, width: u32, height: u32, config: &ScreenConfig) -> Result<Self, String> {
+        glfw.window_hint(glfw::WindowHint::RefreshRate(config.refresh_rate));
+
+        let (window, events) = match config.fullscreen_monitor {
+            Some(index) => glfw.with_connected_monitors(|glfw, monitors| {
+                let monitor = monitors.get(index).ok_or_else(|| format!("Monitor {} is not connected", index))?;
+                let (width, height) = monitor.get_video_mode().map(|mode| (mode.width, mode.height)).unwrap_or((width, height));
+
+                glfw.create_window(width, height, title, glfw::WindowMode::FullScreen(monitor))
+                    .ok_or_else(|| "Failed to create fullscreen GLFW window".to_string())
+            })?,
+            None => glfw
+                .create_window(width, height, title, glfw::WindowMode::Windowed)
+                .ok_or_else(|| "Failed to create GLFW window".to_string())?,
+        };
         
         Ok(PrismWindow {
             window: Some(window),
@@ -26,9 +103,22 @@ impl PrismWindow {
         })
     }
 
-    fn create_shared(&mut self, title: &str, width: u32, height: u32) -> Result<Self, String> {
-        let (window, events) = self.window.as_mut().unwrap().create_shared(width, height, title, glfw::WindowMode::Windowed)
-            .ok_or_else(|| "Failed to create shared GLFW window".to_string())?;
+    fn create_shared(&mut self, glfw: &mut glfw::Glfw, title: &str, width: u32, height: u32, config: &ScreenConfig) -> Result<Self, String> {
+        glfw.window_hint(glfw::WindowHint::RefreshRate(config.refresh_rate));
+
+        let parent = self.window.as_mut().unwrap();
+        let (window, events) = match config.fullscreen_monitor {
+            Some(index) => glfw.with_connected_monitors(|_, monitors| {
+                let monitor = monitors.get(index).ok_or_else(|| format!("Monitor {} is not connected", index))?;
+                let (width, height) = monitor.get_video_mode().map(|mode| (mode.width, mode.height)).unwrap_or((width, height));
+
+                parent.create_shared(width, height, title, glfw::WindowMode::FullScreen(monitor))
+                    .ok_or_else(|| "Failed to create shared fullscreen GLFW window".to_string())
+            })?,
+            None => parent
+                .create_shared(width, height, title, glfw::WindowMode::Windowed)
+                .ok_or_else(|| "Failed to create shared GLFW window".to_string())?,
+        };
 
         Ok(PrismWindow {
             window: Some(window),
@@ -36,6 +126,11 @@ impl PrismWindow {
         })
     }
 
+    fn framebuffer_size(&self) -> (u32, u32) {
+        let (width, height) = self.window.as_ref().unwrap().get_framebuffer_size();
+        (width.max(0) as u32, height.max(0) as u32)
+    }
+
     fn make_current(&mut self) {
         self.window.as_mut().unwrap().make_current();
     }
@@ -45,28 +140,112 @@ pub struct PrismRenderer {
     glfw: Option<glfw::Glfw>,
     should_close: bool,
     initialized: bool,
-    current_window: u32, // 0 for upper, 1 for lower
-    upper_window: Option<PrismWindow>,
+    config: PrismConfig,
+    upper_window: Option<PrismWindow>, // Hosts both screens in composited layouts
     lower_window: Option<PrismWindow>,
-    upper_delta_time: f32,
-    lower_delta_time: f32,
-    upper_delta_instant: Instant,
-    lower_delta_instant: Instant
+    upper_target: Option<OffscreenTarget>,
+    lower_target: Option<OffscreenTarget>,
+    frame_clock: FrameClock,
+    resources: SharedRegistry,
+    active_screen: Option<Screen>,
+    capture_session: Option<CaptureSession>,
+    shader_watcher: ShaderWatcher,
+    shader_includes: HashMap<String, String>,
+    // The context type actually created; `Auto` until `init`.
+    graphics_api: GraphicsApi,
+    // Uniform block names bound to binding points as programs are linked.
+    uniform_block_bindings: HashMap<String, u32>,
+    screen_uniforms: HashMap<Screen, ScreenUniforms>,
+    // Created the first time a screen renders a shadow pass, in that screen's context.
+    shadow_maps: HashMap<Screen, ShadowMap>,
+    shadow_shader: Option<Shader>,
+    // The block being rendered while a shadow pass is open.
+    shadow_pass: Option<ShadowBlock>,
+    // The light this frame's shadow map of the screen being drawn was rendered from.
+    shadow_light: Option<Light>,
+    active_render_target: Option<RenderTarget>,
+    screen_post: HashMap<Screen, ScreenPost>,
+    // Compiled the first time a screen is given effects.
+    post_programs: Option<PostPrograms>,
+    // Attribute-less vertex arrays for full-screen passes, one per context.
+    fullscreen_vaos: [u32; resource::MAX_CONTEXTS],
+    // Created with the first sprite batch drawn.
+    sprites: Option<SpriteResources>,
+    // Created on first use by `white_texture`.
+    white_texture: Option<Texture>,
+    sprite_vaos: [u32; resource::MAX_CONTEXTS],
+    // What each context's fixed-function state was last set to.
+    state_caches: [StateCache; resource::MAX_CONTEXTS],
+    // In pixels from the top left of what is being drawn into; reset with every screen.
+    scissor: Option<Rect>,
+}
+
+/// A linked program with what reflection found in it, and the files it was built from.
+struct CompiledProgram {
+    program: u32,
+    uniforms: Vec<UniformInfo>,
+    attributes: Vec<AttributeInfo>,
+    files: Vec<std::path::PathBuf>,
+}
+
+/// Shader and streaming buffers shared by every sprite batch.
+struct SpriteResources {
+    shader: Shader,
+    // Stands in for the texture of plain coloured quads.
+    white: Texture,
+    vertices: ResourceRef,
+    indices: ResourceRef,
+}
+
+/// Built-in uniform blocks of a screen. Each screen has its own buffers, so that the lower
+/// screen's context never rewrites data the upper one may still be drawing with.
+struct ScreenUniforms {
+    camera: UniformBuffer<CameraBlock>,
+    lights: UniformBuffer<LightsBlock>,
+    shadow: UniformBuffer<ShadowBlock>,
 }
 
 impl PrismRenderer {
     pub fn new() -> Self {
+        Self::with_config(PrismConfig::default())
+    }
+
+    pub fn with_config(config: PrismConfig) -> Self {
         Self {
             glfw: None,
             should_close: false,
             initialized: false,
-            current_window: 4294967295,
+            config,
             upper_window: None,
             lower_window: None,
-            upper_delta_time: 0.0,
-            lower_delta_time: 0.0,
-            upper_delta_instant: Instant::now(),
-            lower_delta_instant: Instant::now()
+            upper_target: None,
+            lower_target: None,
+            frame_clock: FrameClock::new(),
+            resources: ResourceRegistry::new_shared(),
+            active_screen: None,
+            capture_session: None,
+            shader_watcher: ShaderWatcher::new(),
+            shader_includes: shader::BUILTIN_INCLUDES.iter().map(|(name, source)| (name.to_string(), source.to_string())).collect(),
+            graphics_api: GraphicsApi::Auto,
+            uniform_block_bindings: HashMap::from([
+                (CameraBlock::NAME.to_string(), CameraBlock::BINDING),
+                (LightsBlock::NAME.to_string(), LightsBlock::BINDING),
+                (ShadowBlock::NAME.to_string(), ShadowBlock::BINDING),
+            ]),
+            screen_uniforms: HashMap::new(),
+            shadow_maps: HashMap::new(),
+            shadow_shader: None,
+            shadow_pass: None,
+            shadow_light: None,
+            active_render_target: None,
+            screen_post: HashMap::new(),
+            post_programs: None,
+            fullscreen_vaos: [0; resource::MAX_CONTEXTS],
+            sprites: None,
+            white_texture: None,
+            sprite_vaos: [0; resource::MAX_CONTEXTS],
+            state_caches: Default::default(),
+            scissor: None,
         }
     }
 
@@ -75,83 +254,412 @@ impl PrismRenderer {
             return Err("PrismRenderer is already initialized".to_string());
         }
 
-        let mut glfw = glfw::init(glfw::fail_on_errors).map_err(|e| e.to_string())?;
-        
-        // glfw.window_hint(glfw::WindowHint::ContextVersion(3, 1));
-        glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
-        glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
-        // glfw.window_hint(glfw::WindowHint::ClientApi(glfw::ClientApiHint::OpenGlEs));
+        // Errors are logged rather than fatal, so that a context type the driver lacks can
+        // fall back to the next one.
+        let mut glfw = glfw::init(glfw::log_errors).map_err(|e| e.to_string())?;
         glfw.window_hint(glfw::WindowHint::Resizable(false));
 
-        let mut upper_window = PrismWindow::create(&mut glfw, "Prism Upper Window", 800, 480)?;
+        let upper_size = self.config.screen_size(Screen::Upper);
+        let lower_size = self.config.screen_size(Screen::Lower);
+        let layout = self.config.layout;
+
+        let (title, (width, height)) = if layout.is_composited() {
+            ("Prism", layout.regions(upper_size, lower_size).0)
+        }
+        else {
+            ("Prism Upper Window", upper_size)
+        };
+
+        let mut errors = Vec::new();
+        let mut created = None;
+        for &api in self.config.graphics_api.candidates() {
+            set_context_hints(&mut glfw, api);
+            match PrismWindow::create(&mut glfw, title, width, height, &self.config.upper) {
+                Ok(window) => {
+                    created = Some((window, api));
+                    break;
+                }
+                Err(e) => errors.push(format!("{:?}: {}", api, e)),
+            }
+        }
+
+        // New contexts start from the GL defaults.
+        self.state_caches = Default::default();
+
+        let (mut upper_window, api) = created.ok_or_else(|| format!("Failed to create a GL context ({})", errors.join("; ")))?;
 
         upper_window.make_current();
+        glfw.set_swap_interval(if self.config.vsync { glfw::SwapInterval::Sync(1) } else { glfw::SwapInterval::None });
 
-        gl_loader::init_gl();
-        gl::load_with(|s| gl_loader::get_proc_address(s) as *const _);
+        // Entry points come from the context itself, as GLES drivers live in a different
+        // library than desktop GL.
+        let window = upper_window.window.as_mut().unwrap();
+        gl::load_with(|s| window.get_proc_address(s).map_or(std::ptr::null(), |f| f as *const _));
 
         unsafe {
-            gl::Viewport(0, 0, 800, 480);
             gl::Enable(gl::DEPTH_TEST);
         }
 
-        let mut lower_window = upper_window.create_shared("Prism Lower Window", 800, 480)?;
+        // Screens always render at their configured resolution into a framebuffer of their
+        // window's context, which is then scaled onto the window when presenting.
+        self.upper_target = Some(OffscreenTarget::create(upper_size.0, upper_size.1)?);
+
+        if layout.is_composited() {
+            self.lower_target = Some(OffscreenTarget::create(lower_size.0, lower_size.1)?);
+        }
+        else {
+            let mut lower_window = upper_window.create_shared(&mut glfw, "Prism Lower Window", lower_size.0, lower_size.1, &self.config.lower)?;
+
+            lower_window.make_current();
+            glfw.set_swap_interval(if self.config.vsync { glfw::SwapInterval::Sync(1) } else { glfw::SwapInterval::None });
 
-        lower_window.make_current();
+            unsafe {
+                gl::Enable(gl::DEPTH_TEST);
+            }
 
-        unsafe {
-            gl::Viewport(0, 0, 800, 480);
-            gl::Enable(gl::DEPTH_TEST);
+            self.lower_target = Some(OffscreenTarget::create(lower_size.0, lower_size.1)?);
+            self.lower_window = Some(lower_window);
         }
         
         glfw.make_context_current(None);
 
+        self.frame_clock = FrameClock::new();
+        self.frame_clock.set_target_frame_rate(self.config.target_frame_rate);
+        self.resources = ResourceRegistry::new_shared();
+        self.shader_watcher = ShaderWatcher::new();
+        self.graphics_api = api;
         self.glfw = Some(glfw);
         self.upper_window = Some(upper_window);
-        self.lower_window = Some(lower_window);
         self.initialized = true;
+
+        // These go through the public constructors, which need an initialized renderer, so a
+        // failure tears everything down again instead of leaving the renderer half set up.
+        if let Err(e) = self.create_builtin_resources() {
+            self.deinit();
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    fn create_builtin_resources(&mut self) -> Result<(), String> {
+        for screen in [Screen::Upper, Screen::Lower] {
+            let uniforms = ScreenUniforms {
+                camera: self.create_uniform_buffer()?,
+                lights: self.create_uniform_buffer()?,
+                shadow: self.create_uniform_buffer()?,
+            };
+            self.screen_uniforms.insert(screen, uniforms);
+        }
+
+        self.shadow_shader = Some(self.create_shader_from_source(shader::SHADOW_DEPTH_VERTEX, shader::SHADOW_DEPTH_FRAGMENT)?);
+
+        Ok(())
+    }
+
+    /// Must be called before `init`.
+    pub fn set_config(&mut self, config: PrismConfig) -> Result<(), String> {
+        if self.initialized {
+            return Err("Config must be set before PrismRenderer is initialized".to_string());
+        }
+
+        self.config = config;
+        Ok(())
+    }
+
+    pub fn get_config(&self) -> &PrismConfig {
+        &self.config
+    }
+
+    /// Chooses how the screens are arranged on a desktop. Must be called before `init`.
+    pub fn set_layout(&mut self, layout: ScreenLayout) -> Result<(), String> {
+        if self.initialized {
+            return Err("Screen layout must be set before PrismRenderer is initialized".to_string());
+        }
+
+        self.config.layout = layout;
         Ok(())
     }
 
+    pub fn get_layout(&self) -> ScreenLayout {
+        self.config.layout
+    }
+
+    pub fn screen_size(&self, screen: Screen) -> (u32, u32) {
+        self.config.screen_size(screen)
+    }
+
+    pub fn aspect_ratio(&self, screen: Screen) -> f32 {
+        let (width, height) = self.config.screen_size(screen);
+        width as f32 / height as f32
+    }
+
     pub fn deinit(&mut self) {
         if !self.initialized {
             return;
         }
 
-        gl_loader::end_gl();
+        // The renderer's own resources are not leaks.
+        self.screen_uniforms.clear();
+        self.shadow_shader = None;
+        self.active_render_target = None;
+        self.screen_post.clear();
+        self.post_programs = None;
+        self.sprites = None;
+        self.white_texture = None;
+
+        {
+            let mut resources = self.resources.borrow_mut();
+            for resource in resources.live_resources() {
+                eprintln!("Prism: {} is still alive at deinit and will be deleted", resource);
+            }
+            resources.shut_down();
+        }
+
+        // Framebuffers and vertex arrays are not shared between contexts, so each is deleted on its own.
+        for screen in [Screen::Upper, Screen::Lower] {
+            self.screen_window_mut(screen).make_current();
+            self.collect_garbage(screen);
+            let target = match screen {
+                Screen::Upper => self.upper_target.take(),
+                Screen::Lower => self.lower_target.take(),
+            };
+            if let Some(target) = target {
+                target.delete();
+            }
+            if let Some(shadow_map) = self.shadow_maps.remove(&screen) {
+                shadow_map.delete();
+            }
+            let context = self.context_index(screen);
+            for vao in [&mut self.fullscreen_vaos[context], &mut self.sprite_vaos[context]] {
+                if *vao != 0 {
+                    unsafe {
+                        gl::DeleteVertexArrays(1, vao);
+                    }
+                    *vao = 0;
+                }
+            }
+        }
 
         self.glfw = None;
         self.upper_window = None;
         self.lower_window = None;
+        self.active_screen = None;
+        self.capture_session = None;
+        self.shadow_pass = None;
+        self.shadow_light = None;
         self.initialized = false;
     }
 
-    pub fn create_texture(&mut self, path: &std::path::Path) -> Texture {
-        if !self.initialized {
-            panic!("PrismRenderer must be initialized before creating textures");
+    /// Loads a texture from an image file with the default descriptor.
+    pub fn create_texture(&mut self, path: &std::path::Path) -> Result<Texture, String> {
+        self.create_texture_with_descriptor(path, &TextureDescriptor::default())
+    }
+
+    pub fn create_texture_with_descriptor(&mut self, path: &std::path::Path, descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        let image = texture::load_file(path)?;
+        self.upload_texture(image, descriptor, &path.to_string_lossy())
+    }
+
+    /// Decodes an encoded image (PNG, JPEG, ...) held in memory, e.g. from `include_bytes!`.
+    pub fn create_texture_from_memory(&mut self, bytes: &[u8], descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        let image = texture::load_memory(bytes)?;
+        self.upload_texture(image, descriptor, "texture from memory")
+    }
+
+    /// Creates a texture from raw RGBA8 pixels, top row first.
+    pub fn create_texture_from_rgba(&mut self, width: u32, height: u32, pixels: &[u8], descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        let image = texture::rgba_image(width, height, pixels)?;
+        self.upload_texture(image, descriptor, &format!("{}x{} RGBA texture", width, height))
+    }
+
+    /// Creates a texture from an image, top row first.
+    pub fn create_texture_from_image(&mut self, image: RgbaImage, descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        let label = format!("{}x{} image texture", image.width(), image.height());
+        self.upload_texture(image, descriptor, &label)
+    }
+
+    pub fn create_texture_atlas(&mut self, packed: &PackedAtlas, descriptor: &TextureDescriptor) -> Result<TextureAtlas, String> {
+        RenderBackend::create_texture_atlas(self, packed, descriptor)
+    }
+
+    /// A shared 1x1 white texture, for shaders that sample a texture when there is none.
+    pub fn white_texture(&mut self) -> Result<Texture, String> {
+        if let Some(texture) = &self.white_texture {
+            return Ok(texture.clone());
         }
-    
-        match self.upper_window {
-            Some(ref mut window) => {
-                window.make_current();
+
+        let texture = self.create_texture_from_rgba(1, 1, &[255; 4], &TextureDescriptor::default())?;
+        self.white_texture = Some(texture.clone());
+        Ok(texture)
+    }
+
+    /// Uploads a model's meshes and textures, giving every material `shader`, which is
+    /// expected to take the built-in Phong parameters and a `texture_0` diffuse map.
+    pub fn create_model(&mut self, data: &ModelData, shader: &Shader) -> Result<Model, String> {
+        let textures = data
+            .textures
+            .iter()
+            .map(|texture| self.create_texture_from_image(texture.image.clone(), &texture.descriptor))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Stands in for the diffuse map of untextured materials.
+        let white = self.white_texture()?;
+
+        let mut materials: Vec<Material> = data
+            .materials
+            .iter()
+            .map(|material_data| {
+                let mut material = Material::new(shader);
+                model::apply_material_data(&mut material, material_data);
+                let texture = material_data.diffuse_texture.and_then(|index| textures.get(index)).unwrap_or(&white);
+                material.set_texture("texture_0", texture);
+                material
+            })
+            .collect();
+
+        let mut default_material = None;
+        let mut mesh_materials = Vec::with_capacity(data.meshes.len());
+        let mut meshes = Vec::with_capacity(data.meshes.len());
+        for mesh_data in &data.meshes {
+            let material = match mesh_data.material.filter(|&index| index < materials.len()) {
+                Some(index) => index,
+                None => *default_material.get_or_insert_with(|| {
+                    let mut material = Material::new(shader);
+                    model::apply_material_data(&mut material, &Default::default());
+                    material.set_texture("texture_0", &white);
+                    materials.push(material);
+                    materials.len() - 1
+                }),
+            };
+            mesh_materials.push(material);
+            meshes.push(self.create_mesh(mesh_data.vertices.clone(), mesh_data.indices.clone(), Vec::new()));
+        }
+
+        let nodes = data
+            .nodes
+            .iter()
+            .map(|node| model::ModelNode { name: node.name.clone(), transform: node.transform, meshes: node.meshes.clone(), children: node.children.clone() })
+            .collect();
+
+        Ok(Model { meshes, mesh_materials, materials, textures, nodes, roots: data.roots.clone() })
+    }
+
+    /// Uploads the pages of a bitmap font. They are always flipped vertically, whatever the
+    /// descriptor says, as glyph regions are located on flipped pages.
+    pub fn create_bitmap_font(&mut self, data: &BitmapFontData, descriptor: &TextureDescriptor) -> Result<Font, String> {
+        let descriptor = TextureDescriptor { flip_vertically: true, ..descriptor.clone() };
+        let pages = data
+            .pages()
+            .iter()
+            .map(|page| self.create_texture_from_image(page.clone(), &descriptor))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Font::from_bitmap(data, &pages))
+    }
+
+    /// Lays `text` out in pixels with a top-left origin, rasterizing any glyphs `font` has
+    /// not drawn before. Keep the layout around for text that does not change.
+    pub fn layout_text(&mut self, font: &mut Font, text: &str, options: &TextOptions) -> Result<TextLayout, String> {
+        let (placed, size) = font.place(text, options);
+
+        let mut glyphs = Vec::with_capacity(placed.len());
+        for (key, pen) in placed {
+            let glyph = match font.cached(key) {
+                Some(glyph) => glyph,
+                None => self.cache_glyph(font, key)?,
+            };
+
+            if let Some(glyph) = glyph {
+                // Whole pixels keep glyphs sharp.
+                let origin = (pen + glyph.offset).round();
+                let rect = Rect::new(origin.x, origin.y, glyph.region.width() as f32, glyph.region.height() as f32);
+                glyphs.push((glyph.region, rect));
             }
-            None => panic!("Upper window is not initialized"),
         }
 
-        // Load image using the image crate
-        let img = image::open(path).expect("Failed to load texture").flipv();
-        let data = img.to_rgba8();
-        let (width, height) = (img.width(), img.height());
+        Ok(TextLayout { glyphs, size })
+    }
+
+    fn cache_glyph(&mut self, font: &mut Font, key: GlyphKey) -> Result<Option<CachedGlyph>, String> {
+        let bitmap = match font.rasterize(key) {
+            Some(bitmap) => bitmap,
+            None => {
+                font.glyphs.insert(key, None);
+                return Ok(None);
+            }
+        };
+
+        let (width, height) = (bitmap.width, bitmap.height);
+        let free = font.pages.iter_mut().enumerate().find_map(|(i, page)| page.allocate(width, height).map(|(x, y)| (i, x, y)));
+        let (page, x, y) = match free {
+            Some(free) => free,
+            None => {
+                let blank = vec![0u8; (GLYPH_PAGE_SIZE * GLYPH_PAGE_SIZE * 4) as usize];
+                let descriptor = TextureDescriptor { min_filter: FilterMode::Linear, mag_filter: FilterMode::Linear, ..TextureDescriptor::default() };
+                let mut page = GlyphPage::new(self.create_texture_from_rgba(GLYPH_PAGE_SIZE, GLYPH_PAGE_SIZE, &blank, &descriptor)?);
+                let (x, y) = page
+                    .allocate(width, height)
+                    .ok_or_else(|| format!("A {}x{} glyph does not fit in a glyph cache page", width, height))?;
+                font.pages.push(page);
+                (font.pages.len() - 1, x, y)
+            }
+        };
+
+        let texture = font.pages[page].texture.clone();
+        // Pages are flipped like other textures, so the glyph's top row goes highest.
+        self.update_texture(&texture, x, GLYPH_PAGE_SIZE - y - height, width, height, &bitmap.pixels);
+
+        let glyph = CachedGlyph { region: TextureRegion::from_pixels(texture, x, y, width, height, true), offset: bitmap.offset };
+        font.glyphs.insert(key, Some(glyph.clone()));
+        Ok(Some(glyph))
+    }
+
+    /// Replaces a block of `texture`'s texels, counted from its first stored row, with RGBA pixels.
+    fn update_texture(&mut self, texture: &Texture, x: u32, y: u32, width: u32, height: u32, pixels: &[u8]) {
+        self.begin_resource_upload();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture.raw_id());
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                x as i32,
+                y as i32,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const _,
+            );
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        self.end_resource_upload();
+    }
+
+    fn upload_texture(&mut self, image: RgbaImage, descriptor: &TextureDescriptor, label: &str) -> Result<Texture, String> {
+        if !self.initialized {
+            return Err("PrismRenderer must be initialized before creating textures".to_string());
+        }
+
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            return Err(format!("Texture \"{}\" has no pixels", label));
+        }
+
+        let data = texture::orient(image, descriptor);
+
+        self.begin_resource_upload();
 
         let mut texture: u32 = 0;
         unsafe {
             gl::GenTextures(1, &mut texture);
             gl::BindTexture(gl::TEXTURE_2D, texture);
 
+            // Rows of odd-width images are not 4-byte aligned
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
             gl::TexImage2D(
                 gl::TEXTURE_2D,
                 0,
-                gl::RGBA as i32,
+                descriptor.gl_internal_format(),
                 width as i32,
                 height as i32,
                 0,
@@ -160,22 +668,83 @@ impl PrismRenderer {
                 data.as_ptr() as *const _,
             );
 
-            // Set texture parameters
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, descriptor.wrap_s.to_gl());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, descriptor.wrap_t.to_gl());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, descriptor.gl_min_filter());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, descriptor.gl_mag_filter());
+
+            if descriptor.mipmaps {
+                gl::GenerateMipmap(gl::TEXTURE_2D);
+            }
 
-            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+
+        self.end_resource_upload();
+
+        Ok(Texture {
+            resource: ResourceRef::new(&self.resources, GpuObject::Texture(texture), label),
+            width,
+            height,
+        })
+    }
+
+    /// Resources are created on the upper window's context, whose objects the lower one shares.
+    fn begin_resource_upload(&mut self) {
+        match self.upper_window {
+            Some(ref mut window) => {
+                window.make_current();
+            }
+            None => panic!("Upper window is not initialized"),
+        }
+    }
 
-            match self.glfw {
+    /// Returns to the context of the screen being rendered, if any.
+    fn end_resource_upload(&mut self) {
+        match self.active_screen {
+            Some(screen) => self.screen_window_mut(screen).make_current(),
+            None => match self.glfw {
                 Some(ref mut glfw) => {
                     glfw.make_context_current(None);
                 }
                 None => panic!("GLFW is not initialized"),
+            },
+        }
+    }
+
+    /// Index of the GL context `screen` renders with.
+    fn context_index(&self, screen: Screen) -> usize {
+        match screen {
+            Screen::Lower if !self.config.layout.is_composited() => 1,
+            _ => 0,
+        }
+    }
+
+    /// Deletes resources released since the last call. `screen`'s context must be current.
+    fn collect_garbage(&mut self, screen: Screen) {
+        let (objects, vaos, framebuffers) = self.resources.borrow_mut().take_pending(self.context_index(screen));
+
+        unsafe {
+            for object in objects {
+                match object {
+                    GpuObject::Texture(id) => gl::DeleteTextures(1, &id),
+                    GpuObject::Mesh { vbo, ebo, .. } => {
+                        gl::DeleteBuffers(1, &vbo);
+                        gl::DeleteBuffers(1, &ebo);
+                    }
+                    GpuObject::Program(id) => gl::DeleteProgram(id),
+                    GpuObject::Buffer(id) => gl::DeleteBuffers(1, &id),
+                    GpuObject::RenderTarget { .. } => render_target::delete_renderbuffers(&object),
+                }
+            }
+
+            if !vaos.is_empty() {
+                gl::DeleteVertexArrays(vaos.len() as i32, vaos.as_ptr());
             }
 
-            Texture { id: texture }
+            if !framebuffers.is_empty() {
+                gl::DeleteFramebuffers(framebuffers.len() as i32, framebuffers.as_ptr());
+            }
         }
     }
 
@@ -188,6 +757,10 @@ impl PrismRenderer {
             panic!("PrismRenderer must be initialized before handling events");
         }
 
+        // Events are handled once per frame, so this is where the frame clock advances.
+        self.frame_clock.tick();
+        self.reload_changed_shaders();
+
         if let Some(glfw) = &mut self.glfw {
             glfw.poll_events();
 
@@ -236,11 +809,16 @@ impl PrismRenderer {
             panic!("PrismRenderer must be initialized before beginning upper screen");
         }
 
-        self.upper_delta_instant = Instant::now();
+        self.screen_window_mut(Screen::Upper).make_current();
+        self.collect_garbage(Screen::Upper);
 
-        if let Some(upper_window) = &mut self.upper_window {
-            upper_window.make_current();
-        }
+        self.bind_screen_target(Screen::Upper);
+
+        self.active_screen = Some(Screen::Upper);
+        self.scissor = None;
+        // Nothing is shadowed until this frame's shadow pass has been rendered.
+        self.set_shadow_block(&ShadowBlock::default());
+        self.shadow_light = None;
     }
 
     pub fn end_upper_screen(&mut self) {
@@ -248,13 +826,13 @@ impl PrismRenderer {
             panic!("PrismRenderer must be initialized before ending upper screen");
         }
 
-        if let Some(upper_window) = &mut self.upper_window {
-            if let Some(window) = &mut upper_window.window {
-                window.swap_buffers();
-            }
-        }
+        // Blits are clipped by the scissor test too.
+        self.state_caches[self.context_index(Screen::Upper)].set_scissor(None);
+        self.apply_post_processing(Screen::Upper);
+        self.capture_frame(Screen::Upper);
+        self.present(Screen::Upper);
 
-        self.upper_delta_time = self.upper_delta_instant.elapsed().as_secs_f32();
+        self.active_screen = None;
 
         if let Some(glfw) = &mut self.glfw {
             glfw.make_context_current(None);
@@ -266,11 +844,16 @@ impl PrismRenderer {
             panic!("PrismRenderer must be initialized before beginning lower screen");
         }
 
-        self.lower_delta_instant = Instant::now();
+        self.screen_window_mut(Screen::Lower).make_current();
+        self.collect_garbage(Screen::Lower);
 
-        if let Some(lower_window) = &mut self.lower_window {
-            lower_window.make_current();
-        }
+        self.bind_screen_target(Screen::Lower);
+
+        self.active_screen = Some(Screen::Lower);
+        self.scissor = None;
+        // Nothing is shadowed until this frame's shadow pass has been rendered.
+        self.set_shadow_block(&ShadowBlock::default());
+        self.shadow_light = None;
     }
 
     pub fn end_lower_screen(&mut self) {
@@ -278,217 +861,1319 @@ impl PrismRenderer {
             panic!("PrismRenderer must be initialized before ending lower screen");
         }
 
-        if let Some(lower_window) = &mut self.lower_window {
-            if let Some(window) = &mut lower_window.window {
-                window.swap_buffers();
-            }
-        }
+        self.state_caches[self.context_index(Screen::Lower)].set_scissor(None);
+        self.apply_post_processing(Screen::Lower);
+        self.capture_frame(Screen::Lower);
+        self.present(Screen::Lower);
 
-        self.lower_delta_time = self.lower_delta_instant.elapsed().as_secs_f32();
+        self.active_screen = None;
 
         if let Some(glfw) = &mut self.glfw {
             glfw.make_context_current(None);
         }
     }
 
-    pub fn clear_screen(&mut self, color: glam::Vec4) {
+    /// Reads back the given screen. While the screen is being rendered this returns the
+    /// frame drawn so far, otherwise the last presented frame. Screens with post-processing
+    /// only have their frame once it has ended.
+    pub fn capture_screen(&mut self, screen: Screen) -> RgbaImage {
         if !self.initialized {
-            panic!("PrismRenderer must be initialized before clearing screen");
+            panic!("PrismRenderer must be initialized before capturing screens");
         }
 
-        unsafe {
-            gl::ClearColor(color.x, color.y, color.z, color.w);
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        if self.active_screen == Some(screen) {
+            return self.read_screen_pixels(screen);
         }
+
+        self.screen_window_mut(screen).make_current();
+        let image = self.read_screen_pixels(screen);
+
+        match self.active_screen {
+            Some(active) => self.screen_window_mut(active).make_current(),
+            None => match self.glfw {
+                Some(ref mut glfw) => glfw.make_context_current(None),
+                None => panic!("GLFW is not initialized"),
+            },
+        }
+
+        image
     }
 
-    pub fn create_mesh(&mut self, vertices: Vec<Vertex>, indices: Vec<u32>, textures: Vec<Texture>) -> Mesh {
+    /// Starts writing every `every_nth` frame of `screen` to `output` until `stop_capture` is called.
+    pub fn start_capture(&mut self, screen: Screen, every_nth: u32, output: CaptureOutput) -> Result<(), String> {
         if !self.initialized {
-            panic!("PrismRenderer must be initialized before creating meshes");
+            panic!("PrismRenderer must be initialized before capturing screens");
         }
 
-        match self.upper_window {
-            Some(ref mut window) => {
-                window.make_current();
-            }
-            None => panic!("Upper window is not initialized"),
+        if self.capture_session.is_some() {
+            return Err("A capture session is already running".to_string());
         }
-        
-        let mut vao = 0;
-        let mut vbo = 0;
-        let mut ebo = 0;
 
-        unsafe {
-            gl::GenVertexArrays(1, &mut vao);
-            gl::GenBuffers(1, &mut vbo);
-            gl::GenBuffers(1, &mut ebo);
+        self.capture_session = Some(CaptureSession::new(screen, every_nth, output)?);
+        Ok(())
+    }
 
-            let converted_vertices: Vec<f32> = vertices.iter().flat_map(|v| {
-                vec![
-                    v.position.x, v.position.y, v.position.z,
-                    v.normal.x, v.normal.y, v.normal.z,
-                    v.tex_coords.x, v.tex_coords.y,
-                ]
-            }).collect();
+    /// Ends the running capture session, returning the number of frames written.
+    pub fn stop_capture(&mut self) -> Result<u32, String> {
+        match self.capture_session.take() {
+            Some(session) => session.finish(),
+            None => Err("No capture session is running".to_string()),
+        }
+    }
 
-            gl::BindVertexArray(vao);
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (converted_vertices.len() * std::mem::size_of::<f32>()) as isize,
-                converted_vertices.as_ptr() as *const _,
-                gl::STATIC_DRAW,
-            );
+    fn capture_frame(&mut self, screen: Screen) {
+        let should_capture = match self.capture_session {
+            Some(ref mut session) => session.screen == screen && session.next_frame(),
+            None => false,
+        };
 
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
-            gl::BufferData(
-                gl::ELEMENT_ARRAY_BUFFER,
-                (indices.len() * std::mem::size_of::<u32>()) as isize,
-                indices.as_ptr() as *const _,
-                gl::STATIC_DRAW,
-            );
+        if should_capture {
+            let image = self.read_screen_pixels(screen);
+            if let Some(session) = &mut self.capture_session {
+                session.write_frame(image);
+            }
+        }
+    }
 
-            gl::EnableVertexAttribArray(0);
-            gl::VertexAttribPointer(
-                0,
-                3,
-                gl::FLOAT,
-                gl::FALSE,
-                (8 * std::mem::size_of::<f32>()) as i32,
-                std::ptr::null()
-            );
-            gl::EnableVertexAttribArray(1);
-            gl::VertexAttribPointer(
-                1,
-                3,
-                gl::FLOAT,
-                gl::FALSE,
-                (8 * std::mem::size_of::<f32>()) as i32,
-                (3 * std::mem::size_of::<f32>()) as *const std::ffi::c_void
-            );
-            gl::EnableVertexAttribArray(2);
-            gl::VertexAttribPointer(
-                2,
-                2,
-                gl::FLOAT,
-                gl::FALSE,
-                (8 * std::mem::size_of::<f32>()) as i32,
-                (6 * std::mem::size_of::<f32>()) as *const std::ffi::c_void
-            );
+    fn read_screen_pixels(&mut self, screen: Screen) -> RgbaImage {
+        let target = match self.screen_target(screen) {
+            Some(target) => target,
+            None => panic!("{:?} screen target is not initialized", screen),
+        };
+        let (fbo, width, height) = (target.fbo, target.width, target.height);
 
-            gl::BindVertexArray(0);
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, fbo);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
         }
-
-        match self.glfw {
-            Some(ref mut glfw) => {
-                glfw.make_context_current(None);
-            }
-            None => panic!("GLFW is not initialized"),
+        let image = capture::read_pixels(width, height);
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
         }
 
-        Mesh {
-            vertices,
-            indices,
-            textures,
-            model: Mat4::IDENTITY,
-            vao,
-            vbo,
-            ebo,
-            position: glam::Vec3::ZERO,
-            rotation: glam::Vec3::ZERO,
-            scale: glam::Vec3::ONE,
-        }
+        image
     }
 
-    pub fn create_shader_from_source(&mut self, vertex_src: &str, fragment_src: &str) -> Result<Shader, String> {
-        if !self.initialized {
-            panic!("PrismRenderer must be initialized before creating shaders");
+    /// Where the layout sits inside `screen`'s window, in framebuffer pixels with a top-left
+    /// origin, along with the layout's size and the screens shown in that window.
+    fn window_content(&self, screen: Screen) -> (Rect, (u32, u32), Vec<(Screen, Rect)>) {
+        let layout = self.config.layout;
+        let upper_size = self.config.screen_size(Screen::Upper);
+        let lower_size = self.config.screen_size(Screen::Lower);
+
+        let (total, screens) = if layout.is_composited() {
+            let (total, upper_region, lower_region) = layout.regions(upper_size, lower_size);
+            let screens = [(Screen::Upper, upper_region), (Screen::Lower, lower_region)]
+                .into_iter()
+                .filter_map(|(screen, region)| region.map(|region| (screen, region)))
+                .collect();
+            (total, screens)
         }
+        else {
+            let size = self.config.screen_size(screen);
+            (size, vec![(screen, Rect::new(0.0, 0.0, size.0 as f32, size.1 as f32))])
+        };
 
-        match self.upper_window {
-            Some(ref mut window) => {
-                window.make_current();
-            }
-            None => panic!("Upper window is not initialized"),
-        }
+        let owner = if layout.is_composited() { Screen::Upper } else { screen };
+        let framebuffer_size = self.screen_window(screen).framebuffer_size();
+        let content = self.config.screen(owner).scaling.fit(total, framebuffer_size);
 
-        let mut vertex_shader: u32 = 0;
-        let mut fragment_shader = 0;
-        let mut shader = 0;
+        (content, total, screens)
+    }
 
-        unsafe {
-            vertex_shader = gl::CreateShader(gl::VERTEX_SHADER);
-            fragment_shader = gl::CreateShader(gl::FRAGMENT_SHADER);
+    fn present(&mut self, screen: Screen) {
+        if self.config.layout.is_composited() && self.config.layout.presenting_screen() != screen {
+            return;
+        }
 
-            let c_str_vert = std::ffi::CString::new(vertex_src.as_bytes()).unwrap();
-            gl::ShaderSource(vertex_shader, 1, &c_str_vert.as_ptr(), std::ptr::null());
-            gl::CompileShader(vertex_shader);
-            let mut success: i32 = 1;
-            gl::GetShaderiv(vertex_shader, gl::COMPILE_STATUS, &mut success);
-            if success == 0 {
-                let mut buffer: Vec<u8> = Vec::with_capacity(1024);
+        let (content, total, screens) = self.window_content(screen);
+        let (fb_width, fb_height) = self.screen_window(screen).framebuffer_size();
+        let scale = content.size() / Vec2::new(total.0 as f32, total.1 as f32);
 
-                gl::GetShaderInfoLog(vertex_shader, 1024, std::ptr::null_mut(), buffer.as_mut_ptr() as *mut i8);
-                buffer.set_len(1024);
-                let error_message = String::from_utf8_lossy(&buffer).into_owned();
-                return Err(format!("Vertex shader compilation failed: {}", error_message));
+        unsafe {
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, fb_width as i32, fb_height as i32);
+            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+
+            for (shown, region) in screens {
+                let target = match self.screen_target(shown) {
+                    Some(target) => target,
+                    None => continue,
+                };
+
+                // Blits use a bottom-left origin, regions a top-left one.
+                let x0 = (content.x + region.x * scale.x).round() as i32;
+                let x1 = (content.x + (region.x + region.width) * scale.x).round() as i32;
+                let y0 = fb_height as i32 - (content.y + (region.y + region.height) * scale.y).round() as i32;
+                let y1 = fb_height as i32 - (content.y + region.y * scale.y).round() as i32;
+                let filter = if x1 - x0 == target.width as i32 && y1 - y0 == target.height as i32 { gl::NEAREST } else { gl::LINEAR };
+
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, target.fbo);
+                gl::BlitFramebuffer(
+                    0,
+                    0,
+                    target.width as i32,
+                    target.height as i32,
+                    x0,
+                    y0,
+                    x1,
+                    y1,
+                    gl::COLOR_BUFFER_BIT,
+                    filter,
+                );
             }
 
-            let c_str_frag = std::ffi::CString::new(fragment_src.as_bytes()).unwrap();
-            gl::ShaderSource(fragment_shader, 1, &c_str_frag.as_ptr(), std::ptr::null());
-            gl::CompileShader(fragment_shader);
-            success = 1;
-            gl::GetShaderiv(fragment_shader, gl::COMPILE_STATUS, &mut success);
-            if success == 0 {
-                let mut buffer: Vec<u8> = Vec::with_capacity(1024);
-                gl::GetShaderInfoLog(fragment_shader, 1024, std::ptr::null_mut(), buffer.as_mut_ptr() as *mut i8);
-                buffer.set_len(1024);
-                let error_message = String::from_utf8_lossy(&buffer).into_owned();
-                return Err(format!("Fragment shader compilation failed: {}", error_message));
-            }
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
 
-            shader = gl::CreateProgram();
-            gl::AttachShader(shader, vertex_shader);
-            gl::AttachShader(shader, fragment_shader);
-            gl::LinkProgram(shader);
+        if let Some(window) = &mut self.screen_window_mut(screen).window {
+            window.swap_buffers();
+        }
+    }
 
-            gl::DeleteShader(vertex_shader);
-            gl::DeleteShader(fragment_shader);
+    fn screen_target(&self, screen: Screen) -> Option<&OffscreenTarget> {
+        match screen {
+            Screen::Upper => self.upper_target.as_ref(),
+            Screen::Lower => self.lower_target.as_ref(),
         }
+    }
 
-        match self.glfw {
-            Some(ref mut glfw) => {
-                glfw.make_context_current(None);
+    /// Binds what `screen` is drawn into: its post-processing scene target if it has
+    /// effects, otherwise its offscreen target.
+    fn bind_screen_target(&self, screen: Screen) {
+        match self.screen_post.get(&screen) {
+            Some(post) => self.bind_render_target(&post.scene, screen),
+            None => {
+                if let Some(target) = self.screen_target(screen) {
+                    target.bind();
+                }
             }
-            None => panic!("GLFW is not initialized"),
-        };
+        }
+    }
+
+    fn screen_window(&self, screen: Screen) -> &PrismWindow {
+        let window = match screen {
+            Screen::Lower if !self.config.layout.is_composited() => self.lower_window.as_ref(),
+            _ => self.upper_window.as_ref(),
+        };
+
+        match window {
+            Some(window) => window,
+            None => panic!("{:?} window is not initialized", screen),
+        }
+    }
+
+    fn screen_window_mut(&mut self, screen: Screen) -> &mut PrismWindow {
+        let window = match screen {
+            Screen::Lower if !self.config.layout.is_composited() => self.lower_window.as_mut(),
+            _ => self.upper_window.as_mut(),
+        };
+
+        match window {
+            Some(window) => window,
+            None => panic!("{:?} window is not initialized", screen),
+        }
+    }
+
+    /// Clears the whole screen, or render target, ignoring the scissor rectangle. Depth goes
+    /// back to the far plane and stencil to 0.
+    pub fn clear_screen(&mut self, color: glam::Vec4) {
+        if !self.initialized {
+            panic!("PrismRenderer must be initialized before clearing screen");
+        }
+
+        let screen = match self.active_screen {
+            Some(screen) => screen,
+            None => panic!("A screen must be begun before clearing it"),
+        };
+
+        // A material without depth or stencil writes may have left them off, which would keep
+        // those buffers from being cleared.
+        let cache = &mut self.state_caches[self.context_index(screen)];
+        cache.set_depth_write(true);
+        cache.set_stencil(None);
+        cache.set_scissor(None);
+
+        unsafe {
+            gl::ClearColor(color.x, color.y, color.z, color.w);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        }
+    }
+
+    /// Clips the meshes and sprites drawn afterwards to `rect`, in pixels from the top left
+    /// of the screen or render target being drawn into. `None` turns clipping off, as does
+    /// beginning a screen.
+    pub fn set_scissor(&mut self, rect: Option<Rect>) {
+        if self.active_screen.is_none() {
+            panic!("A screen must be begun before setting a scissor rectangle");
+        }
+        self.scissor = rect;
+    }
+
+    pub fn scissor(&self) -> Option<Rect> {
+        self.scissor
+    }
+
+    /// Sets `state` on `screen`'s context, making GL calls only for what changed since the
+    /// last draw there. `scissored` draws are clipped to the rectangle of `set_scissor`.
+    fn apply_render_state(&mut self, screen: Screen, state: &RenderState, scissored: bool) {
+        let scissor = match self.scissor.filter(|_| scissored) {
+            Some(rect) => {
+                let height = match &self.active_render_target {
+                    Some(target) => target.height(),
+                    None => self.config.screen_size(screen).1,
+                };
+                // GL counts from the bottom left.
+                let bottom = height as f32 - rect.y - rect.height;
+                Some([rect.x.round() as i32, bottom.round() as i32, rect.width.round().max(0.0) as i32, rect.height.round().max(0.0) as i32])
+            }
+            None => None,
+        };
+
+        let cache = &mut self.state_caches[self.context_index(screen)];
+        cache.apply(state);
+        cache.set_scissor(scissor);
+    }
+
+    pub fn create_mesh(&mut self, vertices: Vec<Vertex>, indices: Vec<u32>, textures: Vec<Texture>) -> Mesh {
+        let mut mesh = self.create_mesh_with_layout(&VertexBuffer::from_vertices(&vertices), &indices, textures, BufferUsage::Static);
+        mesh.vertices = vertices;
+        mesh.indices = indices;
+        mesh
+    }
+
+    /// A mesh with any vertex layout; shaders read each attribute at its location. Meshes
+    /// that will be rewritten with `update_mesh` should not be `BufferUsage::Static`.
+    pub fn create_mesh_with_layout(&mut self, vertices: &VertexBuffer, indices: &[u32], textures: Vec<Texture>, usage: BufferUsage) -> Mesh {
+        if !self.initialized {
+            panic!("PrismRenderer must be initialized before creating meshes");
+        }
+
+        self.begin_resource_upload();
+
+        let mut buffers = [0; 2];
+        unsafe {
+            gl::GenBuffers(2, buffers.as_mut_ptr());
+            upload_mesh_buffers(buffers[0], buffers[1], vertices.as_bytes(), Some(indices), usage);
+        }
+
+        self.end_resource_upload();
+
+        let label = format!("{} vertices, {} indices", vertices.len(), indices.len());
+        let object = GpuObject::Mesh { vbo: buffers[0], ebo: buffers[1], vaos: [0; resource::MAX_CONTEXTS] };
+
+        Mesh {
+            resource: ResourceRef::new(&self.resources, object, &label),
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            textures,
+            layout: vertices.layout().clone(),
+            usage,
+            vertex_count: vertices.len(),
+            index_count: indices.len(),
+            bounds: vertices.bounds(),
+            model: Mat4::IDENTITY,
+            position: glam::Vec3::ZERO,
+            rotation: glam::Vec3::ZERO,
+            scale: glam::Vec3::ONE,
+        }
+    }
+
+    /// Replaces a mesh's vertices, and its indices when given, keeping its layout. The
+    /// buffers get new storage each time, orphaning the old one, so frames still drawing from
+    /// it do not stall; GL 3.3 and GLES 3.0 have no persistent mapping. The mesh must not
+    /// have clones, which share the buffers but would keep their old counts and bounds.
+    pub fn update_mesh(&mut self, mesh: &mut Mesh, vertices: &VertexBuffer, indices: Option<&[u32]>) {
+        if vertices.layout() != &mesh.layout {
+            panic!("A mesh's vertex layout cannot change; create a new mesh instead");
+        }
+        if mesh.resource.is_shared() {
+            panic!("A mesh with clones cannot be updated, as the clones would not see the new counts and bounds");
+        }
+
+        let (vbo, ebo) = self.mesh_buffers(mesh);
+        self.begin_resource_upload();
+        unsafe {
+            upload_mesh_buffers(vbo, ebo, vertices.as_bytes(), indices, mesh.usage);
+        }
+        self.end_resource_upload();
+
+        mesh.vertex_count = vertices.len();
+        mesh.bounds = vertices.bounds();
+        if let Some(indices) = indices {
+            mesh.index_count = indices.len();
+        }
+        // The copies kept for meshes made from `Vertex`es no longer match.
+        mesh.vertices.clear();
+        mesh.indices.clear();
+    }
+
+    /// Overwrites the vertices from `first_vertex` on in place, for meshes that change in
+    /// part. Unlike `update_mesh`, this waits for draws still reading the buffer. The mesh
+    /// must not have clones either.
+    pub fn update_mesh_vertices(&mut self, mesh: &mut Mesh, first_vertex: usize, vertices: &VertexBuffer) {
+        if vertices.layout() != &mesh.layout {
+            panic!("A mesh's vertex layout cannot change; create a new mesh instead");
+        }
+        if mesh.resource.is_shared() {
+            panic!("A mesh with clones cannot be updated, as the clones would not see the new bounds");
+        }
+        if first_vertex + vertices.len() > mesh.vertex_count {
+            panic!("Vertices {}..{} are out of range for a mesh of {}", first_vertex, first_vertex + vertices.len(), mesh.vertex_count);
+        }
+
+        let (vbo, _) = self.mesh_buffers(mesh);
+        let bytes = vertices.as_bytes();
+        self.begin_resource_upload();
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferSubData(gl::ARRAY_BUFFER, (first_vertex * mesh.layout.stride()) as isize, bytes.len() as isize, bytes.as_ptr() as *const _);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        self.end_resource_upload();
+
+        // Only grows, as the vertices left alone are not known here.
+        mesh.bounds = match (mesh.bounds, vertices.bounds()) {
+            (Some(bounds), Some(updated)) => Some(bounds.union(&updated)),
+            (bounds, updated) => bounds.or(updated),
+        };
+        mesh.vertices.clear();
+    }
+
+    /// Uploads per-instance data for `draw_mesh_instanced`.
+    pub fn create_instance_buffer(&mut self, instances: &[Instance]) -> Result<InstanceBuffer, String> {
+        if !self.initialized {
+            return Err("PrismRenderer must be initialized before creating instance buffers".to_string());
+        }
+
+        self.begin_resource_upload();
+        let mut buffer = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut buffer);
+        }
+        self.end_resource_upload();
+
+        let resource = ResourceRef::new(&self.resources, GpuObject::Buffer(buffer), "instances");
+        let mut instance_buffer = InstanceBuffer { resource, len: 0 };
+        self.update_instance_buffer(&mut instance_buffer, instances);
+        Ok(instance_buffer)
+    }
+
+    /// Replaces the instances of `buffer`. Its storage is orphaned like `update_mesh` does,
+    /// so refilling it between draws of the same frame is fine.
+    pub fn update_instance_buffer(&mut self, buffer: &mut InstanceBuffer, instances: &[Instance]) {
+        let id = self.instance_buffer_id(buffer);
+        let data = instancing::pack(instances);
+
+        self.begin_resource_upload();
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, id);
+            gl::BufferData(gl::ARRAY_BUFFER, std::mem::size_of_val(data.as_slice()) as isize, data.as_ptr() as *const _, gl::DYNAMIC_DRAW);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        self.end_resource_upload();
+
+        buffer.len = instances.len();
+    }
+
+    fn instance_buffer_id(&self, buffer: &InstanceBuffer) -> u32 {
+        if !std::rc::Rc::ptr_eq(buffer.resource.registry(), &self.resources) {
+            panic!("Instance buffer was not created by this PrismRenderer");
+        }
+
+        match buffer.raw_id() {
+            0 => panic!("Instance buffer has already been deleted"),
+            id => id,
+        }
+    }
+
+    pub fn create_mesh_from_geometry(&mut self, geometry: Geometry, textures: Vec<Texture>) -> Mesh {
+        self.create_mesh(geometry.vertices, geometry.indices, textures)
+    }
+
+    fn mesh_buffers(&self, mesh: &Mesh) -> (u32, u32) {
+        if !std::rc::Rc::ptr_eq(mesh.resource.registry(), &self.resources) {
+            panic!("Mesh was not created by this PrismRenderer");
+        }
+
+        match mesh.resource.object() {
+            Some(GpuObject::Mesh { vbo, ebo, .. }) => (vbo, ebo),
+            _ => panic!("Mesh has already been deleted"),
+        }
+    }
+
+    /// Vertex arrays cannot be shared between contexts, so each context builds its own the
+    /// first time it draws a mesh.
+    fn mesh_vertex_array(&self, mesh: &Mesh, context: usize) -> u32 {
+        let (vbo, ebo) = self.mesh_buffers(mesh);
+
+        let mut resources = self.resources.borrow_mut();
+        let vaos = match resources.get_mut(mesh.handle()) {
+            Some(GpuObject::Mesh { vaos, .. }) => vaos,
+            _ => panic!("Mesh has already been deleted"),
+        };
+
+        if vaos[context] != 0 {
+            return vaos[context];
+        }
+
+        let stride = mesh.layout.stride() as i32;
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+
+            for attribute in mesh.layout.attributes() {
+                let (components, component_type, normalized, integer) = attribute.format.to_gl();
+                let offset = attribute.offset as *const std::ffi::c_void;
+
+                gl::EnableVertexAttribArray(attribute.location);
+                if integer {
+                    gl::VertexAttribIPointer(attribute.location, components, component_type, stride, offset);
+                }
+                else {
+                    gl::VertexAttribPointer(attribute.location, components, component_type, if normalized { gl::TRUE } else { gl::FALSE }, stride, offset);
+                }
+            }
+
+            gl::BindVertexArray(0);
+        }
+
+        vaos[context] = vao;
+        vao
+    }
+
+    pub fn create_shader_from_source(&mut self, vertex_src: &str, fragment_src: &str) -> Result<Shader, String> {
+        self.create_shader_with_defines(vertex_src, fragment_src, &ShaderDefines::new())
+    }
+
+    pub fn create_shader_with_defines(&mut self, vertex_src: &str, fragment_src: &str, defines: &ShaderDefines) -> Result<Shader, String> {
+        let CompiledProgram { program, uniforms, attributes, .. } = self.compile_sources(vertex_src, fragment_src, None, defines)?;
+        let resource = ResourceRef::new(&self.resources, GpuObject::Program(program), "shader program");
+        Ok(Shader::new(resource, false, uniforms, attributes))
+    }
+
+    /// Creates a shader from GLSL files. With hot reloading on, the files and everything they
+    /// include are watched for changes.
+    pub fn create_shader_from_files(&mut self, vertex_path: &std::path::Path, fragment_path: &std::path::Path) -> Result<Shader, String> {
+        self.create_shader_from_files_with_defines(vertex_path, fragment_path, &ShaderDefines::new())
+    }
+
+    pub fn create_shader_from_files_with_defines(&mut self, vertex_path: &std::path::Path, fragment_path: &std::path::Path, defines: &ShaderDefines) -> Result<Shader, String> {
+        let CompiledProgram { program, uniforms, attributes, files } = self.compile_files(vertex_path, fragment_path, defines)?;
+        let label = format!("{} + {}", vertex_path.to_string_lossy(), fragment_path.to_string_lossy());
+        let resource = ResourceRef::new(&self.resources, GpuObject::Program(program), &label);
+
+        self.shader_watcher.watch(resource.handle(), vertex_path, fragment_path, defines, files);
+        Ok(Shader::new(resource, false, uniforms, attributes))
+    }
+
+    /// The variant of `variants` compiled with `defines`, compiling it on first use.
+    pub fn shader_variant<'a>(&mut self, variants: &'a mut ShaderVariants, defines: &ShaderDefines) -> Result<&'a mut Shader, String> {
+        if !variants.variants.contains_key(defines) {
+            let shader = match &variants.source {
+                ShaderSource::Inline { vertex, fragment } => self.create_shader_with_defines(vertex, fragment, defines)?,
+                ShaderSource::Files { vertex, fragment } => self.create_shader_from_files_with_defines(vertex, fragment, defines)?,
+            };
+            variants.variants.insert(defines.clone(), shader);
+        }
+
+        Ok(variants.variants.get_mut(defines).unwrap())
+    }
+
+    /// Makes `#include "name"` resolve to `source` in shaders created afterwards.
+    pub fn register_shader_include(&mut self, name: &str, source: &str) {
+        self.shader_includes.insert(name.to_string(), source.to_string());
+    }
+
+    /// GLSL dialect shaders are compiled as; decides the `#version` header prepended to them.
+    pub fn glsl_profile(&self) -> GlslProfile {
+        match self.graphics_api {
+            GraphicsApi::OpenGlEs => GlslProfile::Gles30,
+            _ => GlslProfile::Gl33Core,
+        }
+    }
+
+    /// The kind of context `init` created, or `Auto` before that.
+    pub fn graphics_api(&self) -> GraphicsApi {
+        self.graphics_api
+    }
+
+    /// Preprocesses, compiles and links a program, also returning the files it was built from.
+    fn compile_sources(
+        &mut self,
+        vertex_src: &str,
+        fragment_src: &str,
+        paths: Option<(&std::path::Path, &std::path::Path)>,
+        defines: &ShaderDefines,
+    ) -> Result<CompiledProgram, String> {
+        if !self.initialized {
+            panic!("PrismRenderer must be initialized before creating shaders");
+        }
+
+        let profile = self.glsl_profile();
+        let (vertex_name, fragment_name) = match paths {
+            Some((vertex_path, fragment_path)) => (vertex_path.to_string_lossy().into_owned(), fragment_path.to_string_lossy().into_owned()),
+            None => ("vertex shader".to_string(), "fragment shader".to_string()),
+        };
+
+        let vertex = glsl::preprocess(vertex_src, &vertex_name, paths.map(|(path, _)| path), profile, defines, &self.shader_includes)?;
+        let fragment = glsl::preprocess(fragment_src, &fragment_name, paths.map(|(_, path)| path), profile, defines, &self.shader_includes)?;
+
+        self.begin_resource_upload();
+        let program = shader::build_program(&vertex, &fragment, &self.uniform_block_bindings);
+        self.end_resource_upload();
+
+        let (program, uniforms, attributes) = program?;
+        let mut files = vertex.files;
+        for file in fragment.files {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        Ok(CompiledProgram { program, uniforms, attributes, files })
+    }
+
+    fn compile_files(
+        &mut self,
+        vertex_path: &std::path::Path,
+        fragment_path: &std::path::Path,
+        defines: &ShaderDefines,
+    ) -> Result<CompiledProgram, String> {
+        let (vertex_src, fragment_src) = shader::read_sources(vertex_path, fragment_path)?;
+        self.compile_sources(&vertex_src, &fragment_src, Some((vertex_path, fragment_path)), defines)
+    }
+
+    /// Recompiles shaders created from files whenever their sources change on disk, checked
+    /// in `handle_events`. A shader that fails to compile keeps its previous program.
+    pub fn set_shader_hot_reload(&mut self, enabled: bool) {
+        self.shader_watcher.set_enabled(enabled);
+    }
+
+    pub fn shader_hot_reload(&self) -> bool {
+        self.shader_watcher.is_enabled()
+    }
+
+    fn reload_changed_shaders(&mut self) {
+        let resources = self.resources.clone();
+        let changed = self.shader_watcher.poll(|handle| resources.borrow().get(handle).is_some());
+
+        for shader in changed {
+            let label = format!("{} + {}", shader.vertex_path.to_string_lossy(), shader.fragment_path.to_string_lossy());
+
+            match self.compile_files(&shader.vertex_path, &shader.fragment_path, &shader.defines) {
+                // Shaders pick up the new program, and re-apply their uniforms, on their next
+                // uniform set; the old program is deleted with the rest of the garbage.
+                Ok(CompiledProgram { program, files, .. }) => {
+                    resources.borrow_mut().replace(shader.handle, GpuObject::Program(program));
+                    self.shader_watcher.set_files(shader.handle, files);
+                    eprintln!("Prism: reloaded shader {}", label);
+                }
+                Err(e) => eprintln!("Prism: failed to reload shader {}, keeping the previous version: {}", label, e),
+            }
+        }
+    }
+
+    /// Creates a buffer for a uniform block. Programs linked afterwards that declare a block
+    /// named `T::NAME` get it bound to `T::BINDING`.
+    pub fn create_uniform_buffer<T: UniformBlock>(&mut self) -> Result<UniformBuffer<T>, String> {
+        if !self.initialized {
+            return Err("PrismRenderer must be initialized before creating uniform buffers".to_string());
+        }
+
+        self.uniform_block_bindings.insert(T::NAME.to_string(), T::BINDING);
+
+        self.begin_resource_upload();
+        let mut buffer = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut buffer);
+        }
+        self.end_resource_upload();
+
+        let resource = ResourceRef::new(&self.resources, GpuObject::Buffer(buffer), &format!("{} uniform block", T::NAME));
+        Ok(UniformBuffer::new(resource))
+    }
+
+    /// Uploads `block` and binds the buffer to `T::BINDING` for the screen being drawn. Upload
+    /// once per frame per screen, before the draws that read it.
+    pub fn upload_uniform_block<T: UniformBlock>(&mut self, buffer: &UniformBuffer<T>, block: &T) {
+        if self.active_screen.is_none() {
+            panic!("A screen must be begun before uploading uniform blocks");
+        }
+
+        let data = uniform_block::to_std140(block);
+        unsafe {
+            gl::BindBuffer(gl::UNIFORM_BUFFER, buffer.raw_id());
+            // Respecifying the whole store lets the driver hand out fresh memory instead of
+            // waiting for draws still reading the previous frame's data.
+            gl::BufferData(gl::UNIFORM_BUFFER, data.len() as isize, data.as_ptr() as *const _, gl::DYNAMIC_DRAW);
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, T::BINDING, buffer.raw_id());
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        }
+    }
+
+    /// Sets the built-in `Camera` block for the screen being drawn.
+    pub fn set_camera_block(&mut self, block: &CameraBlock) {
+        let screen = self.active_screen.expect("A screen must be begun before setting the camera block");
+        let uniforms = self.screen_uniforms.remove(&screen).unwrap();
+        self.upload_uniform_block(&uniforms.camera, block);
+        self.screen_uniforms.insert(screen, uniforms);
+    }
+
+    /// Sets the built-in `Lights` block for the screen being drawn. Only the light the
+    /// screen's shadow pass was rendered from keeps `casts_shadows`.
+    pub fn set_lights_block(&mut self, block: &LightsBlock) {
+        let screen = self.active_screen.expect("A screen must be begun before setting the lights block");
+        let block = block.with_shadow_caster(self.shadow_light.as_ref());
+        let uniforms = self.screen_uniforms.remove(&screen).unwrap();
+        self.upload_uniform_block(&uniforms.lights, &block);
+        self.screen_uniforms.insert(screen, uniforms);
+    }
+
+    fn set_shadow_block(&mut self, block: &ShadowBlock) {
+        let screen = self.active_screen.expect("A screen must be begun before setting the shadow block");
+        let uniforms = self.screen_uniforms.remove(&screen).unwrap();
+        self.upload_uniform_block(&uniforms.shadow, block);
+        self.screen_uniforms.insert(screen, uniforms);
+    }
+
+    /// Starts rendering the shadow map of the screen being drawn, as seen from `light`. The
+    /// map covers the sphere at `center` with `radius`; keep it tight around the casters for
+    /// crisp shadows. Draw the casters with `draw_shadow_caster`, then call `end_shadow_pass`
+    /// before drawing the screen itself. Mark `light` with `casts_shadows` in the lights block
+    /// so the built-in shaders sample the map for it.
+    pub fn begin_shadow_pass(&mut self, light: &Light, center: Vec3, radius: f32) -> Result<(), String> {
+        let screen = match self.active_screen {
+            Some(screen) => screen,
+            None => panic!("A screen must be begun before rendering shadows"),
+        };
+
+        // The pass ends by going back to the screen, not to a render target.
+        if self.shadow_pass.is_some() || self.active_render_target.is_some() {
+            panic!("A shadow pass cannot be nested, or begun while drawing into a render target");
+        }
+
+        let light_space = shadow::light_space_matrix(light, center, radius)
+            .ok_or_else(|| format!("A {:?} light cannot cast shadows", light.kind))?;
+
+        // The config cannot change after init, so a map once created keeps its resolution.
+        let shadows = self.config.shadows;
+        if let Entry::Vacant(entry) = self.shadow_maps.entry(screen) {
+            entry.insert(ShadowMap::create(shadows.resolution)?);
+        }
+
+        let block = ShadowBlock {
+            light_space,
+            depth_bias: shadows.depth_bias,
+            slope_bias: shadows.slope_bias,
+            pcf_radius: shadows.pcf_radius,
+            enabled: true,
+        };
+        self.set_shadow_block(&block);
+        self.shadow_pass = Some(block);
+        self.shadow_light = Some(*light);
+
+        self.shadow_maps[&screen].bind();
+        self.apply_render_state(screen, &RenderState::default(), false);
+        unsafe {
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+
+        Ok(())
+    }
+
+    /// Draws `mesh` into the shadow map, so that it shadows the meshes drawn after the pass.
+    pub fn draw_shadow_caster(&mut self, mesh: &Mesh) {
+        self.draw_shadow_caster_with(mesh, None);
+    }
+
+    /// Draws every instance of `mesh` in `instances` into the shadow map.
+    pub fn draw_shadow_caster_instanced(&mut self, mesh: &Mesh, instances: &InstanceBuffer) {
+        self.draw_shadow_caster_with(mesh, Some(instances));
+    }
+
+    fn draw_shadow_caster_with(&mut self, mesh: &Mesh, instances: Option<&InstanceBuffer>) {
+        let screen = match (self.active_screen, self.shadow_pass) {
+            (Some(screen), Some(_)) => screen,
+            _ => panic!("begin_shadow_pass must be called before drawing shadow casters"),
+        };
+        if instances.is_some_and(|instances| instances.is_empty()) {
+            return;
+        }
+        let vao = self.mesh_vertex_array(mesh, self.context_index(screen));
+        let instances = instances.map(|instances| (self.instance_buffer_id(instances), instances.len()));
+
+        let shader = self.shadow_shader.as_mut().unwrap();
+        unsafe {
+            gl::UseProgram(shader.program());
+        }
+        shader.set_uniform_mat4("model", if instances.is_some() { Mat4::IDENTITY } else { mesh.model });
+
+        unsafe {
+            gl::BindVertexArray(vao);
+            draw_elements(mesh.index_count, instances);
+            gl::BindVertexArray(0);
+        }
+    }
+
+    /// Finishes the shadow map and goes back to drawing the screen.
+    pub fn end_shadow_pass(&mut self) {
+        let screen = match (self.active_screen, self.shadow_pass) {
+            (Some(screen), Some(_)) => screen,
+            _ => panic!("No shadow pass is being rendered"),
+        };
+
+        self.bind_screen_target(screen);
+        self.shadow_pass = None;
+    }
+
+    /// Creates an offscreen target to draw into, whose color can then be used as a texture.
+    pub fn create_render_target(&mut self, descriptor: &RenderTargetDescriptor) -> Result<RenderTarget, String> {
+        if !self.initialized {
+            return Err("PrismRenderer must be initialized before creating render targets".to_string());
+        }
+
+        if descriptor.width == 0 || descriptor.height == 0 {
+            return Err("Render target has no pixels".to_string());
+        }
+
+        self.begin_resource_upload();
+        let target = match self.create_render_target_objects(descriptor, descriptor.format) {
+            // GLES 3.0 can only render to half floats with EXT_color_buffer_float.
+            Err(e) if descriptor.format == ColorFormat::Rgba16F => {
+                eprintln!("Prism: {}, falling back to an Rgba8 render target", e);
+                self.create_render_target_objects(descriptor, ColorFormat::Rgba8)
+            }
+            result => result,
+        };
+        self.end_resource_upload();
+
+        target
+    }
+
+    /// Creates the attachments and the upload context's framebuffers. That context must be current.
+    fn create_render_target_objects(&mut self, descriptor: &RenderTargetDescriptor, format: ColorFormat) -> Result<RenderTarget, String> {
+        let (width, height) = (descriptor.width as i32, descriptor.height as i32);
+        let (internal_format, pixel_format, pixel_type) = format.to_gl();
+        let filter = match descriptor.filter {
+            FilterMode::Nearest => gl::NEAREST,
+            FilterMode::Linear => gl::LINEAR,
+        } as i32;
+
+        let mut texture = 0;
+        let mut depth = 0;
+        let mut msaa_color = 0;
+        let mut samples = descriptor.samples.max(1);
+
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as i32, width, height, 0, pixel_format, pixel_type, std::ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            if samples > 1 {
+                let mut max_samples = 0;
+                gl::GetIntegerv(gl::MAX_SAMPLES, &mut max_samples);
+                samples = samples.min(max_samples.max(1) as u32);
+            }
+
+            if samples > 1 {
+                gl::GenRenderbuffers(1, &mut msaa_color);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, msaa_color);
+                gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples as i32, internal_format, width, height);
+            }
+
+            if descriptor.depth {
+                gl::GenRenderbuffers(1, &mut depth);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, depth);
+                if samples > 1 {
+                    gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples as i32, gl::DEPTH24_STENCIL8, width, height);
+                }
+                else {
+                    gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, width, height);
+                }
+            }
+
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+        }
+
+        let mut object = GpuObject::RenderTarget { depth, msaa_color, fbos: [0; resource::MAX_CONTEXTS], resolve_fbos: [0; resource::MAX_CONTEXTS] };
+
+        match render_target::create_framebuffers(&object, texture) {
+            Ok((fbo, resolve_fbo)) => {
+                if let GpuObject::RenderTarget { fbos, resolve_fbos, .. } = &mut object {
+                    fbos[self.context_index(Screen::Upper)] = fbo;
+                    resolve_fbos[self.context_index(Screen::Upper)] = resolve_fbo;
+                }
+            }
+            Err(e) => {
+                render_target::delete_renderbuffers(&object);
+                unsafe {
+                    gl::DeleteTextures(1, &texture);
+                }
+                return Err(e);
+            }
+        }
+
+        let label = match samples {
+            1 => format!("{}x{} {:?}", width, height, format),
+            _ => format!("{}x{} {:?} {}x MSAA", width, height, format, samples),
+        };
+
+        let color = Texture {
+            resource: ResourceRef::new(&self.resources, GpuObject::Texture(texture), &format!("{} render target color", label)),
+            width: descriptor.width,
+            height: descriptor.height,
+        };
+
+        Ok(RenderTarget {
+            resource: ResourceRef::new(&self.resources, object, &label),
+            color,
+            format,
+            samples,
+        })
+    }
+
+    /// Framebuffers cannot be shared between contexts, so each context builds its own the
+    /// first time it draws into a target. Returns the draw and resolve framebuffers.
+    fn render_target_framebuffers(&self, target: &RenderTarget, context: usize) -> (u32, u32) {
+        if !std::rc::Rc::ptr_eq(target.resource.registry(), &self.resources) {
+            panic!("Render target was not created by this PrismRenderer");
+        }
+
+        let color = target.color.raw_id();
+        let mut resources = self.resources.borrow_mut();
+        let object = match resources.get_mut(target.handle()) {
+            Some(object @ GpuObject::RenderTarget { .. }) => object,
+            _ => panic!("Render target has already been deleted"),
+        };
+
+        if let GpuObject::RenderTarget { fbos, resolve_fbos, .. } = object
+            && fbos[context] != 0
+        {
+            return (fbos[context], resolve_fbos[context]);
+        }
+
+        // Already complete on the context it was created with, so it is complete here too.
+        let (fbo, resolve_fbo) = match render_target::create_framebuffers(object, color) {
+            Ok(framebuffers) => framebuffers,
+            Err(e) => panic!("{}", e),
+        };
+
+        if let GpuObject::RenderTarget { fbos, resolve_fbos, .. } = object {
+            fbos[context] = fbo;
+            resolve_fbos[context] = resolve_fbo;
+        }
+
+        (fbo, resolve_fbo)
+    }
+
+    fn bind_render_target(&self, target: &RenderTarget, screen: Screen) {
+        let (fbo, _) = self.render_target_framebuffers(target, self.context_index(screen));
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::Viewport(0, 0, target.width() as i32, target.height() as i32);
+        }
+    }
+
+    /// Resolves a multisampled target into its color texture.
+    fn resolve_render_target(&mut self, target: &RenderTarget, screen: Screen) {
+        if !target.is_multisampled() {
+            return;
+        }
+
+        // Blits are clipped by the scissor test too.
+        self.state_caches[self.context_index(screen)].set_scissor(None);
+
+        let (fbo, resolve_fbo) = self.render_target_framebuffers(target, self.context_index(screen));
+        let (width, height) = (target.width() as i32, target.height() as i32);
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, fbo);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, resolve_fbo);
+            gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /// Redirects drawing on the current screen into `target` until `end_render_target`.
+    /// Set the camera block for the view the target shows, and set it back for the screen
+    /// afterwards. A material must not sample the target it is being drawn into.
+    pub fn begin_render_target(&mut self, target: &RenderTarget) {
+        let screen = match self.active_screen {
+            Some(screen) => screen,
+            None => panic!("A screen must be begun before drawing into a render target"),
+        };
 
-        Ok(Shader { id: shader })
+        if self.active_render_target.is_some() || self.shadow_pass.is_some() {
+            panic!("Render targets cannot be nested, or begun during a shadow pass");
+        }
+
+        self.bind_render_target(target, screen);
+        self.active_render_target = Some(target.clone());
+    }
+
+    /// Finishes the render target, resolving it if multisampled, and goes back to drawing the screen.
+    pub fn end_render_target(&mut self) {
+        let (screen, target) = match (self.active_screen, self.active_render_target.take()) {
+            (Some(screen), Some(target)) => (screen, target),
+            _ => panic!("No render target is being drawn into"),
+        };
+
+        self.resolve_render_target(&target, screen);
+        self.bind_screen_target(screen);
+    }
+
+    /// Sets the effects `screen` is post-processed with once drawn. An empty stack turns
+    /// post-processing off. Must not be called while the screen is being drawn.
+    pub fn set_post_processing(&mut self, screen: Screen, stack: PostProcessStack) -> Result<(), String> {
+        if !self.initialized {
+            return Err("PrismRenderer must be initialized before setting up post-processing".to_string());
+        }
+
+        if self.active_screen == Some(screen) {
+            return Err(format!("Post-processing of the {:?} screen cannot change while it is being drawn", screen));
+        }
+
+        if stack.is_empty() {
+            self.screen_post.remove(&screen);
+            return Ok(());
+        }
+
+        if self.post_programs.is_none() {
+            let mut shaders = Vec::with_capacity(post::EFFECT_FRAGMENTS.len());
+            for fragment in post::EFFECT_FRAGMENTS {
+                shaders.push(self.create_shader_from_source(post::FULLSCREEN_VERTEX, fragment)?);
+            }
+            self.post_programs = Some(PostPrograms::new(shaders));
+        }
+
+        let (width, height) = self.config.screen_size(screen);
+        let scene_descriptor = RenderTargetDescriptor {
+            format: ColorFormat::Rgba16F,
+            samples: stack.samples,
+            ..RenderTargetDescriptor::new(width, height)
+        };
+        let pass_descriptor = RenderTargetDescriptor { depth: false, samples: 1, ..scene_descriptor.clone() };
+
+        let scene = self.create_render_target(&scene_descriptor)?;
+        let ping_pong = [self.create_render_target(&pass_descriptor)?, self.create_render_target(&pass_descriptor)?];
+
+        let bloom = if stack.effects.iter().any(|effect| matches!(effect, PostEffect::Bloom { .. })) {
+            let half = RenderTargetDescriptor { width: (width / 2).max(1), height: (height / 2).max(1), ..pass_descriptor };
+            Some([self.create_render_target(&half)?, self.create_render_target(&half)?])
+        }
+        else {
+            None
+        };
+
+        self.screen_post.insert(screen, ScreenPost { stack, scene, ping_pong, bloom });
+        Ok(())
+    }
+
+    pub fn post_processing(&self, screen: Screen) -> Option<&PostProcessStack> {
+        self.screen_post.get(&screen).map(|post| &post.stack)
+    }
+
+    fn fullscreen_vertex_array(&mut self, context: usize) -> u32 {
+        if self.fullscreen_vaos[context] == 0 {
+            unsafe {
+                gl::GenVertexArrays(1, &mut self.fullscreen_vaos[context]);
+            }
+        }
+        self.fullscreen_vaos[context]
+    }
+
+    /// Runs `screen`'s effects on what was drawn into its scene target, the last one
+    /// writing to the screen's offscreen target.
+    fn apply_post_processing(&mut self, screen: Screen) {
+        let post = match self.screen_post.remove(&screen) {
+            Some(post) => post,
+            None => return,
+        };
+        let mut programs = self.post_programs.take().expect("Post-processing programs are compiled with the first stack");
+
+        self.resolve_render_target(&post.scene, screen);
+
+        self.apply_render_state(screen, &RenderState { depth_test: false, depth_write: false, ..RenderState::default() }, false);
+        let vao = self.fullscreen_vertex_array(self.context_index(screen));
+        unsafe {
+            gl::BindVertexArray(vao);
+        }
+
+        let mut source = post.scene.color.clone();
+        let last = post.stack.effects.len() - 1;
+
+        for (i, effect) in post.stack.effects.iter().enumerate() {
+            let output = if i == last { None } else { Some(&post.ping_pong[i % 2]) };
+
+            match effect {
+                PostEffect::ToneMapping { operator, exposure } => {
+                    let shader = &mut programs.tone_map;
+                    shader.set_uniform_int("toneMapOperator", operator.id());
+                    shader.set_uniform_float("exposure", *exposure);
+                    self.draw_fullscreen(shader, &[("source", &source)], output, screen);
+                }
+                PostEffect::Bloom { threshold, intensity, passes } => {
+                    let bloom = post.bloom.as_ref().expect("Bloom targets are created with the stack");
+                    let texel = Vec2::new(1.0 / bloom[0].width() as f32, 1.0 / bloom[0].height() as f32);
+
+                    programs.bright_pass.set_uniform_float("threshold", *threshold);
+                    self.draw_fullscreen(&mut programs.bright_pass, &[("source", &source)], Some(&bloom[0]), screen);
+
+                    for _ in 0..(*passes).max(1) {
+                        programs.blur.set_uniform_vec2("direction", Vec2::new(texel.x, 0.0));
+                        self.draw_fullscreen(&mut programs.blur, &[("source", bloom[0].color_texture())], Some(&bloom[1]), screen);
+                        programs.blur.set_uniform_vec2("direction", Vec2::new(0.0, texel.y));
+                        self.draw_fullscreen(&mut programs.blur, &[("source", bloom[1].color_texture())], Some(&bloom[0]), screen);
+                    }
+
+                    programs.bloom_combine.set_uniform_float("intensity", *intensity);
+                    self.draw_fullscreen(&mut programs.bloom_combine, &[("source", &source), ("bloom", bloom[0].color_texture())], output, screen);
+                }
+                PostEffect::ColorGrading { lut, strength } => {
+                    let shader = &mut programs.color_grading;
+                    shader.set_uniform_float("lutSize", lut.height() as f32);
+                    shader.set_uniform_float("strength", *strength);
+                    self.draw_fullscreen(shader, &[("source", &source), ("lut", lut)], output, screen);
+                }
+                PostEffect::Fxaa => {
+                    let shader = &mut programs.fxaa;
+                    shader.set_uniform_vec2("texelSize", Vec2::new(1.0 / source.width() as f32, 1.0 / source.height() as f32));
+                    self.draw_fullscreen(shader, &[("source", &source)], output, screen);
+                }
+            }
+
+            if let Some(output) = output {
+                source = output.color.clone();
+            }
+        }
+
+        unsafe {
+            gl::BindVertexArray(0);
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+
+        self.post_programs = Some(programs);
+        self.screen_post.insert(screen, post);
+    }
+
+    /// Draws a full-screen triangle with `shader` into `output`, or the screen's offscreen
+    /// target if `None`. The full-screen vertex array must be bound.
+    fn draw_fullscreen(&self, shader: &mut Shader, textures: &[(&str, &Texture)], output: Option<&RenderTarget>, screen: Screen) {
+        match output {
+            Some(target) => self.bind_render_target(target, screen),
+            None => {
+                if let Some(target) = self.screen_target(screen) {
+                    target.bind();
+                }
+            }
+        }
+
+        unsafe {
+            gl::UseProgram(shader.program());
+            for (unit, (sampler, texture)) in textures.iter().enumerate() {
+                gl::ActiveTexture(gl::TEXTURE0 + unit as u32);
+                gl::BindTexture(gl::TEXTURE_2D, texture.raw_id());
+                shader.set_uniform_sampler(sampler, unit as u32);
+            }
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+    }
+
+    fn create_sprite_resources(&mut self) -> Result<SpriteResources, String> {
+        let shader = self.create_shader_from_source(sprite::SPRITE_VERTEX, sprite::SPRITE_FRAGMENT)?;
+        let white = self.white_texture()?;
+
+        self.begin_resource_upload();
+        let mut buffers = [0; 2];
+        unsafe {
+            gl::GenBuffers(2, buffers.as_mut_ptr());
+        }
+        self.end_resource_upload();
+
+        Ok(SpriteResources {
+            shader,
+            white,
+            vertices: ResourceRef::new(&self.resources, GpuObject::Buffer(buffers[0]), "sprite vertices"),
+            indices: ResourceRef::new(&self.resources, GpuObject::Buffer(buffers[1]), "sprite indices"),
+        })
+    }
+
+    fn sprite_vertex_array(&mut self, sprites: &SpriteResources, context: usize) -> u32 {
+        if self.sprite_vaos[context] != 0 {
+            return self.sprite_vaos[context];
+        }
+
+        let (vbo, ebo) = match (sprites.vertices.object(), sprites.indices.object()) {
+            (Some(GpuObject::Buffer(vbo)), Some(GpuObject::Buffer(ebo))) => (vbo, ebo),
+            _ => panic!("Sprite buffers have already been deleted"),
+        };
+
+        let stride = (sprite::SPRITE_VERTEX_FLOATS * std::mem::size_of::<f32>()) as i32;
+        let offset = |floats: usize| (floats * std::mem::size_of::<f32>()) as *const std::ffi::c_void;
+
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, offset(0));
+            gl::EnableVertexAttribArray(1);
+            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, offset(2));
+            gl::EnableVertexAttribArray(2);
+            gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, stride, offset(4));
+
+            gl::BindVertexArray(0);
+        }
+
+        self.sprite_vaos[context] = vao;
+        vao
+    }
+
+    /// Draws the quads of `batch` over what is already on the current screen or render
+    /// target, alpha blended and without depth testing. Returns the number of draw calls.
+    pub fn draw_sprite_batch(&mut self, batch: &SpriteBatch) -> usize {
+        let screen = match self.active_screen {
+            Some(screen) => screen,
+            None => panic!("A screen must be begun before drawing sprites"),
+        };
+
+        if self.shadow_pass.is_some() {
+            panic!("Sprites cannot be drawn during a shadow pass");
+        }
+
+        if batch.is_empty() {
+            return 0;
+        }
+
+        let mut sprites = match self.sprites.take() {
+            Some(sprites) => sprites,
+            None => self.create_sprite_resources().unwrap_or_else(|e| panic!("Failed to set up sprite rendering: {}", e)),
+        };
+
+        let (width, height) = match &self.active_render_target {
+            Some(target) => (target.width(), target.height()),
+            None => self.config.screen_size(screen),
+        };
+        let projection = batch.projection().unwrap_or_else(|| Mat4::orthographic_rh_gl(0.0, width as f32, height as f32, 0.0, -1.0, 1.0));
+
+        let (vertices, indices, runs) = batch.build();
+        let vao = self.sprite_vertex_array(&sprites, self.context_index(screen));
+        let vbo = match sprites.vertices.object() {
+            Some(GpuObject::Buffer(vbo)) => vbo,
+            _ => 0,
+        };
+
+        let state = RenderState { depth_test: false, ..RenderState::blended(BlendMode::Alpha) };
+        self.apply_render_state(screen, &state, true);
+
+        unsafe {
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * std::mem::size_of::<f32>()) as isize, vertices.as_ptr() as *const _, gl::STREAM_DRAW);
+            // The element buffer binding belongs to the bound vertex array.
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * std::mem::size_of::<u32>()) as isize, indices.as_ptr() as *const _, gl::STREAM_DRAW);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            gl::UseProgram(sprites.shader.program());
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+
+        sprites.shader.set_uniform_mat4("projection", projection);
+        sprites.shader.set_uniform_sampler("texture_0", 0);
+
+        unsafe {
+            for run in &runs {
+                let texture = run.texture.as_ref().unwrap_or(&sprites.white);
+                gl::BindTexture(gl::TEXTURE_2D, texture.raw_id());
+                gl::DrawElements(
+                    gl::TRIANGLES,
+                    run.index_count as i32,
+                    gl::UNSIGNED_INT,
+                    (run.first_index * std::mem::size_of::<u32>()) as *const std::ffi::c_void,
+                );
+            }
+
+            gl::BindVertexArray(0);
+        }
+
+        self.sprites = Some(sprites);
+        runs.len()
+    }
+
+    pub fn draw_mesh(&mut self, mesh: &Mesh, material: &mut Material) {
+        self.draw_mesh_with(mesh, material, None);
+    }
+
+    /// Draws `mesh` once per instance in `instances` with a single draw call, each placed by
+    /// its instance's transform instead of the mesh's model matrix. Every instance shares the
+    /// lights block set for the draw.
+    pub fn draw_mesh_instanced(&mut self, mesh: &Mesh, material: &mut Material, instances: &InstanceBuffer) {
+        self.draw_mesh_with(mesh, material, Some(instances));
     }
 
-    pub fn draw_mesh(&mut self, mesh: &Mesh, shader: &mut Shader) {
+    fn draw_mesh_with(&mut self, mesh: &Mesh, material: &mut Material, instances: Option<&InstanceBuffer>) {
         if !self.initialized {
             panic!("PrismRenderer must be initialized before drawing meshes");
         }
 
+        if self.shadow_pass.is_some() {
+            panic!("Meshes cannot be drawn during a shadow pass; use draw_shadow_caster");
+        }
+
+        let screen = match self.active_screen {
+            Some(screen) => screen,
+            None => panic!("A screen must be begun before drawing meshes"),
+        };
+        if instances.is_some_and(|instances| instances.is_empty()) {
+            return;
+        }
+        let vao = self.mesh_vertex_array(mesh, self.context_index(screen));
+        let instances = instances.map(|instances| (self.instance_buffer_id(instances), instances.len()));
+
+        self.apply_render_state(screen, &material.state, true);
+
         unsafe {
-            gl::UseProgram(shader.id);
+            gl::UseProgram(material.shader.program());
 
-            for (i, texture) in mesh.textures.iter().enumerate() {
-                gl::ActiveTexture(gl::TEXTURE0 + i as u32);
-                gl::Uniform1i(gl::GetUniformLocation(shader.id, format!("texture_{}", i).as_ptr() as *const i8), i as i32);
-                gl::BindTexture(gl::TEXTURE_2D, texture.id);
+            let mesh_textures = mesh.textures.iter().enumerate().map(|(i, texture)| (format!("texture_{}", i), texture));
+            let material_textures = material.textures.iter().map(|(sampler, texture)| (sampler.clone(), texture));
+            let textures: Vec<(String, &Texture)> = mesh_textures.chain(material_textures).collect();
+
+            for (unit, (sampler, texture)) in textures.iter().enumerate() {
+                gl::ActiveTexture(gl::TEXTURE0 + unit as u32);
+                gl::BindTexture(gl::TEXTURE_2D, texture.raw_id());
+                material.shader.set_uniform_sampler(sampler, unit as u32);
             }
 
-            shader.set_uniform_mat4("model", mesh.model);
+            // The shadow map takes the unit after the material's textures.
+            if let Some(shadow_map) = self.shadow_maps.get(&screen)
+                && material.shader.uniform("shadowMap").is_some()
+            {
+                let unit = textures.len() as u32;
+                gl::ActiveTexture(gl::TEXTURE0 + unit);
+                gl::BindTexture(gl::TEXTURE_2D, shadow_map.depth);
+                material.shader.set_uniform_sampler("shadowMap", unit);
+            }
+        }
 
-            gl::BindVertexArray(mesh.vao);
-            gl::DrawElements(
-                gl::TRIANGLES,
-                mesh.indices.len() as i32,
-                gl::UNSIGNED_INT,
-                std::ptr::null(),
-            );
+        material.shader.set_uniform_bool("receiveShadows", material.state.receive_shadows);
+        material.apply_params();
+        material.shader.set_uniform_mat4("model", if instances.is_some() { Mat4::IDENTITY } else { mesh.model });
+
+        unsafe {
+            gl::BindVertexArray(vao);
+            draw_elements(mesh.index_count, instances);
             gl::BindVertexArray(0);
             gl::ActiveTexture(gl::TEXTURE0);
         }
@@ -510,12 +2195,21 @@ impl PrismRenderer {
             panic!("PrismRenderer must be initialized before getting delta time");
         }
 
-        if self.current_window == 0 {
-            self.upper_delta_time
-        }
-        else {
-            self.lower_delta_time
-        }
+        self.frame_clock.delta()
+    }
+
+    pub fn get_fps(&self) -> f32 {
+        self.frame_clock.fps()
+    }
+
+    pub fn get_frame_count(&self) -> u64 {
+        self.frame_clock.frame_count()
+    }
+
+    /// Sleeps in `handle_events` so frames start no faster than `frame_rate`. `None` disables pacing.
+    pub fn set_target_frame_rate(&mut self, frame_rate: Option<f32>) {
+        self.config.target_frame_rate = frame_rate;
+        self.frame_clock.set_target_frame_rate(frame_rate);
     }
     
     pub fn key_pressed(&self, key: glfw::Key) -> bool {
@@ -536,19 +2230,116 @@ impl PrismRenderer {
             None => panic!("Upper window is not initialized"),
         }
 
-        match self.lower_window {
-            Some(ref window) => {
-                if let Some(win) = &window.window {
-                    if win.get_key(key) == glfw::Action::Press {
-                        res = glfw::Action::Press;
-                    }
-                }
-            }
-            None => panic!("Lower window is not initialized"),
+        // Composited layouts have no lower window.
+        if let Some(ref window) = self.lower_window
+            && let Some(win) = &window.window
+            && win.get_key(key) == glfw::Action::Press
+        {
+            res = glfw::Action::Press;
         }
 
         res == glfw::Action::Press
     }
+
+    pub fn mouse_button_pressed(&self, button: glfw::MouseButton) -> bool {
+        if !self.initialized {
+            panic!("PrismRenderer must be initialized before checking mouse button states");
+        }
+
+        [&self.upper_window, &self.lower_window]
+            .into_iter()
+            .flatten()
+            .filter_map(|window| window.window.as_ref())
+            .any(|window| window.get_mouse_button(button) == glfw::Action::Press)
+    }
+
+    /// Cursor position in pixels of `screen`, or `None` if the cursor is not over that screen.
+    pub fn cursor_position(&self, screen: Screen) -> Option<Vec2> {
+        if !self.initialized {
+            panic!("PrismRenderer must be initialized before reading the cursor position");
+        }
+
+        let window = self.screen_window(screen).window.as_ref()?;
+        let (x, y) = window.get_cursor_pos();
+        let (width, height) = window.get_size();
+        let (fb_width, fb_height) = window.get_framebuffer_size();
+
+        // Cursor positions are in window coordinates, which differ from framebuffer pixels on HiDPI displays.
+        let point = Vec2::new(x as f32, y as f32) * Vec2::new(fb_width as f32 / width as f32, fb_height as f32 / height as f32);
+        let (content, _, _) = self.window_content(screen);
+
+        self.config.layout.map_point(
+            screen,
+            point,
+            content,
+            self.config.screen_size(Screen::Upper),
+            self.config.screen_size(Screen::Lower),
+        )
+    }
+}
+
+impl RenderBackend for PrismRenderer {
+    fn create_mesh(&mut self, vertices: Vec<Vertex>, indices: Vec<u32>, textures: Vec<Texture>) -> Mesh {
+        PrismRenderer::create_mesh(self, vertices, indices, textures)
+    }
+
+    fn create_texture_from_image(&mut self, image: RgbaImage, descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        PrismRenderer::create_texture_from_image(self, image, descriptor)
+    }
+
+    fn create_texture_with_descriptor(&mut self, path: &std::path::Path, descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        PrismRenderer::create_texture_with_descriptor(self, path, descriptor)
+    }
+
+    fn create_texture_from_memory(&mut self, bytes: &[u8], descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        PrismRenderer::create_texture_from_memory(self, bytes, descriptor)
+    }
+
+    fn create_texture_from_rgba(&mut self, width: u32, height: u32, pixels: &[u8], descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        PrismRenderer::create_texture_from_rgba(self, width, height, pixels, descriptor)
+    }
+
+    fn create_shader(&mut self, vertex_src: &str, fragment_src: &str) -> Result<Shader, String> {
+        self.create_shader_from_source(vertex_src, fragment_src)
+    }
+
+    fn create_shader_from_files(&mut self, vertex_path: &std::path::Path, fragment_path: &std::path::Path) -> Result<Shader, String> {
+        PrismRenderer::create_shader_from_files(self, vertex_path, fragment_path)
+    }
+
+    fn draw_mesh(&mut self, mesh: &Mesh, material: &mut Material) {
+        PrismRenderer::draw_mesh(self, mesh, material)
+    }
+
+    fn set_camera_block(&mut self, block: &CameraBlock) {
+        PrismRenderer::set_camera_block(self, block)
+    }
+
+    fn set_lights_block(&mut self, block: &LightsBlock) {
+        PrismRenderer::set_lights_block(self, block)
+    }
+
+    fn clear_screen(&mut self, color: glam::Vec4) {
+        PrismRenderer::clear_screen(self, color)
+    }
+
+    fn begin_screen(&mut self, screen: Screen) {
+        match screen {
+            Screen::Upper => self.begin_upper_screen(),
+            Screen::Lower => self.begin_lower_screen(),
+        }
+    }
+
+    fn end_screen(&mut self, screen: Screen) {
+        match screen {
+            Screen::Upper => self.end_upper_screen(),
+            Screen::Lower => self.end_lower_screen(),
+        }
+    }
+
+    fn capture_screen(&mut self, screen: Screen) -> RgbaImage {
+        PrismRenderer::capture_screen(self, screen)
+    }
 }
 
 impl Drop for PrismRenderer {
@@ -557,6 +2348,33 @@ impl Drop for PrismRenderer {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn origin(&self) -> Vec2 {
+        Vec2::new(self.x, self.y)
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.width, self.height)
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.x >= self.x && point.y >= self.y && point.x < self.x + self.width && point.y < self.y + self.height
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vertex {
     pub position: glam::Vec3,
     pub normal: glam::Vec3,
@@ -598,43 +2416,6 @@ impl Into<[f32; 8]> for Vertex {
     }
 }
 
-pub struct Texture {
-    pub id: u32
-}
-
-pub struct Shader {
-    pub id: u32,
-}
-
-impl Shader {
-    pub fn set_uniform_mat4(&mut self, name: &str, value: Mat4) {
-        unsafe {
-            gl::UseProgram(self.id);
-            let name_c_str = CString::from_str(name).unwrap();
-            let location = gl::GetUniformLocation(self.id, name_c_str.as_ptr());
-            gl::UniformMatrix4fv(location, 1, gl::FALSE, value.as_ref().as_ptr())
-        }
-    }
-
-    pub fn set_uniform_vec3(&mut self, name: &str, value: Vec3) {
-        unsafe {
-            gl::UseProgram(self.id);
-            let name_c_str = CString::from_str(name).unwrap();
-            let location = gl::GetUniformLocation(self.id, name_c_str.as_ptr());
-            gl::Uniform3f(location, value.x, value.y, value.z);
-        }
-    }
-
-    pub fn set_uniform_float(&mut self, name: &str, value: f32) {
-        unsafe {
-            gl::UseProgram(self.id);
-            let name_c_str = CString::from_str(name).unwrap();
-            let location = gl::GetUniformLocation(self.id, name_c_str.as_ptr());
-            gl::Uniform1f(location, value);
-        }
-    }
-}
-
 #[derive(Clone, Copy)]
 pub struct Camera {
     position: glam::Vec3,
@@ -681,10 +2462,20 @@ impl Camera {
         Mat4::look_at_rh(self.position, self.position + self.front, self.up)
     }
 
+    /// `aspect_ratio` should come from `PrismRenderer::aspect_ratio` for the screen being drawn.
+    pub fn get_projection_matrix(&self, aspect_ratio: f32) -> Mat4 {
+        Mat4::perspective_rh(self.zoom.to_radians(), aspect_ratio, 0.1, 100.0)
+    }
+
     pub fn get_position(&self) -> Vec3 {
         self.position
     }
 
+    /// What the camera sees, for culling meshes with `Frustum::intersects_aabb`.
+    pub fn get_frustum(&self, aspect_ratio: f32) -> Frustum {
+        Frustum::from_matrix(self.get_projection_matrix(aspect_ratio) * self.get_view_matrix())
+    }
+
     pub fn adjust_x(&mut self, amount: f32) {
         self.position.x += amount;
         self.update_camera_vectors();
@@ -704,4 +2495,64 @@ impl Camera {
         self.position += vector;
         self.update_camera_vectors();
     }
-}
\ No newline at end of file
+}
+
+/// Gives a mesh's buffers new storage holding `vertices` and, when given, `indices`.
+unsafe fn upload_mesh_buffers(vbo: u32, ebo: u32, vertices: &[u8], indices: Option<&[u32]>, usage: BufferUsage) {
+    unsafe {
+        // The element array binding belongs to whichever vertex array is bound.
+        gl::BindVertexArray(0);
+
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        gl::BufferData(gl::ARRAY_BUFFER, vertices.len() as isize, vertices.as_ptr() as *const _, usage.to_gl());
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+        if let Some(indices) = indices {
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+            gl::BufferData(
+                gl::ELEMENT_ARRAY_BUFFER,
+                std::mem::size_of_val(indices) as isize,
+                indices.as_ptr() as *const _,
+                usage.to_gl(),
+            );
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+        }
+    }
+}
+
+/// Draws the bound vertex array, once or once per instance in the given instance buffer.
+/// Without instances the per-instance attributes get the values of one untransformed, white
+/// instance; they are reset on every draw as instanced draws leave them undefined.
+unsafe fn draw_elements(index_count: usize, instances: Option<(u32, usize)>) {
+    unsafe {
+        match instances {
+            Some((buffer, count)) => {
+                let stride = (INSTANCE_FLOATS * std::mem::size_of::<f32>()) as i32;
+                gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+                for (column, location) in (INSTANCE_TRANSFORM..=INSTANCE_COLOR).enumerate() {
+                    let offset = (column * 4 * std::mem::size_of::<f32>()) as *const std::ffi::c_void;
+                    gl::EnableVertexAttribArray(location);
+                    gl::VertexAttribPointer(location, 4, gl::FLOAT, gl::FALSE, stride, offset);
+                    gl::VertexAttribDivisor(location, 1);
+                }
+                gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+                gl::DrawElementsInstanced(gl::TRIANGLES, index_count as i32, gl::UNSIGNED_INT, std::ptr::null(), count as i32);
+
+                // The arrays live in the mesh's vertex array, which plain draws use too.
+                for location in INSTANCE_TRANSFORM..=INSTANCE_COLOR {
+                    gl::DisableVertexAttribArray(location);
+                }
+            }
+            None => {
+                for (column, location) in (INSTANCE_TRANSFORM..INSTANCE_COLOR).enumerate() {
+                    let [x, y, z, w] = Mat4::IDENTITY.col(column).to_array();
+                    gl::VertexAttrib4f(location, x, y, z, w);
+                }
+                gl::VertexAttrib4f(INSTANCE_COLOR, 1.0, 1.0, 1.0, 1.0);
+
+                gl::DrawElements(gl::TRIANGLES, index_count as i32, gl::UNSIGNED_INT, std::ptr::null());
+            }
+        }
+    }
+}
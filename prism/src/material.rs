@@ -0,0 +1,430 @@
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+
+use crate::{Shader, Texture, shader::UniformValue};
+
+/// How a material's output is combined with what is already on screen.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlendMode {
+    /// Overwrites the destination.
+    Opaque,
+    /// Standard `src * a + dst * (1 - a)` transparency.
+    Alpha,
+    /// `src + dst * (1 - a)`, for colours already multiplied by their alpha. Avoids the dark
+    /// fringes straight alpha gets around filtered edges.
+    Premultiplied,
+    /// Adds to the destination, for glows and particles.
+    Additive,
+    /// `src * dst`, darkening what is behind, e.g. for decals and tinted glass.
+    Multiply,
+}
+
+impl BlendMode {
+    /// Source and destination factors for colour, then for alpha.
+    fn factors(self) -> [u32; 4] {
+        match self {
+            BlendMode::Opaque => [gl::ONE, gl::ZERO, gl::ONE, gl::ZERO],
+            BlendMode::Alpha => [gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::ONE, gl::ONE_MINUS_SRC_ALPHA],
+            BlendMode::Premultiplied => [gl::ONE, gl::ONE_MINUS_SRC_ALPHA, gl::ONE, gl::ONE_MINUS_SRC_ALPHA],
+            BlendMode::Additive => [gl::SRC_ALPHA, gl::ONE, gl::ZERO, gl::ONE],
+            BlendMode::Multiply => [gl::DST_COLOR, gl::ZERO, gl::ZERO, gl::ONE],
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CullMode {
+    None,
+    /// Drops triangles facing away from the camera, i.e. wound clockwise on screen.
+    Back,
+    Front,
+}
+
+/// How a depth or stencil value is compared with the one in the buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompareFunction {
+    Never,
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
+}
+
+impl CompareFunction {
+    fn to_gl(self) -> u32 {
+        match self {
+            CompareFunction::Never => gl::NEVER,
+            CompareFunction::Less => gl::LESS,
+            CompareFunction::Equal => gl::EQUAL,
+            CompareFunction::LessEqual => gl::LEQUAL,
+            CompareFunction::Greater => gl::GREATER,
+            CompareFunction::NotEqual => gl::NOTEQUAL,
+            CompareFunction::GreaterEqual => gl::GEQUAL,
+            CompareFunction::Always => gl::ALWAYS,
+        }
+    }
+}
+
+/// What happens to the stencil value of a pixel.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    /// Sets it to the reference value.
+    Replace,
+    /// Adds one, stopping at 255.
+    Increment,
+    IncrementWrap,
+    /// Subtracts one, stopping at 0.
+    Decrement,
+    DecrementWrap,
+    Invert,
+}
+
+impl StencilOp {
+    fn to_gl(self) -> u32 {
+        match self {
+            StencilOp::Keep => gl::KEEP,
+            StencilOp::Zero => gl::ZERO,
+            StencilOp::Replace => gl::REPLACE,
+            StencilOp::Increment => gl::INCR,
+            StencilOp::IncrementWrap => gl::INCR_WRAP,
+            StencilOp::Decrement => gl::DECR,
+            StencilOp::DecrementWrap => gl::DECR_WRAP,
+            StencilOp::Invert => gl::INVERT,
+        }
+    }
+}
+
+/// Stencil test of a material, the same for front and back faces. Pixels pass when
+/// `reference & read_mask` compares true against `stored & read_mask`; only the bits in
+/// `write_mask` are changed. Screens and render targets with depth have 8 stencil bits,
+/// cleared to 0 with the depth.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StencilState {
+    pub compare: CompareFunction,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+    /// When the stencil test fails.
+    pub fail: StencilOp,
+    /// When the stencil test passes but the depth test fails.
+    pub depth_fail: StencilOp,
+    pub pass: StencilOp,
+}
+
+impl StencilState {
+    /// Writes `reference` wherever the mesh is drawn, e.g. to mark the inside of a portal.
+    pub fn write(reference: u8) -> Self {
+        Self {
+            compare: CompareFunction::Always,
+            reference,
+            read_mask: 0xFF,
+            write_mask: 0xFF,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Replace,
+        }
+    }
+
+    /// Only draws where the stencil buffer holds `reference`, leaving it unchanged.
+    pub fn equal(reference: u8) -> Self {
+        Self {
+            compare: CompareFunction::Equal,
+            write_mask: 0,
+            pass: StencilOp::Keep,
+            ..Self::write(reference)
+        }
+    }
+
+    /// Only draws where the stencil buffer does not hold `reference`, e.g. for outlines.
+    pub fn not_equal(reference: u8) -> Self {
+        Self { compare: CompareFunction::NotEqual, ..Self::equal(reference) }
+    }
+}
+
+/// Fixed-function state a material is drawn with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RenderState {
+    pub blend: BlendMode,
+    pub cull: CullMode,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_compare: CompareFunction,
+    /// `None` leaves the stencil buffer alone.
+    pub stencil: Option<StencilState>,
+    /// Whether the screen's shadow map darkens the mesh.
+    pub receive_shadows: bool,
+}
+
+impl Default for RenderState {
+    /// Opaque with depth testing and no culling, matching how meshes were drawn before
+    /// materials existed.
+    fn default() -> Self {
+        Self {
+            blend: BlendMode::Opaque,
+            cull: CullMode::None,
+            depth_test: true,
+            depth_write: true,
+            depth_compare: CompareFunction::Less,
+            stencil: None,
+            receive_shadows: true,
+        }
+    }
+}
+
+impl RenderState {
+    /// Blended with `blend`. Anything but `Opaque` still tests depth but does not write it,
+    /// so that meshes behind show through; draw those furthest first.
+    pub fn blended(blend: BlendMode) -> Self {
+        Self {
+            blend,
+            depth_write: blend == BlendMode::Opaque,
+            ..Self::default()
+        }
+    }
+}
+
+/// The fixed-function state last set on a context, so that draws only make the GL calls
+/// for what differs. Fields are `None` until first set.
+#[derive(Default)]
+pub(crate) struct StateCache {
+    blend: Option<BlendMode>,
+    cull: Option<CullMode>,
+    depth_test: Option<bool>,
+    depth_write: Option<bool>,
+    depth_compare: Option<CompareFunction>,
+    stencil: Option<Option<StencilState>>,
+    scissor: Option<Option<[i32; 4]>>,
+}
+
+/// Stores `value` and returns true if it differs from what was cached.
+fn changed<T: PartialEq>(cached: &mut Option<T>, value: T) -> bool {
+    if cached.as_ref() == Some(&value) {
+        return false;
+    }
+    *cached = Some(value);
+    true
+}
+
+impl StateCache {
+    pub(crate) fn apply(&mut self, state: &RenderState) {
+        unsafe {
+            if changed(&mut self.blend, state.blend) {
+                if state.blend == BlendMode::Opaque {
+                    gl::Disable(gl::BLEND);
+                }
+                else {
+                    let [src, dst, src_alpha, dst_alpha] = state.blend.factors();
+                    gl::Enable(gl::BLEND);
+                    gl::BlendFuncSeparate(src, dst, src_alpha, dst_alpha);
+                }
+            }
+
+            if changed(&mut self.cull, state.cull) {
+                match state.cull {
+                    CullMode::None => gl::Disable(gl::CULL_FACE),
+                    CullMode::Back => {
+                        gl::Enable(gl::CULL_FACE);
+                        gl::CullFace(gl::BACK);
+                    }
+                    CullMode::Front => {
+                        gl::Enable(gl::CULL_FACE);
+                        gl::CullFace(gl::FRONT);
+                    }
+                }
+            }
+
+            if changed(&mut self.depth_test, state.depth_test) {
+                if state.depth_test {
+                    gl::Enable(gl::DEPTH_TEST);
+                }
+                else {
+                    gl::Disable(gl::DEPTH_TEST);
+                }
+            }
+
+            if changed(&mut self.depth_compare, state.depth_compare) {
+                gl::DepthFunc(state.depth_compare.to_gl());
+            }
+        }
+
+        self.set_depth_write(state.depth_write);
+        self.set_stencil(state.stencil);
+    }
+
+    pub(crate) fn set_depth_write(&mut self, depth_write: bool) {
+        if changed(&mut self.depth_write, depth_write) {
+            unsafe {
+                gl::DepthMask(if depth_write { gl::TRUE } else { gl::FALSE });
+            }
+        }
+    }
+
+    /// Turning the stencil test off also lets every bit be written again, so that clears reach
+    /// the whole stencil buffer.
+    pub(crate) fn set_stencil(&mut self, stencil: Option<StencilState>) {
+        if !changed(&mut self.stencil, stencil) {
+            return;
+        }
+
+        unsafe {
+            match stencil {
+                Some(stencil) => {
+                    gl::Enable(gl::STENCIL_TEST);
+                    gl::StencilFunc(stencil.compare.to_gl(), stencil.reference as i32, stencil.read_mask as u32);
+                    gl::StencilMask(stencil.write_mask as u32);
+                    gl::StencilOp(stencil.fail.to_gl(), stencil.depth_fail.to_gl(), stencil.pass.to_gl());
+                }
+                None => {
+                    gl::Disable(gl::STENCIL_TEST);
+                    gl::StencilMask(0xFF);
+                }
+            }
+        }
+    }
+
+    /// `x`, `y`, width and height in pixels from the bottom left, or `None` for no scissor test.
+    pub(crate) fn set_scissor(&mut self, scissor: Option<[i32; 4]>) {
+        if !changed(&mut self.scissor, scissor) {
+            return;
+        }
+
+        unsafe {
+            match scissor {
+                Some([x, y, width, height]) => {
+                    gl::Enable(gl::SCISSOR_TEST);
+                    gl::Scissor(x, y, width, height);
+                }
+                None => gl::Disable(gl::SCISSOR_TEST),
+            }
+        }
+    }
+}
+
+/// Values of the built-in shaders' optional parameters when a material does not set them:
+/// fully opaque, nothing cut out.
+const BUILT_IN_DEFAULTS: [(&str, UniformValue); 2] = [("transparency", UniformValue::Float(0.0)), ("alphaCutoff", UniformValue::Float(0.0))];
+
+/// What a mesh is drawn with: a shader, the uniform values it is fed, the textures bound to
+/// its samplers and the render state. Materials made from the same shader share its program.
+#[derive(Clone)]
+pub struct Material {
+    pub(crate) shader: Shader,
+    pub(crate) params: Vec<(String, UniformValue)>,
+    // Bound to texture units in order, after any textures of the mesh itself.
+    pub(crate) textures: Vec<(String, Texture)>,
+    pub state: RenderState,
+}
+
+impl Material {
+    pub fn new(shader: &Shader) -> Self {
+        Self {
+            shader: shader.clone(),
+            params: Vec::new(),
+            textures: Vec::new(),
+            state: RenderState::default(),
+        }
+    }
+
+    pub fn shader(&self) -> &Shader {
+        &self.shader
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) {
+        self.set_param(name, UniformValue::Float(value));
+    }
+
+    pub fn set_vec2(&mut self, name: &str, value: Vec2) {
+        self.set_param(name, UniformValue::Vec2(value));
+    }
+
+    pub fn set_vec3(&mut self, name: &str, value: Vec3) {
+        self.set_param(name, UniformValue::Vec3(value));
+    }
+
+    pub fn set_vec4(&mut self, name: &str, value: Vec4) {
+        self.set_param(name, UniformValue::Vec4(value));
+    }
+
+    pub fn set_int(&mut self, name: &str, value: i32) {
+        self.set_param(name, UniformValue::Int(value));
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.set_param(name, UniformValue::Bool(value));
+    }
+
+    pub fn set_mat3(&mut self, name: &str, value: Mat3) {
+        self.set_param(name, UniformValue::Mat3(value));
+    }
+
+    pub fn set_mat4(&mut self, name: &str, value: Mat4) {
+        self.set_param(name, UniformValue::Mat4(value));
+    }
+
+    /// Binds `texture` to the sampler uniform `sampler`, replacing any texture it had.
+    pub fn set_texture(&mut self, sampler: &str, texture: &Texture) {
+        match self.textures.iter_mut().find(|(name, _)| name == sampler) {
+            Some((_, slot)) => *slot = texture.clone(),
+            None => self.textures.push((sampler.to_string(), texture.clone())),
+        }
+    }
+
+    pub fn texture(&self, sampler: &str) -> Option<&Texture> {
+        self.textures.iter().find(|(name, _)| name == sampler).map(|(_, texture)| texture)
+    }
+
+    fn set_param(&mut self, name: &str, value: UniformValue) {
+        match self.params.iter_mut().find(|(param, _)| param == name) {
+            Some((_, slot)) => *slot = value,
+            None => self.params.push((name.to_string(), value)),
+        }
+    }
+
+    /// Material parameters of the built-in Phong shader.
+    pub fn set_phong(&mut self, ambient: Vec3, diffuse: Vec3, specular: Vec3, shininess: f32) {
+        self.set_vec3("material.ambient", ambient);
+        self.set_vec3("material.diffuse", diffuse);
+        self.set_vec3("material.specular", specular);
+        self.set_float("material.shininess", shininess);
+    }
+
+    /// Scales the alpha of the built-in Phong shader's output. Only shows with a blending
+    /// `state`, e.g. `RenderState::blended(BlendMode::Alpha)`.
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.set_float("transparency", 1.0 - opacity);
+    }
+
+    /// Makes the built-in Phong shader discard pixels with less alpha than `cutoff`. Cut-outs
+    /// such as foliage stay opaque and need no sorting.
+    pub fn set_alpha_cutoff(&mut self, cutoff: f32) {
+        self.set_float("alphaCutoff", cutoff);
+    }
+
+    /// Whether drawing with `other` would look the same: same shader, parameters, textures
+    /// and render state. Such materials can share one instanced draw.
+    pub fn draws_like(&self, other: &Material) -> bool {
+        self.shader.handle() == other.shader.handle()
+            && self.params == other.params
+            && self.state == other.state
+            && self.textures.len() == other.textures.len()
+            && self.textures.iter().zip(&other.textures).all(|((a_name, a), (b_name, b))| a_name == b_name && a.handle() == b.handle())
+    }
+
+    /// Feeds the parameters to the shader. Its program is shared with other materials, so
+    /// this happens on every draw, and the built-in parameters this material leaves unset are
+    /// put back to their defaults rather than keeping the last material's values.
+    pub(crate) fn apply_params(&mut self) {
+        for (name, value) in &self.params {
+            self.shader.set_uniform(name, *value);
+        }
+        for (name, value) in BUILT_IN_DEFAULTS {
+            if !self.params.iter().any(|(param, _)| param == name) {
+                self.shader.set_uniform(name, value);
+            }
+        }
+    }
+}
@@ -1,15 +1,28 @@
-use glam::Mat4;
+use std::{collections::HashMap, f32::consts::{PI, TAU}};
 
-use crate::{Texture, Vertex};
+use glam::{Mat4, Vec2, Vec3};
 
+use crate::{Texture, Vertex, bounds::{Aabb, BoundingSphere}, resource::{Handle, ResourceRef}, vertex_layout::{BufferUsage, VertexLayout}};
+
+/// Cloning a mesh shares its GPU buffers; each clone has its own transform. Meshes with clones
+/// cannot be updated.
+#[derive(Clone)]
 pub struct Mesh {
+    pub(crate) resource: ResourceRef,
+
+    /// Copies of what meshes made from `Vertex`es were created with; empty for other layouts
+    /// and once the buffers have been updated.
     pub(crate) vertices: Vec<Vertex>,
     pub(crate) indices: Vec<u32>,
     pub(crate) textures: Vec<Texture>,
 
-    pub(crate) vao: u32,
-    pub(crate) vbo: u32,
-    pub(crate) ebo: u32,
+    pub(crate) layout: VertexLayout,
+    pub(crate) usage: BufferUsage,
+    pub(crate) vertex_count: usize,
+    pub(crate) index_count: usize,
+    /// Around the vertex positions, before the model matrix. `None` for meshes without
+    /// vertices or float positions, which are never culled.
+    pub(crate) bounds: Option<Aabb>,
 
     pub(crate) position: glam::Vec3,
     pub(crate) rotation: glam::Vec3,
@@ -19,6 +32,55 @@ pub struct Mesh {
 }
 
 impl Mesh {
+    pub fn handle(&self) -> Handle {
+        self.resource.handle()
+    }
+
+    pub fn layout(&self) -> &VertexLayout {
+        &self.layout
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.usage
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertex_count
+    }
+
+    pub fn index_count(&self) -> usize {
+        self.index_count
+    }
+
+    pub fn local_bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    /// The box around the mesh where its model matrix places it.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.bounds.map(|bounds| bounds.transformed(self.model))
+    }
+
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.bounds.map(|bounds| BoundingSphere::from_aabb(&bounds).transformed(self.model))
+    }
+
+    pub fn model_matrix(&self) -> Mat4 {
+        self.model
+    }
+
+    /// Whether `other` is the same geometry with the same textures, transform aside. Such
+    /// meshes can share one instanced draw.
+    pub fn draws_like(&self, other: &Mesh) -> bool {
+        self.handle() == other.handle()
+            && self.textures.len() == other.textures.len()
+            && self.textures.iter().zip(&other.textures).all(|(a, b)| a.handle() == b.handle())
+    }
+
+    pub fn translation(&self) -> glam::Vec3 {
+        self.position
+    }
+
     pub fn set_rotation_x(&mut self, angle_rad: f32) {
         self.rotation.x = angle_rad;
         self.update_model_matrix();
@@ -44,6 +106,17 @@ impl Mesh {
         self.update_model_matrix();
     }
 
+    /// Replaces the transform with `matrix`, which may include shear. Position, rotation and
+    /// scale are taken from it, so the setters above continue from an approximation.
+    pub fn set_model_matrix(&mut self, matrix: Mat4) {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        let (z, y, x) = rotation.to_euler(glam::EulerRot::ZYX);
+        self.position = translation;
+        self.rotation = glam::Vec3::new(x, y, z);
+        self.scale = scale;
+        self.model = matrix;
+    }
+
     fn update_model_matrix(&mut self) {
         let translation = Mat4::from_translation(self.position);
         let rotation_x = Mat4::from_rotation_x(self.rotation.x);
@@ -53,4 +126,374 @@ impl Mesh {
 
         self.model = translation * rotation_z * rotation_y * rotation_x * scale;
     }
-}
\ No newline at end of file
+}
+
+/// Vertices and indices of a mesh before it is uploaded with
+/// `PrismRenderer::create_mesh_from_geometry`. The generators build Y-up shapes centered on
+/// the origin, indexed, with triangles wound counter-clockwise seen from outside and `v = 0`
+/// at the bottom of textures.
+#[derive(Clone, Debug, Default)]
+pub struct Geometry {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Geometry {
+    /// A cube with sides of `size`, each face showing the whole texture.
+    pub fn cube(size: f32) -> Self {
+        Self::cuboid(Vec3::splat(size))
+    }
+
+    pub fn cuboid(size: Vec3) -> Self {
+        let half = size * 0.5;
+        // Normal, then the face's right and up directions seen from outside.
+        let faces = [
+            (Vec3::X, Vec3::NEG_Z, Vec3::Y),
+            (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+            (Vec3::Y, Vec3::X, Vec3::NEG_Z),
+            (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+            (Vec3::Z, Vec3::X, Vec3::Y),
+            (Vec3::NEG_Z, Vec3::NEG_X, Vec3::Y),
+        ];
+
+        let mut geometry = Self::default();
+        for (normal, right, up) in faces {
+            let base = geometry.vertices.len() as u32;
+            for (u, v) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                let position = (normal + right * (u * 2.0 - 1.0) + up * (v * 2.0 - 1.0)) * half;
+                geometry.vertices.push(Vertex::new(position, normal, Vec2::new(u, v)));
+            }
+            geometry.indices.extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 3, base]);
+        }
+
+        geometry
+    }
+
+    /// A flat square on the XZ plane facing up.
+    pub fn plane(size: Vec2) -> Self {
+        Self::grid(size, 1, 1)
+    }
+
+    /// A plane on the XZ plane facing up, split into `columns` along X and `rows` along Z.
+    /// The texture covers it once, with its top towards -Z.
+    pub fn grid(size: Vec2, columns: u32, rows: u32) -> Self {
+        let (columns, rows) = (columns.max(1), rows.max(1));
+        let mut geometry = Self::default();
+
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let uv = Vec2::new(column as f32 / columns as f32, row as f32 / rows as f32);
+                let position = Vec3::new((uv.x - 0.5) * size.x, 0.0, (0.5 - uv.y) * size.y);
+                geometry.vertices.push(Vertex::new(position, Vec3::Y, uv));
+            }
+        }
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let a = row * (columns + 1) + column;
+                let d = a + columns + 1;
+                geometry.indices.extend_from_slice(&[a, a + 1, d + 1, d + 1, d, a]);
+            }
+        }
+
+        geometry
+    }
+
+    /// A sphere of `segments` around its axis and `rings` from pole to pole. Textures wrap
+    /// around it once, in equirectangular projection.
+    pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Self {
+        let rings = rings.max(2);
+        let profile = (0..=rings).map(|ring| {
+            let angle = PI * ring as f32 / rings as f32;
+            let normal = Vec2::new(angle.sin(), angle.cos());
+            ProfilePoint { radius: normal.x * radius, y: normal.y * radius, normal, v: 1.0 - ring as f32 / rings as f32 }
+        });
+        Self::lathe(&profile.collect::<Vec<_>>(), segments)
+    }
+
+    /// A sphere made by splitting the faces of an icosahedron `subdivisions` times, with
+    /// triangles of nearly equal size. Textures are mapped as on `uv_sphere`.
+    pub fn icosphere(radius: f32, subdivisions: u32) -> Self {
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut positions: Vec<Vec3> = [
+            (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+            (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+            (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+        ]
+        .into_iter()
+        .map(|(x, y, z)| Vec3::new(x, y, z).normalize())
+        .collect();
+
+        let mut triangles: Vec<[u32; 3]> = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+            let mut midpoint = |a: u32, b: u32| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    positions.push((positions[a as usize] + positions[b as usize]).normalize());
+                    positions.len() as u32 - 1
+                })
+            };
+
+            triangles = triangles
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        let mut geometry = Self {
+            vertices: positions
+                .iter()
+                .map(|&direction| Vertex::new(direction * radius, direction, sphere_uv(direction)))
+                .collect(),
+            indices: Vec::with_capacity(triangles.len() * 3),
+        };
+
+        // Triangles crossing the seam at u = 0 need copies of their vertices at u + 1, or
+        // the whole texture would be squeezed into them backwards.
+        let mut wrapped: HashMap<u32, u32> = HashMap::new();
+        for triangle in &mut triangles {
+            let us = triangle.map(|i| geometry.vertices[i as usize].tex_coords.x);
+            if us.iter().cloned().fold(f32::MIN, f32::max) - us.iter().cloned().fold(f32::MAX, f32::min) <= 0.5 {
+                continue;
+            }
+
+            for index in triangle.iter_mut() {
+                if geometry.vertices[*index as usize].tex_coords.x < 0.5 {
+                    *index = *wrapped.entry(*index).or_insert_with(|| {
+                        let mut vertex = geometry.vertices[*index as usize];
+                        vertex.tex_coords.x += 1.0;
+                        geometry.vertices.push(vertex);
+                        geometry.vertices.len() as u32 - 1
+                    });
+                }
+            }
+        }
+
+        geometry.indices.extend(triangles.iter().flatten());
+        geometry
+    }
+
+    /// A capped cylinder of `height` along Y.
+    pub fn cylinder(radius: f32, height: f32, segments: u32) -> Self {
+        let half = height * 0.5;
+        let mut geometry = Self::lathe(
+            &[
+                ProfilePoint { radius, y: half, normal: Vec2::X, v: 1.0 },
+                ProfilePoint { radius, y: -half, normal: Vec2::X, v: 0.0 },
+            ],
+            segments,
+        );
+        geometry.add_cap(radius, half, true, segments);
+        geometry.add_cap(radius, -half, false, segments);
+        geometry
+    }
+
+    /// A cone of `height` along Y with its tip at the top, capped at the bottom.
+    pub fn cone(radius: f32, height: f32, segments: u32) -> Self {
+        let half = height * 0.5;
+        let normal = Vec2::new(height, radius).normalize_or_zero();
+        let mut geometry = Self::lathe(
+            &[
+                ProfilePoint { radius: 0.0, y: half, normal, v: 1.0 },
+                ProfilePoint { radius, y: -half, normal, v: 0.0 },
+            ],
+            segments,
+        );
+        geometry.add_cap(radius, -half, false, segments);
+        geometry
+    }
+
+    /// A cylinder of `height` along Y between the centers of two hemispheres, so its total
+    /// height is `height + 2 * radius`. `rings` is per hemisphere.
+    pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Self {
+        let rings = rings.max(1);
+        let half = height * 0.5;
+        // Texture rows are spread by distance along the surface.
+        let length = PI * radius + height;
+
+        let mut profile = Vec::with_capacity(2 * (rings as usize + 1));
+        for (center, first_angle, distance) in [(half, 0.0, 0.0), (-half, PI / 2.0, PI * radius / 2.0 + height)] {
+            for ring in 0..=rings {
+                let step = ring as f32 / rings as f32;
+                let angle = first_angle + PI / 2.0 * step;
+                let normal = Vec2::new(angle.sin(), angle.cos());
+                let v = 1.0 - (distance + PI / 2.0 * radius * step) / length;
+                profile.push(ProfilePoint { radius: normal.x * radius, y: center + normal.y * radius, normal, v });
+            }
+        }
+
+        Self::lathe(&profile, segments)
+    }
+
+    /// A ring around Y, `major_radius` from its center to the middle of its tube.
+    pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Self {
+        let minor_segments = minor_segments.max(3);
+        // Around the tube from its top, over the outside first.
+        let profile = (0..=minor_segments).map(|segment| {
+            let step = segment as f32 / minor_segments as f32;
+            let angle = PI / 2.0 - TAU * step;
+            let normal = Vec2::new(angle.cos(), angle.sin());
+            ProfilePoint { radius: major_radius + normal.x * minor_radius, y: normal.y * minor_radius, normal, v: 1.0 - step }
+        });
+        Self::lathe(&profile.collect::<Vec<_>>(), major_segments)
+    }
+
+    /// One vertex per index, for flat shading or per-triangle data. Indices become trivial.
+    pub fn unindexed(self) -> Self {
+        let vertices: Vec<Vertex> = self.indices.iter().map(|&i| self.vertices[i as usize]).collect();
+        let indices = (0..vertices.len() as u32).collect();
+        Self { vertices, indices }
+    }
+
+    /// Revolves `profile`, listed from top to bottom, around Y in `segments` steps.
+    fn lathe(profile: &[ProfilePoint], segments: u32) -> Self {
+        let segments = segments.max(3);
+        let mut geometry = Self::default();
+        // Poles computed with sin(PI) are only nearly on the axis.
+        let on_axis = |point: &ProfilePoint| point.radius.abs() <= f32::EPSILON * point.y.abs().max(1.0);
+
+        for point in profile {
+            let radius = if on_axis(point) { 0.0 } else { point.radius };
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let (sin, cos) = (TAU * u).sin_cos();
+                geometry.vertices.push(Vertex::new(
+                    Vec3::new(radius * sin, point.y, radius * cos),
+                    Vec3::new(point.normal.x * sin, point.normal.y, point.normal.x * cos).normalize_or_zero(),
+                    Vec2::new(u, point.v),
+                ));
+            }
+        }
+
+        let row = segments + 1;
+        for (i, pair) in profile.windows(2).enumerate() {
+            for segment in 0..segments {
+                let a = i as u32 * row + segment;
+                let b = a + row;
+                // Skip the triangles that collapse where the profile meets the axis.
+                if !on_axis(&pair[1]) {
+                    geometry.indices.extend_from_slice(&[a, b, b + 1]);
+                }
+                if !on_axis(&pair[0]) {
+                    geometry.indices.extend_from_slice(&[b + 1, a + 1, a]);
+                }
+            }
+        }
+
+        geometry
+    }
+
+    /// A disc at height `y` facing up or down.
+    fn add_cap(&mut self, radius: f32, y: f32, up: bool, segments: u32) {
+        let segments = segments.max(3);
+        let normal = if up { Vec3::Y } else { Vec3::NEG_Y };
+        let center = self.vertices.len() as u32;
+        self.vertices.push(Vertex::new(Vec3::new(0.0, y, 0.0), normal, Vec2::splat(0.5)));
+
+        for segment in 0..=segments {
+            let (sin, cos) = (TAU * segment as f32 / segments as f32).sin_cos();
+            let tex_coords = Vec2::new(0.5 + 0.5 * sin, 0.5 + if up { -0.5 } else { 0.5 } * cos);
+            self.vertices.push(Vertex::new(Vec3::new(radius * sin, y, radius * cos), normal, tex_coords));
+        }
+
+        for segment in 0..segments {
+            let (first, second) = (center + 1 + segment, center + 2 + segment);
+            if up {
+                self.indices.extend_from_slice(&[center, first, second]);
+            }
+            else {
+                self.indices.extend_from_slice(&[center, second, first]);
+            }
+        }
+    }
+}
+
+/// A point of a shape's outline in the XY plane, revolved by `Geometry::lathe`.
+struct ProfilePoint {
+    /// Distance from the Y axis.
+    radius: f32,
+    y: f32,
+    /// Outward, as (radial, y).
+    normal: Vec2,
+    v: f32,
+}
+
+/// Equirectangular texture coordinates of a direction from the center of a sphere.
+fn sphere_uv(direction: Vec3) -> Vec2 {
+    let u = direction.x.atan2(direction.z) / TAU;
+    Vec2::new(if u < 0.0 { u + 1.0 } else { u }, 0.5 + direction.y.clamp(-1.0, 1.0).asin() / PI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that every triangle faces the same way as its vertex normals, i.e. is wound
+    /// counter-clockwise seen from outside.
+    fn assert_wound_outwards(name: &str, geometry: &Geometry) {
+        assert_eq!(geometry.indices.len() % 3, 0, "{}", name);
+        for triangle in geometry.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| geometry.vertices[triangle[i] as usize]);
+            let face = (b.position - a.position).cross(c.position - a.position);
+            let normal = a.normal + b.normal + c.normal;
+            assert!(face.length() > 1e-6, "{} has a degenerate triangle {:?}", name, triangle);
+            assert!(face.dot(normal) > 0.0, "{} has a triangle {:?} wound inwards", name, triangle);
+        }
+    }
+
+    #[test]
+    fn generators_have_the_expected_counts() {
+        let cases = [
+            ("cube", Geometry::cube(1.0), 24, 36),
+            ("grid", Geometry::grid(Vec2::ONE, 3, 2), 12, 36),
+            ("uv_sphere", Geometry::uv_sphere(1.0, 8, 4), 45, 144),
+            ("cylinder", Geometry::cylinder(1.0, 2.0, 8), 38, 96),
+            ("cone", Geometry::cone(1.0, 2.0, 8), 28, 48),
+            ("capsule", Geometry::capsule(1.0, 2.0, 8, 4), 90, 384),
+            ("torus", Geometry::torus(2.0, 0.5, 8, 6), 63, 288),
+        ];
+
+        for (name, geometry, vertices, indices) in cases {
+            assert_eq!((geometry.vertices.len(), geometry.indices.len()), (vertices, indices), "{}", name);
+        }
+
+        assert_eq!(Geometry::icosphere(1.0, 0).indices.len(), 60);
+        assert_eq!(Geometry::icosphere(1.0, 2).indices.len(), 960);
+    }
+
+    #[test]
+    fn generators_wind_counter_clockwise_from_outside() {
+        assert_wound_outwards("cube", &Geometry::cube(2.0));
+        assert_wound_outwards("grid", &Geometry::grid(Vec2::new(2.0, 1.0), 4, 3));
+        assert_wound_outwards("uv_sphere", &Geometry::uv_sphere(1.0, 12, 6));
+        assert_wound_outwards("icosphere", &Geometry::icosphere(1.0, 2));
+        assert_wound_outwards("cylinder", &Geometry::cylinder(0.5, 2.0, 12));
+        assert_wound_outwards("cone", &Geometry::cone(0.5, 2.0, 12));
+        assert_wound_outwards("capsule", &Geometry::capsule(0.5, 1.0, 12, 3));
+        assert_wound_outwards("torus", &Geometry::torus(1.0, 0.25, 12, 8));
+    }
+
+    #[test]
+    fn icosphere_vertices_lie_on_the_sphere() {
+        let geometry = Geometry::icosphere(2.0, 1);
+        assert!(geometry.vertices.iter().all(|vertex| (vertex.position.length() - 2.0).abs() < 1e-5));
+    }
+
+    #[test]
+    fn unindexed_copies_a_vertex_per_index() {
+        let geometry = Geometry::cube(1.0);
+        let unindexed = geometry.clone().unindexed();
+        assert_eq!(unindexed.vertices.len(), geometry.indices.len());
+        assert!(unindexed.indices.iter().enumerate().all(|(i, &index)| i as u32 == index));
+        assert_eq!(unindexed.vertices[5].position, geometry.vertices[geometry.indices[5] as usize].position);
+    }
+}
@@ -0,0 +1,464 @@
+use std::{collections::HashMap, path::Path};
+
+use glam::{Mat4, Vec2, Vec3};
+use image::RgbaImage;
+
+use crate::{Texture, Vertex, atlas::page_path, material::{BlendMode, CullMode, Material, RenderState}, mesh::Mesh, texture::{self, FilterMode, TextureDescriptor, WrapMode}};
+
+/// Triangles sharing one material.
+#[derive(Clone)]
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// Index into `ModelData::materials`; `None` uses a plain white material.
+    pub material: Option<usize>,
+}
+
+/// Phong parameters for the built-in shader, approximated from PBR values for glTF.
+#[derive(Clone, Debug)]
+pub struct MaterialData {
+    pub name: String,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub shininess: f32,
+    /// Index into `ModelData::textures`.
+    pub diffuse_texture: Option<usize>,
+    /// Blends with what is behind instead of being opaque.
+    pub transparent: bool,
+    /// Multiplies the alpha of the diffuse texture.
+    pub opacity: f32,
+    /// Pixels with less alpha than this are discarded, for cut-outs such as leaves.
+    pub alpha_cutoff: Option<f32>,
+    pub double_sided: bool,
+}
+
+impl Default for MaterialData {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            ambient: Vec3::ONE,
+            diffuse: Vec3::ONE,
+            specular: Vec3::splat(0.5),
+            shininess: 32.0,
+            diffuse_texture: None,
+            transparent: false,
+            opacity: 1.0,
+            alpha_cutoff: None,
+            double_sided: true,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TextureData {
+    pub image: RgbaImage,
+    pub descriptor: TextureDescriptor,
+}
+
+#[derive(Clone, Debug)]
+pub struct NodeData {
+    pub name: String,
+    /// Relative to the parent node.
+    pub transform: Mat4,
+    /// Indices into `ModelData::meshes`.
+    pub meshes: Vec<usize>,
+    /// Indices into `ModelData::nodes`.
+    pub children: Vec<usize>,
+}
+
+/// A model on the CPU, ready to be uploaded with `PrismRenderer::create_model`.
+#[derive(Clone)]
+pub struct ModelData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+    pub textures: Vec<TextureData>,
+    pub nodes: Vec<NodeData>,
+    /// Nodes without a parent, indices into `nodes`.
+    pub roots: Vec<usize>,
+}
+
+impl ModelData {
+    /// Loads a model, picking the importer by file extension.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("obj") => Self::load_obj(path),
+            Some("gltf") | Some("glb") => Self::load_gltf(path),
+            _ => Err(format!("Unsupported model format {}; expected .obj, .gltf or .glb", path.to_string_lossy())),
+        }
+    }
+
+    /// Loads a Wavefront OBJ with the MTL libraries it references. Polygons are triangulated,
+    /// and points and lines are skipped. Each object becomes a root node.
+    pub fn load_obj(path: &Path) -> Result<Self, String> {
+        let options = tobj::LoadOptions { single_index: true, triangulate: true, ignore_points: true, ignore_lines: true };
+        let (objects, materials) = tobj::load_obj(path, &options).map_err(|e| format!("Failed to load {}: {}", path.to_string_lossy(), e))?;
+
+        let materials = materials.unwrap_or_else(|e| {
+            eprintln!("Prism: {} is drawn with default materials: {}", path.to_string_lossy(), e);
+            Vec::new()
+        });
+
+        let mut data = Self::empty();
+        let mut texture_indices: HashMap<String, usize> = HashMap::new();
+
+        for material in &materials {
+            let diffuse_texture = match &material.diffuse_texture {
+                Some(file) => {
+                    // Map options such as "-s 1 1 1" come before the file name.
+                    let file = if file.starts_with('-') { file.split_whitespace().last().unwrap_or(file) } else { file.as_str() };
+                    let index = match texture_indices.get(file) {
+                        Some(&index) => index,
+                        None => {
+                            let descriptor = TextureDescriptor {
+                                wrap_s: WrapMode::Repeat,
+                                wrap_t: WrapMode::Repeat,
+                                min_filter: FilterMode::Linear,
+                                mag_filter: FilterMode::Linear,
+                                mipmaps: true,
+                                ..TextureDescriptor::default()
+                            };
+                            data.textures.push(TextureData { image: texture::load_file(&page_path(path, file))?, descriptor });
+                            texture_indices.insert(file.to_string(), data.textures.len() - 1);
+                            data.textures.len() - 1
+                        }
+                    };
+                    Some(index)
+                }
+                None => None,
+            };
+
+            let defaults = MaterialData::default();
+            data.materials.push(MaterialData {
+                name: material.name.clone(),
+                ambient: material.ambient.map_or(defaults.ambient, Vec3::from),
+                diffuse: material.diffuse.map_or(defaults.diffuse, Vec3::from),
+                specular: material.specular.map_or(defaults.specular, Vec3::from),
+                shininess: material.shininess.unwrap_or(defaults.shininess).max(1.0),
+                diffuse_texture,
+                transparent: material.dissolve.is_some_and(|dissolve| dissolve < 1.0),
+                opacity: material.dissolve.unwrap_or(1.0),
+                alpha_cutoff: None,
+                double_sided: true,
+            });
+        }
+
+        for object in objects {
+            let mesh = &object.mesh;
+            let vertices = (0..mesh.positions.len() / 3)
+                .map(|i| Vertex {
+                    position: Vec3::from_slice(&mesh.positions[i * 3..i * 3 + 3]),
+                    normal: mesh.normals.get(i * 3..i * 3 + 3).map_or(Vec3::ZERO, Vec3::from_slice),
+                    tex_coords: mesh.texcoords.get(i * 2..i * 2 + 2).map_or(Vec2::ZERO, Vec2::from_slice),
+                })
+                .collect();
+
+            let mut mesh_data = MeshData {
+                name: object.name.clone(),
+                vertices,
+                indices: mesh.indices.clone(),
+                material: mesh.material_id.filter(|&id| id < data.materials.len()),
+            };
+            if mesh.normals.is_empty() {
+                compute_normals(&mut mesh_data);
+            }
+
+            data.meshes.push(mesh_data);
+            data.nodes.push(NodeData { name: object.name, transform: Mat4::IDENTITY, meshes: vec![data.meshes.len() - 1], children: Vec::new() });
+            data.roots.push(data.nodes.len() - 1);
+        }
+
+        Ok(data)
+    }
+
+    /// Loads a glTF 2.0 file, embedded or with separate buffers and images, or a GLB.
+    /// Metallic-roughness materials are approximated with Phong parameters. Skins,
+    /// animations and morph targets are ignored with a warning; extensions the file
+    /// requires and primitives other than triangles are errors.
+    pub fn load_gltf(path: &Path) -> Result<Self, String> {
+        let name = path.to_string_lossy();
+        let (document, buffers, images) = gltf::import(path).map_err(|e| format!("Failed to load {}: {}", name, e))?;
+
+        if let Some(extension) = document.extensions_required().next() {
+            return Err(format!("{} requires the unsupported glTF extension {}", name, extension));
+        }
+
+        if document.skins().next().is_some() || document.animations().next().is_some() {
+            eprintln!("Prism: Skins and animations in {} are not supported and were ignored", name);
+        }
+
+        let mut data = Self::empty();
+
+        for texture in document.textures() {
+            let image = &images[texture.source().index()];
+            let sampler = texture.sampler();
+            let wrap = |mode: gltf::texture::WrappingMode| match mode {
+                gltf::texture::WrappingMode::ClampToEdge => WrapMode::ClampToEdge,
+                gltf::texture::WrappingMode::MirroredRepeat => WrapMode::MirroredRepeat,
+                gltf::texture::WrappingMode::Repeat => WrapMode::Repeat,
+            };
+            let nearest = matches!(sampler.mag_filter(), Some(gltf::texture::MagFilter::Nearest));
+
+            let descriptor = TextureDescriptor {
+                wrap_s: wrap(sampler.wrap_s()),
+                wrap_t: wrap(sampler.wrap_t()),
+                min_filter: if nearest { FilterMode::Nearest } else { FilterMode::Linear },
+                mag_filter: if nearest { FilterMode::Nearest } else { FilterMode::Linear },
+                mipmaps: !matches!(sampler.min_filter(), Some(gltf::texture::MinFilter::Nearest) | Some(gltf::texture::MinFilter::Linear)),
+                ..TextureDescriptor::default()
+            };
+            data.textures.push(TextureData { image: gltf_image(image, texture.index())?, descriptor });
+        }
+
+        for material in document.materials() {
+            let pbr = material.pbr_metallic_roughness();
+            let [r, g, b, a] = pbr.base_color_factor();
+            let base_color = Vec3::new(r, g, b);
+            let roughness = pbr.roughness_factor().clamp(0.05, 1.0);
+            // Metals tint their highlights; rough surfaces spread them out.
+            let specular = Vec3::splat(0.04).lerp(base_color, pbr.metallic_factor()) * (1.0 - roughness * 0.5);
+
+            data.materials.push(MaterialData {
+                name: material.name().unwrap_or("").to_string(),
+                ambient: base_color,
+                diffuse: base_color,
+                specular,
+                shininess: (2.0 / roughness.powi(4) - 2.0).clamp(1.0, 256.0),
+                diffuse_texture: pbr.base_color_texture().map(|info| info.texture().index()),
+                transparent: material.alpha_mode() == gltf::material::AlphaMode::Blend,
+                opacity: if material.alpha_mode() == gltf::material::AlphaMode::Opaque { 1.0 } else { a },
+                alpha_cutoff: match material.alpha_mode() {
+                    gltf::material::AlphaMode::Mask => Some(material.alpha_cutoff().unwrap_or(0.5)),
+                    _ => None,
+                },
+                double_sided: material.double_sided(),
+            });
+        }
+
+        // The model meshes each glTF mesh was split into, one per primitive.
+        let mut mesh_groups = Vec::new();
+        for mesh in document.meshes() {
+            let mesh_name = mesh.name().unwrap_or("").to_string();
+            let mut group = Vec::new();
+
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    return Err(format!("Mesh \"{}\" in {} uses {:?} primitives; only triangles are supported", mesh_name, name, primitive.mode()));
+                }
+                if primitive.morph_targets().next().is_some() {
+                    eprintln!("Prism: Morph targets of mesh \"{}\" are not supported and were ignored", mesh_name);
+                }
+
+                let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+                let positions: Vec<Vec3> = reader
+                    .read_positions()
+                    .ok_or_else(|| format!("Mesh \"{}\" in {} has a primitive without positions", mesh_name, name))?
+                    .map(Vec3::from)
+                    .collect();
+                let normals: Option<Vec<Vec3>> = reader.read_normals().map(|normals| normals.map(Vec3::from).collect());
+                // glTF puts v = 0 at the top of an image, and textures are flipped.
+                let tex_coords: Option<Vec<Vec2>> = reader
+                    .read_tex_coords(0)
+                    .map(|tex_coords| tex_coords.into_f32().map(|[u, v]| Vec2::new(u, 1.0 - v)).collect());
+                let indices = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+
+                let vertices = positions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &position)| Vertex {
+                        position,
+                        normal: normals.as_ref().and_then(|normals| normals.get(i).copied()).unwrap_or(Vec3::ZERO),
+                        tex_coords: tex_coords.as_ref().and_then(|tex_coords| tex_coords.get(i).copied()).unwrap_or(Vec2::ZERO),
+                    })
+                    .collect();
+
+                let mut mesh_data = MeshData { name: mesh_name.clone(), vertices, indices, material: primitive.material().index() };
+                if normals.is_none() {
+                    compute_normals(&mut mesh_data);
+                }
+
+                data.meshes.push(mesh_data);
+                group.push(data.meshes.len() - 1);
+            }
+
+            mesh_groups.push(group);
+        }
+
+        for node in document.nodes() {
+            data.nodes.push(NodeData {
+                name: node.name().unwrap_or("").to_string(),
+                transform: Mat4::from_cols_array_2d(&node.transform().matrix()),
+                meshes: node.mesh().map(|mesh| mesh_groups[mesh.index()].clone()).unwrap_or_default(),
+                children: node.children().map(|child| child.index()).collect(),
+            });
+        }
+
+        data.roots = match document.default_scene().or_else(|| document.scenes().next()) {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => {
+                let mut is_child = vec![false; data.nodes.len()];
+                for child in data.nodes.iter().flat_map(|node| &node.children) {
+                    is_child[*child] = true;
+                }
+                (0..data.nodes.len()).filter(|&i| !is_child[i]).collect()
+            }
+        };
+
+        Ok(data)
+    }
+
+    fn empty() -> Self {
+        Self { meshes: Vec::new(), materials: Vec::new(), textures: Vec::new(), nodes: Vec::new(), roots: Vec::new() }
+    }
+}
+
+/// Expands a decoded glTF image to RGBA8.
+fn gltf_image(image: &gltf::image::Data, texture: usize) -> Result<RgbaImage, String> {
+    use gltf::image::Format;
+
+    let channels = match image.format {
+        Format::R8 => 1,
+        Format::R8G8 => 2,
+        Format::R8G8B8 => 3,
+        Format::R8G8B8A8 => 4,
+        format => return Err(format!("Texture {} has the unsupported pixel format {:?}", texture, format)),
+    };
+
+    let pixels = image
+        .pixels
+        .chunks_exact(channels)
+        .flat_map(|pixel| match *pixel {
+            [gray] => [gray, gray, gray, 255],
+            [gray, alpha] => [gray, gray, gray, alpha],
+            [r, g, b] => [r, g, b, 255],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!(),
+        })
+        .collect();
+
+    RgbaImage::from_raw(image.width, image.height, pixels).ok_or_else(|| format!("Texture {} has a truncated image", texture))
+}
+
+/// Smooth normals, weighted by the area of the triangles sharing each vertex.
+fn compute_normals(mesh: &mut MeshData) {
+    for vertex in &mut mesh.vertices {
+        vertex.normal = Vec3::ZERO;
+    }
+
+    for triangle in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+        if a.max(b).max(c) >= mesh.vertices.len() {
+            continue;
+        }
+
+        let normal = (mesh.vertices[b].position - mesh.vertices[a].position).cross(mesh.vertices[c].position - mesh.vertices[a].position);
+        for i in [a, b, c] {
+            mesh.vertices[i].normal += normal;
+        }
+    }
+
+    for vertex in &mut mesh.vertices {
+        vertex.normal = vertex.normal.normalize_or_zero();
+    }
+}
+
+#[derive(Clone)]
+pub struct ModelNode {
+    pub name: String,
+    /// Relative to the parent node.
+    pub transform: Mat4,
+    /// Indices into `Model::meshes`, each drawn with the material `Model::mesh_material` gives.
+    pub meshes: Vec<usize>,
+    pub children: Vec<usize>,
+}
+
+/// A mesh of a model placed where its node puts it, with its own copy of its material.
+pub struct ModelInstance {
+    /// Name of the node the mesh belongs to.
+    pub name: String,
+    pub mesh: Mesh,
+    pub material: Material,
+}
+
+/// An uploaded model. Meshes and textures are shared by the instances made from it.
+pub struct Model {
+    pub(crate) meshes: Vec<Mesh>,
+    pub(crate) mesh_materials: Vec<usize>,
+    pub(crate) materials: Vec<Material>,
+    pub(crate) textures: Vec<Texture>,
+    pub(crate) nodes: Vec<ModelNode>,
+    pub(crate) roots: Vec<usize>,
+}
+
+impl Model {
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    /// The material of `mesh`, an index into `meshes`.
+    pub fn mesh_material(&self, mesh: usize) -> &Material {
+        &self.materials[self.mesh_materials[mesh]]
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn materials_mut(&mut self) -> &mut [Material] {
+        &mut self.materials
+    }
+
+    pub fn textures(&self) -> &[Texture] {
+        &self.textures
+    }
+
+    pub fn nodes(&self) -> &[ModelNode] {
+        &self.nodes
+    }
+
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    /// Every mesh of the node hierarchy, with the transforms of its node and the node's
+    /// ancestors applied on top of `transform`.
+    pub fn instances(&self, transform: Mat4) -> Vec<ModelInstance> {
+        let mut instances = Vec::new();
+        let mut stack: Vec<(usize, Mat4)> = self.roots.iter().rev().map(|&root| (root, transform)).collect();
+
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            let world = parent * node.transform;
+
+            for &mesh in &node.meshes {
+                let mut instance = self.meshes[mesh].clone();
+                instance.set_model_matrix(world);
+                instances.push(ModelInstance { name: node.name.clone(), mesh: instance, material: self.mesh_material(mesh).clone() });
+            }
+
+            stack.extend(node.children.iter().rev().map(|&child| (child, world)));
+        }
+
+        instances
+    }
+}
+
+/// Render state for an imported material.
+pub(crate) fn apply_material_data(material: &mut Material, data: &MaterialData) {
+    material.set_phong(data.ambient, data.diffuse, data.specular, data.shininess);
+    if data.transparent {
+        material.state = RenderState::blended(BlendMode::Alpha);
+    }
+    if data.opacity < 1.0 {
+        material.set_opacity(data.opacity);
+    }
+    if let Some(cutoff) = data.alpha_cutoff {
+        material.set_alpha_cutoff(cutoff);
+    }
+    material.state.cull = if data.double_sided { CullMode::None } else { CullMode::Back };
+}
@@ -0,0 +1,131 @@
+use image::{Rgba, RgbaImage};
+
+use crate::{Shader, Texture, render_target::RenderTarget};
+
+pub(crate) const FULLSCREEN_VERTEX: &str = include_str!("shaders/post/fullscreen.vert");
+const TONE_MAP_FRAGMENT: &str = include_str!("shaders/post/tone_map.frag");
+const BRIGHT_PASS_FRAGMENT: &str = include_str!("shaders/post/bright_pass.frag");
+const BLUR_FRAGMENT: &str = include_str!("shaders/post/blur.frag");
+const BLOOM_COMBINE_FRAGMENT: &str = include_str!("shaders/post/bloom_combine.frag");
+const COLOR_GRADING_FRAGMENT: &str = include_str!("shaders/post/color_grading.frag");
+const FXAA_FRAGMENT: &str = include_str!("shaders/post/fxaa.frag");
+
+/// Fragment stages of the built-in effects, compiled with `FULLSCREEN_VERTEX`.
+pub(crate) const EFFECT_FRAGMENTS: [&str; 6] = [
+    TONE_MAP_FRAGMENT,
+    BRIGHT_PASS_FRAGMENT,
+    BLUR_FRAGMENT,
+    BLOOM_COMBINE_FRAGMENT,
+    COLOR_GRADING_FRAGMENT,
+    FXAA_FRAGMENT,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToneMapOperator {
+    Reinhard,
+    /// Filmic curve with more contrast and saturation than Reinhard.
+    Aces,
+}
+
+impl ToneMapOperator {
+    pub(crate) fn id(self) -> i32 {
+        match self {
+            ToneMapOperator::Reinhard => 0,
+            ToneMapOperator::Aces => 1,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum PostEffect {
+    /// Maps HDR colour into the displayable range. Belongs after effects that work on HDR
+    /// values, such as bloom.
+    ToneMapping { operator: ToneMapOperator, exposure: f32 },
+    /// Makes whatever is brighter than `threshold` glow. Each pass blurs wider.
+    Bloom { threshold: f32, intensity: f32, passes: u32 },
+    /// Remaps colours through a lookup table laid out like `neutral_lut`, blended with the
+    /// original by `strength`. The LUT texture should use linear filtering.
+    ColorGrading { lut: Texture, strength: f32 },
+    /// Smooths jagged edges. Belongs last, on tone mapped colour.
+    Fxaa,
+}
+
+/// Effects applied in order to a screen after it has been drawn. Screens with effects draw
+/// into an HDR target first, so lighting may exceed 1.0 until tone mapping.
+#[derive(Clone, Default)]
+pub struct PostProcessStack {
+    pub(crate) effects: Vec<PostEffect>,
+    pub(crate) samples: u32,
+}
+
+impl PostProcessStack {
+    pub fn new() -> Self {
+        Self { effects: Vec::new(), samples: 1 }
+    }
+
+    pub fn with(mut self, effect: PostEffect) -> Self {
+        self.effects.push(effect);
+        self
+    }
+
+    /// Multisamples the scene before the effects run.
+    pub fn multisample(mut self, samples: u32) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn push(&mut self, effect: PostEffect) {
+        self.effects.push(effect);
+    }
+
+    pub fn effects(&self) -> &[PostEffect] {
+        &self.effects
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+}
+
+/// The identity lookup table for `PostEffect::ColorGrading`, `size` slices of `size`² pixels
+/// side by side. Grade a screenshot in an image editor, apply the same adjustments to this
+/// image, and load the result with `flip_vertically` to get a matching LUT.
+pub fn neutral_lut(size: u32) -> RgbaImage {
+    let max = (size.max(2) - 1) as f32;
+    RgbaImage::from_fn(size * size, size, |x, y| {
+        let red = (x % size) as f32 / max;
+        // Rows are stored top first, and green increases upwards once flipped.
+        let green = (size - 1 - y) as f32 / max;
+        let blue = (x / size) as f32 / max;
+        Rgba([(red * 255.0).round() as u8, (green * 255.0).round() as u8, (blue * 255.0).round() as u8, 255])
+    })
+}
+
+/// Programs of the built-in effects, in the order of `EFFECT_FRAGMENTS`.
+pub(crate) struct PostPrograms {
+    pub(crate) tone_map: Shader,
+    pub(crate) bright_pass: Shader,
+    pub(crate) blur: Shader,
+    pub(crate) bloom_combine: Shader,
+    pub(crate) color_grading: Shader,
+    pub(crate) fxaa: Shader,
+}
+
+impl PostPrograms {
+    pub(crate) fn new(shaders: Vec<Shader>) -> Self {
+        let [tone_map, bright_pass, blur, bloom_combine, color_grading, fxaa]: [Shader; 6] =
+            shaders.try_into().unwrap_or_else(|_| panic!("Expected a program for each built-in effect"));
+        Self { tone_map, bright_pass, blur, bloom_combine, color_grading, fxaa }
+    }
+}
+
+/// A screen's effects and the targets they render through.
+pub(crate) struct ScreenPost {
+    pub(crate) stack: PostProcessStack,
+    /// What the screen is drawn into before the effects.
+    pub(crate) scene: RenderTarget,
+    /// Effects alternate between these until the last one writes to the screen.
+    pub(crate) ping_pong: [RenderTarget; 2],
+    /// Half-resolution targets bloom blurs between, if the stack has bloom.
+    pub(crate) bloom: Option<[RenderTarget; 2]>,
+}
@@ -0,0 +1,156 @@
+use crate::{Texture, resource::{GpuObject, Handle, ResourceRef}, texture::FilterMode};
+
+/// Pixel format of a render target's color attachment.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorFormat {
+    Rgba8,
+    /// Half floats, for HDR rendering that is tone mapped later. GLES drivers without
+    /// `EXT_color_buffer_float` fall back to `Rgba8`.
+    Rgba16F,
+}
+
+impl ColorFormat {
+    /// Internal format, pixel format and pixel type.
+    pub(crate) fn to_gl(self) -> (u32, u32, u32) {
+        match self {
+            ColorFormat::Rgba8 => (gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE),
+            ColorFormat::Rgba16F => (gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderTargetDescriptor {
+    pub width: u32,
+    pub height: u32,
+    pub format: ColorFormat,
+    /// Adds a depth and stencil attachment, needed to draw meshes into the target.
+    pub depth: bool,
+    /// Samples per pixel. Above 1 the target renders multisampled and is resolved into its
+    /// color texture by `end_render_target`.
+    pub samples: u32,
+    /// How the color texture is sampled by materials showing it.
+    pub filter: FilterMode,
+}
+
+impl RenderTargetDescriptor {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            format: ColorFormat::Rgba8,
+            depth: true,
+            samples: 1,
+            filter: FilterMode::Linear,
+        }
+    }
+}
+
+/// An offscreen framebuffer whose color ends up in a texture, for mirrors, minimaps or
+/// screens-in-game. Draw into it between `begin_render_target` and `end_render_target`.
+#[derive(Clone)]
+pub struct RenderTarget {
+    pub(crate) resource: ResourceRef,
+    pub(crate) color: Texture,
+    pub(crate) format: ColorFormat,
+    pub(crate) samples: u32,
+}
+
+impl RenderTarget {
+    pub fn handle(&self) -> Handle {
+        self.resource.handle()
+    }
+
+    /// The rendered image. Valid once `end_render_target` has been called.
+    pub fn color_texture(&self) -> &Texture {
+        &self.color
+    }
+
+    pub fn width(&self) -> u32 {
+        self.color.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.color.height()
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width() as f32 / self.height() as f32
+    }
+
+    /// May differ from the descriptor's when the driver could not render to it.
+    pub fn format(&self) -> ColorFormat {
+        self.format
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub(crate) fn is_multisampled(&self) -> bool {
+        self.samples > 1
+    }
+}
+
+/// Attaches a render target's images to new framebuffers in the current context and checks
+/// that the result is complete. Returns the draw framebuffer and, when multisampling, the
+/// resolve framebuffer.
+pub(crate) fn create_framebuffers(object: &GpuObject, color: u32) -> Result<(u32, u32), String> {
+    let (depth, msaa_color) = match object {
+        GpuObject::RenderTarget { depth, msaa_color, .. } => (*depth, *msaa_color),
+        _ => panic!("Not a render target"),
+    };
+
+    let mut fbo = 0;
+    let mut resolve_fbo = 0;
+
+    unsafe {
+        gl::GenFramebuffers(1, &mut fbo);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+
+        if msaa_color != 0 {
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, msaa_color);
+        }
+        else {
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, color, 0);
+        }
+
+        if depth != 0 {
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, depth);
+        }
+
+        let mut status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+
+        if status == gl::FRAMEBUFFER_COMPLETE && msaa_color != 0 {
+            gl::GenFramebuffers(1, &mut resolve_fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, resolve_fbo);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, color, 0);
+            status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        }
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            gl::DeleteFramebuffers(1, &fbo);
+            if resolve_fbo != 0 {
+                gl::DeleteFramebuffers(1, &resolve_fbo);
+            }
+            return Err(format!("Render target framebuffer is incomplete (status 0x{:X})", status));
+        }
+    }
+
+    Ok((fbo, resolve_fbo))
+}
+
+/// Deletes the renderbuffers of a target that never made it into the registry.
+pub(crate) fn delete_renderbuffers(object: &GpuObject) {
+    if let GpuObject::RenderTarget { depth, msaa_color, .. } = object {
+        for renderbuffer in [*depth, *msaa_color] {
+            if renderbuffer != 0 {
+                unsafe {
+                    gl::DeleteRenderbuffers(1, &renderbuffer);
+                }
+            }
+        }
+    }
+}
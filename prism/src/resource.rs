@@ -0,0 +1,321 @@
+use std::{cell::RefCell, rc::Rc};
+
+/// Number of GL contexts a renderer can own: one per window.
+pub(crate) const MAX_CONTEXTS: usize = 2;
+
+/// Identifies a GPU resource owned by a renderer. A handle to a deleted resource never
+/// resolves again, even if its slot is reused.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum GpuObject {
+    Texture(u32),
+    /// Buffers are shared between contexts, vertex arrays are not, so each context gets its
+    /// own lazily created VAO.
+    Mesh { vbo: u32, ebo: u32, vaos: [u32; MAX_CONTEXTS] },
+    Program(u32),
+    Buffer(u32),
+    /// Renderbuffers are shared, framebuffers are per context like vertex arrays. `fbos`
+    /// draw into the attachments; `resolve_fbos` hold the color texture when multisampling.
+    RenderTarget { depth: u32, msaa_color: u32, fbos: [u32; MAX_CONTEXTS], resolve_fbos: [u32; MAX_CONTEXTS] },
+}
+
+impl GpuObject {
+    fn kind(&self) -> &'static str {
+        match self {
+            GpuObject::Texture(_) => "texture",
+            GpuObject::Mesh { .. } => "mesh",
+            GpuObject::Program(_) => "shader program",
+            GpuObject::Buffer(_) => "buffer",
+            GpuObject::RenderTarget { .. } => "render target",
+        }
+    }
+}
+
+struct Slot {
+    generation: u32,
+    ref_count: u32,
+    object: Option<GpuObject>,
+    label: String,
+}
+
+pub(crate) struct ResourceRegistry {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    /// Objects that can be deleted on any context.
+    pending_shared: Vec<GpuObject>,
+    /// Vertex arrays waiting for their owning context to become current.
+    pending_vaos: [Vec<u32>; MAX_CONTEXTS],
+    /// Framebuffers waiting for their owning context, likewise.
+    pending_framebuffers: [Vec<u32>; MAX_CONTEXTS],
+    /// Cleared when the renderer shuts down; its contexts are gone so nothing is queued anymore.
+    alive: bool,
+}
+
+pub(crate) type SharedRegistry = Rc<RefCell<ResourceRegistry>>;
+
+impl ResourceRegistry {
+    pub(crate) fn new_shared() -> SharedRegistry {
+        Rc::new(RefCell::new(Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            pending_shared: Vec::new(),
+            pending_vaos: Default::default(),
+            pending_framebuffers: Default::default(),
+            alive: true,
+        }))
+    }
+
+    pub(crate) fn get(&self, handle: Handle) -> Option<&GpuObject> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.object.as_ref())
+    }
+
+    pub(crate) fn get_mut(&mut self, handle: Handle) -> Option<&mut GpuObject> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.object.as_mut())
+    }
+
+    /// Swaps the object behind `handle`, queueing the old one for deletion.
+    pub(crate) fn replace(&mut self, handle: Handle, object: GpuObject) {
+        if let Some(old) = self.get_mut(handle).map(|current| std::mem::replace(current, object)) {
+            self.queue_deletion(old);
+        }
+    }
+
+    fn retain(&mut self, handle: Handle) {
+        if let Some(slot) = self.slots.get_mut(handle.index as usize).filter(|slot| slot.generation == handle.generation) {
+            slot.ref_count += 1;
+        }
+    }
+
+    fn release(&mut self, handle: Handle) {
+        let slot = match self.slots.get_mut(handle.index as usize).filter(|slot| slot.generation == handle.generation) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        slot.ref_count -= 1;
+        if slot.ref_count > 0 {
+            return;
+        }
+
+        let object = slot.object.take();
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.label.clear();
+        self.free.push(handle.index);
+
+        if let Some(object) = object {
+            self.queue_deletion(object);
+        }
+    }
+
+    fn queue_deletion(&mut self, object: GpuObject) {
+        if !self.alive {
+            return;
+        }
+
+        if let GpuObject::Mesh { vaos, .. } = object {
+            for (context, vao) in vaos.into_iter().enumerate() {
+                if vao != 0 {
+                    self.pending_vaos[context].push(vao);
+                }
+            }
+        }
+
+        if let GpuObject::RenderTarget { fbos, resolve_fbos, .. } = object {
+            for context in 0..MAX_CONTEXTS {
+                for fbo in [fbos[context], resolve_fbos[context]] {
+                    if fbo != 0 {
+                        self.pending_framebuffers[context].push(fbo);
+                    }
+                }
+            }
+        }
+
+        self.pending_shared.push(object);
+    }
+
+    /// Takes the objects that may be deleted while `context` is current: shared objects,
+    /// then the context's vertex arrays and framebuffers.
+    pub(crate) fn take_pending(&mut self, context: usize) -> (Vec<GpuObject>, Vec<u32>, Vec<u32>) {
+        (
+            std::mem::take(&mut self.pending_shared),
+            std::mem::take(&mut self.pending_vaos[context]),
+            std::mem::take(&mut self.pending_framebuffers[context]),
+        )
+    }
+
+    /// Describes every resource still referenced, for the leak report.
+    pub(crate) fn live_resources(&self) -> Vec<String> {
+        self.slots
+            .iter()
+            .filter_map(|slot| {
+                slot.object.as_ref().map(|object| format!("{} \"{}\" ({} references)", object.kind(), slot.label, slot.ref_count))
+            })
+            .collect()
+    }
+
+    /// Queues every object still referenced for deletion along with the rest, and stops queueing
+    /// deletions from resources dropped afterwards, since their contexts will be gone.
+    pub(crate) fn shut_down(&mut self) {
+        let live: Vec<GpuObject> = self.slots.iter_mut().filter_map(|slot| slot.object.take()).collect();
+        for object in live {
+            self.queue_deletion(object);
+        }
+
+        self.alive = false;
+    }
+}
+
+/// Counted reference to a registry entry. The entry is queued for deletion when the last
+/// reference is dropped.
+pub(crate) struct ResourceRef {
+    handle: Handle,
+    registry: SharedRegistry,
+}
+
+impl ResourceRef {
+    pub(crate) fn new(registry: &SharedRegistry, object: GpuObject, label: &str) -> Self {
+        let mut inner = registry.borrow_mut();
+
+        let index = match inner.free.pop() {
+            Some(index) => index,
+            None => {
+                inner.slots.push(Slot { generation: 0, ref_count: 0, object: None, label: String::new() });
+                inner.slots.len() as u32 - 1
+            }
+        };
+
+        let slot = &mut inner.slots[index as usize];
+        slot.ref_count = 1;
+        slot.object = Some(object);
+        slot.label = label.to_string();
+
+        let handle = Handle { index, generation: slot.generation };
+        drop(inner);
+
+        Self { handle, registry: registry.clone() }
+    }
+
+    pub(crate) fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    pub(crate) fn registry(&self) -> &SharedRegistry {
+        &self.registry
+    }
+
+    pub(crate) fn object(&self) -> Option<GpuObject> {
+        self.registry.borrow().get(self.handle).copied()
+    }
+
+    /// Whether other references to the entry are alive.
+    pub(crate) fn is_shared(&self) -> bool {
+        let registry = self.registry.borrow();
+        registry.slots.get(self.handle.index as usize).is_some_and(|slot| slot.generation == self.handle.generation && slot.ref_count > 1)
+    }
+}
+
+impl Clone for ResourceRef {
+    fn clone(&self) -> Self {
+        self.registry.borrow_mut().retain(self.handle);
+        Self { handle: self.handle, registry: self.registry.clone() }
+    }
+}
+
+impl Drop for ResourceRef {
+    fn drop(&mut self) {
+        self.registry.borrow_mut().release(self.handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reused_slots_get_a_new_generation() {
+        let registry = ResourceRegistry::new_shared();
+        let first = ResourceRef::new(&registry, GpuObject::Texture(1), "first");
+        let stale = first.handle();
+        drop(first);
+
+        let second = ResourceRef::new(&registry, GpuObject::Texture(2), "second");
+        assert_eq!(second.handle().index, stale.index);
+        assert_ne!(second.handle(), stale);
+
+        let registry = registry.borrow();
+        assert_eq!(registry.get(stale), None);
+        assert_eq!(registry.get(second.handle()), Some(&GpuObject::Texture(2)));
+    }
+
+    #[test]
+    fn clones_keep_the_object_alive() {
+        let registry = ResourceRegistry::new_shared();
+        let texture = ResourceRef::new(&registry, GpuObject::Texture(7), "texture");
+        assert!(!texture.is_shared());
+
+        let clone = texture.clone();
+        assert!(texture.is_shared());
+        drop(texture);
+
+        assert!(!clone.is_shared());
+        assert_eq!(clone.object(), Some(GpuObject::Texture(7)));
+        assert!(registry.borrow_mut().take_pending(0).0.is_empty());
+
+        drop(clone);
+        assert_eq!(registry.borrow_mut().take_pending(0).0, [GpuObject::Texture(7)]);
+        assert!(registry.borrow().live_resources().is_empty());
+    }
+
+    #[test]
+    fn per_context_objects_wait_for_their_context() {
+        let registry = ResourceRegistry::new_shared();
+        let mesh = ResourceRef::new(&registry, GpuObject::Mesh { vbo: 1, ebo: 2, vaos: [3, 0] }, "mesh");
+        let target = ResourceRef::new(&registry, GpuObject::RenderTarget { depth: 4, msaa_color: 0, fbos: [0, 5], resolve_fbos: [0, 6] }, "target");
+        drop(mesh);
+        drop(target);
+
+        let mut registry = registry.borrow_mut();
+        let (shared, vaos, framebuffers) = registry.take_pending(0);
+        assert_eq!(shared.len(), 2);
+        assert_eq!((vaos, framebuffers), (vec![3], vec![]));
+
+        let (shared, vaos, framebuffers) = registry.take_pending(1);
+        assert!(shared.is_empty());
+        assert_eq!((vaos, framebuffers), (vec![], vec![5, 6]));
+    }
+
+    #[test]
+    fn replaced_objects_are_queued() {
+        let registry = ResourceRegistry::new_shared();
+        let buffer = ResourceRef::new(&registry, GpuObject::Buffer(1), "buffer");
+        registry.borrow_mut().replace(buffer.handle(), GpuObject::Buffer(2));
+
+        assert_eq!(buffer.object(), Some(GpuObject::Buffer(2)));
+        assert_eq!(registry.borrow_mut().take_pending(0).0, [GpuObject::Buffer(1)]);
+    }
+
+    #[test]
+    fn nothing_is_queued_after_shutdown() {
+        let registry = ResourceRegistry::new_shared();
+        let leaked = ResourceRef::new(&registry, GpuObject::Texture(1), "leaked");
+        assert_eq!(registry.borrow().live_resources().len(), 1);
+
+        registry.borrow_mut().shut_down();
+        assert_eq!(registry.borrow_mut().take_pending(0).0, [GpuObject::Texture(1)]);
+        assert_eq!(leaked.object(), None);
+
+        drop(leaked);
+        assert!(registry.borrow_mut().take_pending(0).0.is_empty());
+    }
+}
@@ -0,0 +1,607 @@
+use std::{collections::{HashMap, HashSet}, ffi::CString, path::{Path, PathBuf}, time::{Duration, Instant, SystemTime}};
+
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+
+use crate::{glsl::{PreprocessedSource, ShaderDefines}, resource::{GpuObject, Handle, ResourceRef}};
+
+/// Vertex stage of the built-in Phong shader. Like all sources handed to prism, it has no
+/// `#version` line; the renderer adds the one matching its GL profile.
+pub const PHONG_VERTEX: &str = include_str!("shaders/phong.vert");
+pub const PHONG_FRAGMENT: &str = include_str!("shaders/phong.frag");
+
+/// Depth-only program the shadow pass draws casters with.
+pub(crate) const SHADOW_DEPTH_VERTEX: &str = include_str!("shaders/shadow_depth.vert");
+pub(crate) const SHADOW_DEPTH_FRAGMENT: &str = include_str!("shaders/shadow_depth.frag");
+
+/// Snippets every shader can `#include` by name.
+pub(crate) const BUILTIN_INCLUDES: &[(&str, &str)] = &[
+    ("camera.glsl", include_str!("shaders/camera.glsl")),
+    ("lighting.glsl", include_str!("shaders/lighting.glsl")),
+    ("shadow.glsl", include_str!("shaders/shadow.glsl")),
+];
+
+/// Where the stages of a shader variant family come from.
+#[derive(Clone, Debug)]
+pub enum ShaderSource {
+    Inline { vertex: String, fragment: String },
+    /// Variants built from files are hot reloaded along with other file shaders.
+    Files { vertex: PathBuf, fragment: PathBuf },
+}
+
+/// A vertex/fragment pair compiled on demand once per set of defines, e.g. with and without
+/// `HAS_TEXTURE`. Get variants through `PrismRenderer::shader_variant`.
+pub struct ShaderVariants {
+    pub(crate) source: ShaderSource,
+    pub(crate) variants: HashMap<ShaderDefines, Shader>,
+}
+
+impl ShaderVariants {
+    pub fn new(vertex_src: &str, fragment_src: &str) -> Self {
+        Self {
+            source: ShaderSource::Inline { vertex: vertex_src.to_string(), fragment: fragment_src.to_string() },
+            variants: HashMap::new(),
+        }
+    }
+
+    pub fn from_files(vertex_path: &Path, fragment_path: &Path) -> Self {
+        Self {
+            source: ShaderSource::Files { vertex: vertex_path.to_path_buf(), fragment: fragment_path.to_path_buf() },
+            variants: HashMap::new(),
+        }
+    }
+
+    /// Number of variants compiled so far.
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+}
+
+/// GLSL type of an active uniform or attribute.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UniformType {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    Int,
+    Bool,
+    Mat3,
+    Mat4,
+    Sampler2D,
+    SamplerCube,
+    /// Any other GL type, by its GL enum value.
+    Other(u32),
+}
+
+impl UniformType {
+    fn from_gl(kind: u32) -> Self {
+        match kind {
+            gl::FLOAT => UniformType::Float,
+            gl::FLOAT_VEC2 => UniformType::Vec2,
+            gl::FLOAT_VEC3 => UniformType::Vec3,
+            gl::FLOAT_VEC4 => UniformType::Vec4,
+            gl::INT => UniformType::Int,
+            gl::BOOL => UniformType::Bool,
+            gl::FLOAT_MAT3 => UniformType::Mat3,
+            gl::FLOAT_MAT4 => UniformType::Mat4,
+            gl::SAMPLER_2D | gl::SAMPLER_2D_SHADOW => UniformType::Sampler2D,
+            gl::SAMPLER_CUBE => UniformType::SamplerCube,
+            other => UniformType::Other(other),
+        }
+    }
+
+    fn is_sampler(&self) -> bool {
+        matches!(self, UniformType::Sampler2D | UniformType::SamplerCube)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UniformInfo {
+    /// Name without the `[0]` GL appends to arrays.
+    pub name: String,
+    pub kind: UniformType,
+    /// Number of elements; 1 unless the uniform is an array.
+    pub size: i32,
+    pub location: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct AttributeInfo {
+    pub name: String,
+    pub kind: UniformType,
+    pub location: i32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum UniformValue {
+    Float(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Vec4(Vec4),
+    Int(i32),
+    Bool(bool),
+    Mat3(Mat3),
+    Mat4(Mat4),
+    Sampler(i32),
+}
+
+impl UniformValue {
+    fn type_name(&self) -> &'static str {
+        match self {
+            UniformValue::Float(_) => "float",
+            UniformValue::Vec2(_) => "vec2",
+            UniformValue::Vec3(_) => "vec3",
+            UniformValue::Vec4(_) => "vec4",
+            UniformValue::Int(_) => "int",
+            UniformValue::Bool(_) => "bool",
+            UniformValue::Mat3(_) => "mat3",
+            UniformValue::Mat4(_) => "mat4",
+            UniformValue::Sampler(_) => "sampler",
+        }
+    }
+
+    fn matches(&self, kind: UniformType) -> bool {
+        match self {
+            UniformValue::Float(_) => kind == UniformType::Float,
+            UniformValue::Vec2(_) => kind == UniformType::Vec2,
+            UniformValue::Vec3(_) => kind == UniformType::Vec3,
+            UniformValue::Vec4(_) => kind == UniformType::Vec4,
+            // Samplers are set through glUniform1i, so an int is accepted for them too.
+            UniformValue::Int(_) => kind == UniformType::Int || kind.is_sampler(),
+            UniformValue::Bool(_) => kind == UniformType::Bool,
+            UniformValue::Mat3(_) => kind == UniformType::Mat3,
+            UniformValue::Mat4(_) => kind == UniformType::Mat4,
+            UniformValue::Sampler(_) => kind.is_sampler(),
+        }
+    }
+
+    unsafe fn upload(&self, location: i32) {
+        unsafe {
+            match self {
+                UniformValue::Float(v) => gl::Uniform1f(location, *v),
+                UniformValue::Vec2(v) => gl::Uniform2f(location, v.x, v.y),
+                UniformValue::Vec3(v) => gl::Uniform3f(location, v.x, v.y, v.z),
+                UniformValue::Vec4(v) => gl::Uniform4f(location, v.x, v.y, v.z, v.w),
+                UniformValue::Int(v) | UniformValue::Sampler(v) => gl::Uniform1i(location, *v),
+                UniformValue::Bool(v) => gl::Uniform1i(location, *v as i32),
+                UniformValue::Mat3(v) => gl::UniformMatrix3fv(location, 1, gl::FALSE, v.as_ref().as_ptr()),
+                UniformValue::Mat4(v) => gl::UniformMatrix4fv(location, 1, gl::FALSE, v.as_ref().as_ptr()),
+            }
+        }
+    }
+}
+
+/// Cloning a shader shares its program; each clone keeps its own uniform values.
+#[derive(Clone)]
+pub struct Shader {
+    pub(crate) resource: ResourceRef,
+    // Software shaders have no GL program and are shaded by the rasterizer from `uniforms`.
+    pub(crate) software: bool,
+    // Last value set for each uniform, re-applied when a hot reload swaps the program.
+    pub(crate) uniforms: HashMap<String, UniformValue>,
+    // Active uniforms by name, with array uniforms also under their `[0]` name.
+    reflected_uniforms: HashMap<String, UniformInfo>,
+    attributes: Vec<AttributeInfo>,
+    // Program the reflection was taken from; hot reloading swaps the program underneath.
+    reflected_program: u32,
+    // Mismatches already reported, so a setter called every frame warns only once.
+    warned: HashSet<String>,
+}
+
+impl Shader {
+    pub(crate) fn new(resource: ResourceRef, software: bool, uniforms: Vec<UniformInfo>, attributes: Vec<AttributeInfo>) -> Self {
+        let mut shader = Self {
+            resource,
+            software,
+            uniforms: HashMap::new(),
+            reflected_uniforms: HashMap::new(),
+            attributes,
+            reflected_program: 0,
+            warned: HashSet::new(),
+        };
+
+        shader.reflected_program = shader.program();
+        shader.set_reflection(uniforms);
+        shader
+    }
+
+    fn set_reflection(&mut self, uniforms: Vec<UniformInfo>) {
+        self.reflected_uniforms.clear();
+        for uniform in uniforms {
+            if uniform.size > 1 {
+                self.reflected_uniforms.insert(format!("{}[0]", uniform.name), uniform.clone());
+            }
+            self.reflected_uniforms.insert(uniform.name.clone(), uniform);
+        }
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.resource.handle()
+    }
+
+    pub(crate) fn program(&self) -> u32 {
+        match self.resource.object() {
+            Some(GpuObject::Program(id)) => id,
+            _ => 0,
+        }
+    }
+
+    /// Active uniforms of the linked program. Uniforms the compiler optimised out are absent.
+    /// After a hot reload this reflects the new program once a uniform has been set on it.
+    pub fn active_uniforms(&self) -> impl Iterator<Item = &UniformInfo> {
+        self.reflected_uniforms.iter().filter(|(name, uniform)| **name == uniform.name).map(|(_, uniform)| uniform)
+    }
+
+    pub fn uniform(&self, name: &str) -> Option<&UniformInfo> {
+        self.reflected_uniforms.get(name)
+    }
+
+    pub fn active_attributes(&self) -> &[AttributeInfo] {
+        &self.attributes
+    }
+
+    pub fn set_uniform_mat4(&mut self, name: &str, value: Mat4) {
+        self.set_uniform(name, UniformValue::Mat4(value));
+    }
+
+    pub fn set_uniform_mat3(&mut self, name: &str, value: Mat3) {
+        self.set_uniform(name, UniformValue::Mat3(value));
+    }
+
+    pub fn set_uniform_vec4(&mut self, name: &str, value: Vec4) {
+        self.set_uniform(name, UniformValue::Vec4(value));
+    }
+
+    pub fn set_uniform_vec3(&mut self, name: &str, value: Vec3) {
+        self.set_uniform(name, UniformValue::Vec3(value));
+    }
+
+    pub fn set_uniform_vec2(&mut self, name: &str, value: Vec2) {
+        self.set_uniform(name, UniformValue::Vec2(value));
+    }
+
+    pub fn set_uniform_float(&mut self, name: &str, value: f32) {
+        self.set_uniform(name, UniformValue::Float(value));
+    }
+
+    pub fn set_uniform_int(&mut self, name: &str, value: i32) {
+        self.set_uniform(name, UniformValue::Int(value));
+    }
+
+    pub fn set_uniform_bool(&mut self, name: &str, value: bool) {
+        self.set_uniform(name, UniformValue::Bool(value));
+    }
+
+    /// Points a sampler uniform at a texture unit.
+    pub fn set_uniform_sampler(&mut self, name: &str, unit: u32) {
+        self.set_uniform(name, UniformValue::Sampler(unit as i32));
+    }
+
+    /// Setting a uniform the program does not use is silently ignored, since the GLSL compiler
+    /// removes unused uniforms.
+    pub(crate) fn set_uniform(&mut self, name: &str, value: UniformValue) {
+        match self.uniforms.get_mut(name) {
+            Some(stored) => *stored = value,
+            None => {
+                self.uniforms.insert(name.to_string(), value);
+            }
+        }
+
+        if self.software {
+            return;
+        }
+
+        let program = self.program();
+        if program != self.reflected_program {
+            self.adopt_program(program);
+            return;
+        }
+
+        self.upload(program, name, value);
+    }
+
+    /// Reflects a program swapped in by a hot reload and gives it every uniform value set so far.
+    fn adopt_program(&mut self, program: u32) {
+        unsafe {
+            let uniforms = reflect_uniforms(program);
+            self.attributes = reflect_attributes(program);
+            self.set_reflection(uniforms);
+        }
+        self.reflected_program = program;
+        self.warned.clear();
+
+        let values: Vec<(String, UniformValue)> = self.uniforms.iter().map(|(name, value)| (name.clone(), *value)).collect();
+        for (name, value) in values {
+            self.upload(program, &name, value);
+        }
+    }
+
+    fn upload(&mut self, program: u32, name: &str, value: UniformValue) {
+        let uniform = match self.reflected_uniforms.get(name) {
+            Some(uniform) => uniform,
+            None => return,
+        };
+
+        if !value.matches(uniform.kind) {
+            if self.warned.insert(name.to_string()) {
+                eprintln!("Prism: uniform \"{}\" is a {:?}, but a {} was set", name, uniform.kind, value.type_name());
+            }
+            return;
+        }
+
+        unsafe {
+            gl::UseProgram(program);
+            value.upload(uniform.location);
+        }
+    }
+}
+
+// How often watched shader files are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+struct WatchedShader {
+    handle: Handle,
+    vertex_path: PathBuf,
+    fragment_path: PathBuf,
+    defines: ShaderDefines,
+    /// Every file the program was built from, includes too, with when it was last modified.
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+/// A watched shader whose sources changed on disk.
+pub(crate) struct ChangedShader {
+    pub(crate) handle: Handle,
+    pub(crate) vertex_path: PathBuf,
+    pub(crate) fragment_path: PathBuf,
+    pub(crate) defines: ShaderDefines,
+}
+
+/// Tracks the source files of shaders created from files, for hot reloading.
+pub(crate) struct ShaderWatcher {
+    enabled: bool,
+    last_poll: Option<Instant>,
+    shaders: Vec<WatchedShader>,
+}
+
+impl ShaderWatcher {
+    pub(crate) fn new() -> Self {
+        Self {
+            enabled: false,
+            last_poll: None,
+            shaders: Vec::new(),
+        }
+    }
+
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn watch(&mut self, handle: Handle, vertex_path: &Path, fragment_path: &Path, defines: &ShaderDefines, files: Vec<PathBuf>) {
+        self.shaders.push(WatchedShader {
+            handle,
+            vertex_path: vertex_path.to_path_buf(),
+            fragment_path: fragment_path.to_path_buf(),
+            defines: defines.clone(),
+            files: Vec::new(),
+        });
+        self.set_files(handle, files);
+    }
+
+    /// Replaces the files a shader depends on, after a reload may have changed its includes.
+    pub(crate) fn set_files(&mut self, handle: Handle, files: Vec<PathBuf>) {
+        if let Some(shader) = self.shaders.iter_mut().find(|shader| shader.handle == handle) {
+            shader.files = files.into_iter().map(|file| {
+                let modified = modified_time(&file);
+                (file, modified)
+            }).collect();
+        }
+    }
+
+    /// Shaders whose files changed since they were last compiled. Checks at most every
+    /// `WATCH_INTERVAL`; `is_alive` drops shaders that have since been deleted.
+    pub(crate) fn poll(&mut self, is_alive: impl Fn(Handle) -> bool) -> Vec<ChangedShader> {
+        if !self.enabled || self.last_poll.is_some_and(|last_poll| last_poll.elapsed() < WATCH_INTERVAL) {
+            return Vec::new();
+        }
+        self.last_poll = Some(Instant::now());
+
+        self.shaders.retain(|shader| is_alive(shader.handle));
+
+        let mut changed = Vec::new();
+        for shader in &mut self.shaders {
+            let modified: Vec<Option<SystemTime>> = shader.files.iter().map(|(file, _)| modified_time(file)).collect();
+
+            // Editors often replace files by deleting them first; wait until all exist again.
+            if modified.iter().any(Option::is_none) || shader.files.iter().zip(&modified).all(|((_, old), new)| old == new) {
+                continue;
+            }
+
+            for ((_, old), new) in shader.files.iter_mut().zip(modified) {
+                *old = new;
+            }
+
+            changed.push(ChangedShader {
+                handle: shader.handle,
+                vertex_path: shader.vertex_path.clone(),
+                fragment_path: shader.fragment_path.clone(),
+                defines: shader.defines.clone(),
+            });
+        }
+
+        changed
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+pub(crate) fn read_sources(vertex_path: &Path, fragment_path: &Path) -> Result<(String, String), String> {
+    let read = |path: &Path| std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.to_string_lossy(), e));
+    Ok((read(vertex_path)?, read(fragment_path)?))
+}
+
+/// Compiles and links a program, returning it with its reflected uniforms and attributes.
+/// Nothing is left behind on failure.
+/// Uniform blocks named in `block_bindings` are bound to their binding points.
+pub(crate) fn build_program(
+    vertex: &PreprocessedSource,
+    fragment: &PreprocessedSource,
+    block_bindings: &HashMap<String, u32>,
+) -> Result<(u32, Vec<UniformInfo>, Vec<AttributeInfo>), String> {
+    unsafe {
+        let vertex_shader = compile_shader(gl::VERTEX_SHADER, &vertex.source)
+            .map_err(|log| format!("Vertex shader compilation failed: {}{}", log, vertex.legend()))?;
+        let fragment_shader = match compile_shader(gl::FRAGMENT_SHADER, &fragment.source) {
+            Ok(shader) => shader,
+            Err(log) => {
+                gl::DeleteShader(vertex_shader);
+                return Err(format!("Fragment shader compilation failed: {}{}", log, fragment.legend()));
+            }
+        };
+
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vertex_shader);
+        gl::AttachShader(program, fragment_shader);
+        gl::LinkProgram(program);
+
+        gl::DetachShader(program, vertex_shader);
+        gl::DetachShader(program, fragment_shader);
+        gl::DeleteShader(vertex_shader);
+        gl::DeleteShader(fragment_shader);
+
+        let mut success = 0;
+        gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
+        if success == 0 {
+            let log = program_info_log(program);
+            gl::DeleteProgram(program);
+            return Err(format!("Shader program linking failed: {}", log));
+        }
+
+        // GLSL 3.30 and GLSL ES 3.00 have no `binding` layout qualifier, so blocks are bound here.
+        for (name, binding) in block_bindings {
+            let name = CString::new(name.as_str()).unwrap();
+            let index = gl::GetUniformBlockIndex(program, name.as_ptr());
+            if index != gl::INVALID_INDEX {
+                gl::UniformBlockBinding(program, index, *binding);
+            }
+        }
+
+        Ok((program, reflect_uniforms(program), reflect_attributes(program)))
+    }
+}
+
+unsafe fn compile_shader(kind: u32, source: &str) -> Result<u32, String> {
+    let source = CString::new(source).map_err(|_| "source contains a NUL byte".to_string())?;
+
+    unsafe {
+        let shader = gl::CreateShader(kind);
+        gl::ShaderSource(shader, 1, &source.as_ptr(), std::ptr::null());
+        gl::CompileShader(shader);
+
+        let mut success = 0;
+        gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+        if success == 0 {
+            let mut length = 0;
+            gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut length);
+            let mut buffer = vec![0u8; length.max(1) as usize];
+            let mut written = 0;
+            gl::GetShaderInfoLog(shader, buffer.len() as i32, &mut written, buffer.as_mut_ptr() as *mut _);
+            gl::DeleteShader(shader);
+
+            buffer.truncate(written.max(0) as usize);
+            return Err(String::from_utf8_lossy(&buffer).trim_end().to_string());
+        }
+
+        Ok(shader)
+    }
+}
+
+unsafe fn program_info_log(program: u32) -> String {
+    unsafe {
+        let mut length = 0;
+        gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut length);
+        let mut buffer = vec![0u8; length.max(1) as usize];
+        let mut written = 0;
+        gl::GetProgramInfoLog(program, buffer.len() as i32, &mut written, buffer.as_mut_ptr() as *mut _);
+
+        buffer.truncate(written.max(0) as usize);
+        String::from_utf8_lossy(&buffer).trim_end().to_string()
+    }
+}
+
+unsafe fn reflect_uniforms(program: u32) -> Vec<UniformInfo> {
+    unsafe {
+        let mut count = 0;
+        let mut max_length = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_length);
+
+        let mut uniforms = Vec::with_capacity(count.max(0) as usize);
+        for index in 0..count.max(0) as u32 {
+            let mut buffer = vec![0u8; max_length.max(1) as usize];
+            let (mut length, mut size, mut kind) = (0, 0, 0);
+            gl::GetActiveUniform(program, index, buffer.len() as i32, &mut length, &mut size, &mut kind, buffer.as_mut_ptr() as *mut _);
+            buffer.truncate(length.max(0) as usize);
+
+            let location = match CString::new(buffer.clone()) {
+                Ok(name) => gl::GetUniformLocation(program, name.as_ptr()),
+                Err(_) => -1,
+            };
+
+            // Members of uniform blocks have no location and are not set through these setters.
+            if location < 0 {
+                continue;
+            }
+
+            let name = String::from_utf8_lossy(&buffer);
+            uniforms.push(UniformInfo {
+                name: name.strip_suffix("[0]").unwrap_or(&name).to_string(),
+                kind: UniformType::from_gl(kind),
+                size,
+                location,
+            });
+        }
+
+        uniforms
+    }
+}
+
+unsafe fn reflect_attributes(program: u32) -> Vec<AttributeInfo> {
+    unsafe {
+        let mut count = 0;
+        let mut max_length = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTES, &mut count);
+        gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_length);
+
+        let mut attributes = Vec::with_capacity(count.max(0) as usize);
+        for index in 0..count.max(0) as u32 {
+            let mut buffer = vec![0u8; max_length.max(1) as usize];
+            let (mut length, mut size, mut kind) = (0, 0, 0);
+            gl::GetActiveAttrib(program, index, buffer.len() as i32, &mut length, &mut size, &mut kind, buffer.as_mut_ptr() as *mut _);
+            buffer.truncate(length.max(0) as usize);
+
+            let location = match CString::new(buffer.clone()) {
+                Ok(name) => gl::GetAttribLocation(program, name.as_ptr()),
+                Err(_) => -1,
+            };
+
+            attributes.push(AttributeInfo {
+                name: String::from_utf8_lossy(&buffer).into_owned(),
+                kind: UniformType::from_gl(kind),
+                location,
+            });
+        }
+
+        attributes.sort_by_key(|attribute| attribute.location);
+        attributes
+    }
+}
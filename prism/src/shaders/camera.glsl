@@ -0,0 +1,6 @@
+// Mirrors prism::uniform_block::CameraBlock
+layout (std140) uniform Camera {
+    mat4 view;
+    mat4 projection;
+    vec3 viewPos;
+};
@@ -0,0 +1,81 @@
+#include "shadow.glsl"
+
+#define MAX_LIGHTS 8
+
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+
+struct Light {
+    vec3 position;
+    int type;
+    vec3 direction;
+    float constant;
+    vec3 ambient;
+    float linear;
+    vec3 diffuse;
+    float quadratic;
+    vec3 specular;
+    // Cosines of the cone half-angles
+    float innerCone;
+    float outerCone;
+    bool castsShadows;
+};
+
+// Mirrors prism::uniform_block::LightsBlock
+layout (std140) uniform Lights {
+    Light lights[MAX_LIGHTS];
+    int lightCount;
+};
+
+struct Material {
+    vec3 ambient;
+    vec3 diffuse;
+    vec3 specular;
+    float shininess;
+};
+
+vec3 phong(Light light, Material material, vec3 normal, vec3 fragPos, vec3 viewPos) {
+    vec3 lightDir;
+    float attenuation = 1.0;
+
+    if (light.type == LIGHT_DIRECTIONAL) {
+        lightDir = normalize(-light.direction);
+    } else {
+        lightDir = normalize(light.position - fragPos);
+        float dist = length(light.position - fragPos);
+        attenuation = 1.0 / max(light.constant + light.linear * dist + light.quadratic * dist * dist, 1.0);
+    }
+
+    if (light.type == LIGHT_SPOT) {
+        float theta = dot(lightDir, normalize(-light.direction));
+        attenuation *= clamp((theta - light.outerCone) / max(light.innerCone - light.outerCone, 0.0001), 0.0, 1.0);
+    }
+
+    vec3 ambient = light.ambient * material.ambient;
+
+    // diffuse
+    vec3 norm = normalize(normal);
+    float diff = max(dot(norm, lightDir), 0.0);
+    vec3 diffuse = light.diffuse * (diff * material.diffuse);
+
+    // specular
+    vec3 viewDir = normalize(viewPos - fragPos);
+    vec3 reflectDir = reflect(-lightDir, norm);
+    float spec = pow(max(dot(viewDir, reflectDir), 0.0), material.shininess);
+    vec3 specular = light.specular * (spec * material.specular);
+
+    // Shadows leave the ambient term alone
+    float shadow = light.castsShadows ? shadowFactor(fragPos, norm, lightDir) : 1.0;
+
+    return (ambient + (diffuse + specular) * shadow) * attenuation;
+}
+
+// Sum of every light in the Lights block
+vec3 phongLights(Material material, vec3 normal, vec3 fragPos, vec3 viewPos) {
+    vec3 result = vec3(0.0);
+    for (int i = 0; i < lightCount; ++i) {
+        result += phong(lights[i], material, normal, fragPos, viewPos);
+    }
+    return result;
+}
@@ -0,0 +1,27 @@
+#include "camera.glsl"
+#include "lighting.glsl"
+
+out vec4 FragColor;
+
+in vec3 FragPos;
+in vec3 Normal;
+in vec2 TexCoords;
+in vec4 InstanceColor;
+
+uniform sampler2D texture_0;
+
+uniform Material material;
+// 1 - opacity. Materials that set neither get 0 for both.
+uniform float transparency;
+uniform float alphaCutoff;
+
+void main() {
+    vec4 albedo = texture(texture_0, TexCoords) * InstanceColor;
+    float alpha = albedo.a * (1.0 - transparency);
+    if (alpha < alphaCutoff) {
+        discard;
+    }
+
+    vec3 result = phongLights(material, Normal, FragPos, viewPos) * albedo.rgb;
+    FragColor = vec4(result, alpha);
+}
@@ -0,0 +1,25 @@
+#include "camera.glsl"
+
+layout (location = 0) in vec3 aPos;
+layout (location = 1) in vec3 aNormal;
+layout (location = 2) in vec2 aTexCoords;
+// Per instance in instanced draws, an untransformed white instance otherwise.
+layout (location = 8) in mat4 aInstanceTransform;
+layout (location = 12) in vec4 aInstanceColor;
+
+out vec3 FragPos;
+out vec3 Normal;
+out vec2 TexCoords;
+out vec4 InstanceColor;
+
+uniform mat4 model;
+
+void main() {
+    mat4 world = aInstanceTransform * model;
+    FragPos = vec3(world * vec4(aPos, 1.0));
+    Normal = mat3(transpose(inverse(world))) * aNormal;
+    TexCoords = aTexCoords;
+    InstanceColor = aInstanceColor;
+
+    gl_Position = projection * view * vec4(FragPos, 1.0);
+}
@@ -0,0 +1,12 @@
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D source;
+uniform sampler2D bloom;
+uniform float intensity;
+
+void main() {
+    vec4 color = texture(source, TexCoords);
+    FragColor = vec4(color.rgb + texture(bloom, TexCoords).rgb * intensity, color.a);
+}
@@ -0,0 +1,17 @@
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D source;
+// One texel along the blur axis
+uniform vec2 direction;
+
+// 9-tap Gaussian, using linear filtering to read two texels per sample
+void main() {
+    vec3 color = texture(source, TexCoords).rgb * 0.2270270270;
+    color += texture(source, TexCoords + direction * 1.3846153846).rgb * 0.3162162162;
+    color += texture(source, TexCoords - direction * 1.3846153846).rgb * 0.3162162162;
+    color += texture(source, TexCoords + direction * 3.2307692308).rgb * 0.0702702703;
+    color += texture(source, TexCoords - direction * 3.2307692308).rgb * 0.0702702703;
+    FragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,14 @@
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D source;
+uniform float threshold;
+
+// Keeps only what is brighter than the threshold, fading in softly above it.
+void main() {
+    vec3 color = texture(source, TexCoords).rgb;
+    float brightness = max(color.r, max(color.g, color.b));
+    float contribution = max(brightness - threshold, 0.0) / max(brightness, 0.0001);
+    FragColor = vec4(color * contribution, 1.0);
+}
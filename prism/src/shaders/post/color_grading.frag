@@ -0,0 +1,31 @@
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D source;
+// A strip of lutSize slices of lutSize² texels: red across each slice, green up it, blue
+// from slice to slice. See prism::post::neutral_lut.
+uniform sampler2D lut;
+uniform float lutSize;
+uniform float strength;
+
+vec3 grade(vec3 color) {
+    color = clamp(color, 0.0, 1.0);
+
+    float blue = color.b * (lutSize - 1.0);
+    float slice = floor(blue);
+    float nextSlice = min(slice + 1.0, lutSize - 1.0);
+
+    // Sample texel centers so neighbouring slices do not bleed in
+    vec2 texel = vec2(1.0 / (lutSize * lutSize), 1.0 / lutSize);
+    vec2 inSlice = vec2(color.r, color.g) * (lutSize - 1.0) * texel + texel * 0.5;
+
+    vec3 lower = texture(lut, inSlice + vec2(slice / lutSize, 0.0)).rgb;
+    vec3 upper = texture(lut, inSlice + vec2(nextSlice / lutSize, 0.0)).rgb;
+    return mix(lower, upper, blue - slice);
+}
+
+void main() {
+    vec4 color = texture(source, TexCoords);
+    FragColor = vec4(mix(color.rgb, grade(color.rgb), strength), color.a);
+}
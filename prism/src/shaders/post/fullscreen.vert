@@ -0,0 +1,8 @@
+out vec2 TexCoords;
+
+// One triangle covering the whole target, generated from the vertex index alone.
+void main() {
+    vec2 position = vec2(float((gl_VertexID & 1) << 2), float((gl_VertexID & 2) << 1)) - 1.0;
+    TexCoords = position * 0.5 + 0.5;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,42 @@
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D source;
+uniform vec2 texelSize;
+
+#define FXAA_REDUCE_MIN (1.0 / 128.0)
+#define FXAA_REDUCE_MUL (1.0 / 8.0)
+#define FXAA_SPAN_MAX 8.0
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+// Blurs along edges found from the luma of the four diagonal neighbours.
+void main() {
+    vec4 center = texture(source, TexCoords);
+    float lumaM = luma(center.rgb);
+    float lumaNW = luma(texture(source, TexCoords + vec2(-1.0, -1.0) * texelSize).rgb);
+    float lumaNE = luma(texture(source, TexCoords + vec2(1.0, -1.0) * texelSize).rgb);
+    float lumaSW = luma(texture(source, TexCoords + vec2(-1.0, 1.0) * texelSize).rgb);
+    float lumaSE = luma(texture(source, TexCoords + vec2(1.0, 1.0) * texelSize).rgb);
+
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    vec2 direction = vec2(-((lumaNW + lumaNE) - (lumaSW + lumaSE)), (lumaNW + lumaSW) - (lumaNE + lumaSE));
+    float reduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2(-FXAA_SPAN_MAX), vec2(FXAA_SPAN_MAX)) * texelSize;
+
+    vec3 near = 0.5 * (
+        texture(source, TexCoords + direction * (1.0 / 3.0 - 0.5)).rgb +
+        texture(source, TexCoords + direction * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 far = near * 0.5 + 0.25 * (
+        texture(source, TexCoords - direction * 0.5).rgb +
+        texture(source, TexCoords + direction * 0.5).rgb);
+
+    float lumaFar = luma(far);
+    FragColor = vec4(lumaFar < lumaMin || lumaFar > lumaMax ? near : far, center.a);
+}
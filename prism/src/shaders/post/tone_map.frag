@@ -0,0 +1,28 @@
+#define TONE_MAP_REINHARD 0
+#define TONE_MAP_ACES 1
+
+out vec4 FragColor;
+
+in vec2 TexCoords;
+
+uniform sampler2D source;
+uniform int toneMapOperator;
+uniform float exposure;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main() {
+    vec4 hdr = texture(source, TexCoords);
+    vec3 color = hdr.rgb * exposure;
+
+    if (toneMapOperator == TONE_MAP_ACES) {
+        color = aces(color);
+    } else {
+        color = color / (color + vec3(1.0));
+    }
+
+    FragColor = vec4(color, hdr.a);
+}
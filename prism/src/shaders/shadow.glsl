@@ -0,0 +1,42 @@
+// Mirrors prism::uniform_block::ShadowBlock
+layout (std140) uniform Shadow {
+    mat4 lightSpace;
+    float shadowDepthBias;
+    float shadowSlopeBias;
+    int shadowPcfRadius;
+    bool shadowEnabled;
+};
+
+uniform sampler2D shadowMap;
+// Set per draw from the material's render state
+uniform bool receiveShadows;
+
+// 1.0 where fully lit, 0.0 where fully in shadow
+float shadowFactor(vec3 fragPos, vec3 normal, vec3 lightDir) {
+    if (!shadowEnabled || !receiveShadows) {
+        return 1.0;
+    }
+
+    vec4 lightPos = lightSpace * vec4(fragPos, 1.0);
+    vec3 coords = lightPos.xyz / lightPos.w * 0.5 + 0.5;
+
+    // Outside the shadow map, or beyond its far plane
+    if (coords.z > 1.0 || any(lessThan(coords.xy, vec2(0.0))) || any(greaterThan(coords.xy, vec2(1.0)))) {
+        return 1.0;
+    }
+
+    float bias = max(shadowSlopeBias * (1.0 - dot(normalize(normal), lightDir)), shadowDepthBias);
+    vec2 texel = 1.0 / vec2(textureSize(shadowMap, 0));
+
+    // Percentage-closer filtering over a (2r + 1)² texel square
+    float lit = 0.0;
+    for (int x = -shadowPcfRadius; x <= shadowPcfRadius; ++x) {
+        for (int y = -shadowPcfRadius; y <= shadowPcfRadius; ++y) {
+            float depth = texture(shadowMap, coords.xy + vec2(x, y) * texel).r;
+            lit += coords.z - bias > depth ? 0.0 : 1.0;
+        }
+    }
+
+    float samples = float((2 * shadowPcfRadius + 1) * (2 * shadowPcfRadius + 1));
+    return lit / samples;
+}
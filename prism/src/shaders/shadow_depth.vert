@@ -0,0 +1,10 @@
+#include "shadow.glsl"
+
+layout (location = 0) in vec3 aPos;
+layout (location = 8) in mat4 aInstanceTransform;
+
+uniform mat4 model;
+
+void main() {
+    gl_Position = lightSpace * aInstanceTransform * model * vec4(aPos, 1.0);
+}
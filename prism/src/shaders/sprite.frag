@@ -0,0 +1,10 @@
+out vec4 FragColor;
+
+in vec2 TexCoords;
+in vec4 Color;
+
+uniform sampler2D texture_0;
+
+void main() {
+    FragColor = texture(texture_0, TexCoords) * Color;
+}
@@ -0,0 +1,93 @@
+use glam::{Mat4, Vec3};
+
+use crate::uniform_block::{Light, LightKind};
+
+// Keeps a spot light's near plane from getting so close that depth precision collapses.
+const MIN_SPOT_NEAR: f32 = 0.05;
+
+/// The view-projection a shadow map is rendered with, so that it covers the sphere at
+/// `center` with `radius`. `None` for point lights, which would need a cube map.
+pub fn light_space_matrix(light: &Light, center: Vec3, radius: f32) -> Option<Mat4> {
+    let direction = light.direction.try_normalize()?;
+    // Any up vector works, as long as it is not parallel to the light.
+    let up = if direction.cross(Vec3::Y).length_squared() < 1e-6 { Vec3::Z } else { Vec3::Y };
+
+    match light.kind {
+        LightKind::Directional => {
+            let eye = center - direction * radius * 2.0;
+            let view = Mat4::look_at_rh(eye, center, up);
+            let projection = Mat4::orthographic_rh_gl(-radius, radius, -radius, radius, radius, radius * 3.0);
+            Some(projection * view)
+        }
+        LightKind::Spot => {
+            let distance = light.position.distance(center);
+            let near = (distance - radius).max(MIN_SPOT_NEAR);
+            let far = (distance + radius).max(near * 2.0);
+            let fov = (light.outer_cone * 2.0).clamp(1f32.to_radians(), 179f32.to_radians());
+
+            let view = Mat4::look_at_rh(light.position, light.position + direction, up);
+            Some(Mat4::perspective_rh_gl(fov, 1.0, near, far) * view)
+        }
+        LightKind::Point => None,
+    }
+}
+
+/// Depth-only framebuffer a screen's shadow pass renders into.
+pub(crate) struct ShadowMap {
+    fbo: u32,
+    pub(crate) depth: u32,
+    pub(crate) resolution: u32,
+}
+
+impl ShadowMap {
+    pub(crate) fn create(resolution: u32) -> Result<Self, String> {
+        let mut fbo = 0;
+        let mut depth = 0;
+        let size = resolution as i32;
+
+        unsafe {
+            gl::GenTextures(1, &mut depth);
+            gl::BindTexture(gl::TEXTURE_2D, depth);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT24 as i32, size, size, 0, gl::DEPTH_COMPONENT, gl::UNSIGNED_INT, std::ptr::null());
+            // GLES cannot filter depth textures linearly; the shader filters with PCF instead.
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, depth, 0);
+            let none = gl::NONE;
+            gl::DrawBuffers(1, &none);
+            gl::ReadBuffer(gl::NONE);
+
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+            let map = Self { fbo, depth, resolution };
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                map.delete();
+                return Err(format!("Shadow map framebuffer is incomplete (status 0x{:X})", status));
+            }
+
+            Ok(map)
+        }
+    }
+
+    pub(crate) fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, self.resolution as i32, self.resolution as i32);
+        }
+    }
+
+    /// Must be called with the owning context current.
+    pub(crate) fn delete(&self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(1, &self.depth);
+        }
+    }
+}
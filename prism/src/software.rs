@@ -0,0 +1,628 @@
+use std::path::Path;
+
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+use image::{Rgba, RgbaImage};
+
+use crate::{Shader, Texture, Vertex, backend::{RenderBackend, Screen}, bounds::Aabb, config::PrismConfig, material::{BlendMode, CompareFunction, CullMode, Material, RenderState}, mesh::Mesh, resource::{self, GpuObject, ResourceRef, ResourceRegistry, SharedRegistry}, shader::UniformValue, texture::{self, FilterMode, TextureDescriptor, WrapMode}, uniform_block::{CameraBlock, Light, LightKind, LightsBlock}, vertex_layout::{BufferUsage, VertexLayout}};
+
+struct SoftwareTarget {
+    color: RgbaImage,
+    depth: Vec<f32>,
+}
+
+impl SoftwareTarget {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            color: RgbaImage::new(width, height),
+            depth: vec![1.0; (width * height) as usize],
+        }
+    }
+
+    fn clear(&mut self, color: Vec4) {
+        let pixel = to_rgba(color);
+        for p in self.color.pixels_mut() {
+            *p = pixel;
+        }
+        self.depth.fill(1.0);
+    }
+}
+
+/// CPU rasterizer producing the same image as the GL backend with the built-in Phong shaders.
+/// Shader sources are not interpreted; the camera and lights blocks and the material uniforms
+/// set on the shader feed a fixed Phong model, including opacity and alpha cutoff. The render
+/// state's blending, culling and depth test apply, but there is no stencil buffer and no
+/// shadow pass, so stencil state is ignored and nothing is shadowed. Only meshes created by
+/// this renderer can be drawn, and without instancing every instance colour is white.
+pub struct SoftwareRenderer {
+    upper_target: SoftwareTarget,
+    lower_target: SoftwareTarget,
+    current_screen: Option<Screen>,
+    resources: SharedRegistry,
+    // Indexed by texture id - 1, so that 0 stays "no texture" as in GL.
+    textures: Vec<Option<SoftwareTexture>>,
+    camera_block: CameraBlock,
+    lights_block: LightsBlock,
+}
+
+struct SoftwareTexture {
+    image: RgbaImage,
+    descriptor: TextureDescriptor,
+}
+
+impl Default for SoftwareRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SoftwareRenderer {
+    pub fn new() -> Self {
+        Self::with_config(&PrismConfig::default())
+    }
+
+    /// Only the screen sizes are used; there is no window to scale into.
+    pub fn with_config(config: &PrismConfig) -> Self {
+        let (upper_width, upper_height) = config.screen_size(Screen::Upper);
+        let (lower_width, lower_height) = config.screen_size(Screen::Lower);
+
+        Self {
+            upper_target: SoftwareTarget::new(upper_width, upper_height),
+            lower_target: SoftwareTarget::new(lower_width, lower_height),
+            current_screen: None,
+            resources: ResourceRegistry::new_shared(),
+            textures: Vec::new(),
+            camera_block: CameraBlock::default(),
+            lights_block: LightsBlock::default(),
+        }
+    }
+
+    pub fn framebuffer(&self, screen: Screen) -> &RgbaImage {
+        match screen {
+            Screen::Upper => &self.upper_target.color,
+            Screen::Lower => &self.lower_target.color,
+        }
+    }
+
+    fn collect_garbage(&mut self) {
+        let (objects, _, _) = self.resources.borrow_mut().take_pending(0);
+        for object in objects {
+            if let GpuObject::Texture(id) = object {
+                self.textures[id as usize - 1] = None;
+            }
+        }
+    }
+
+    fn upload_texture(&mut self, image: RgbaImage, descriptor: &TextureDescriptor, label: &str) -> Result<Texture, String> {
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            return Err(format!("Texture \"{}\" has no pixels", label));
+        }
+
+        self.textures.push(Some(SoftwareTexture { image: texture::orient(image, descriptor), descriptor: descriptor.clone() }));
+
+        let id = self.textures.len() as u32;
+        Ok(Texture {
+            resource: ResourceRef::new(&self.resources, GpuObject::Texture(id), label),
+            width,
+            height,
+        })
+    }
+
+    fn current_target(&mut self) -> &mut SoftwareTarget {
+        match self.current_screen {
+            Some(Screen::Upper) => &mut self.upper_target,
+            Some(Screen::Lower) => &mut self.lower_target,
+            None => panic!("SoftwareRenderer::begin_screen must be called before rendering"),
+        }
+    }
+}
+
+impl RenderBackend for SoftwareRenderer {
+    fn create_mesh(&mut self, vertices: Vec<Vertex>, indices: Vec<u32>, textures: Vec<Texture>) -> Mesh {
+        let object = GpuObject::Mesh { vbo: 0, ebo: 0, vaos: [0; resource::MAX_CONTEXTS] };
+
+        Mesh {
+            resource: ResourceRef::new(&self.resources, object, "software mesh"),
+            layout: VertexLayout::standard(),
+            usage: BufferUsage::Static,
+            vertex_count: vertices.len(),
+            index_count: indices.len(),
+            bounds: Aabb::from_points(vertices.iter().map(|vertex| vertex.position)),
+            vertices,
+            indices,
+            textures,
+            model: Mat4::IDENTITY,
+            position: Vec3::ZERO,
+            rotation: Vec3::ZERO,
+            scale: Vec3::ONE,
+        }
+    }
+
+    fn create_texture_from_image(&mut self, image: RgbaImage, descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        let label = format!("{}x{} image texture", image.width(), image.height());
+        self.upload_texture(image, descriptor, &label)
+    }
+
+    fn create_texture_with_descriptor(&mut self, path: &Path, descriptor: &TextureDescriptor) -> Result<Texture, String> {
+        let image = texture::load_file(path)?;
+        self.upload_texture(image, descriptor, &path.to_string_lossy())
+    }
+
+    fn create_shader(&mut self, _vertex_src: &str, _fragment_src: &str) -> Result<Shader, String> {
+        let resource = ResourceRef::new(&self.resources, GpuObject::Program(0), "software shader");
+        Ok(Shader::new(resource, true, Vec::new(), Vec::new()))
+    }
+
+    fn draw_mesh(&mut self, mesh: &Mesh, material: &mut Material) {
+        if mesh.vertices.len() != mesh.vertex_count {
+            panic!("Mesh has no vertices on the CPU; SoftwareRenderer only draws meshes it created");
+        }
+
+        material.apply_params();
+        let shader = &material.shader;
+
+        let texture = material.texture("texture_0")
+            .or(mesh.textures.first())
+            .and_then(|t| (t.raw_id() as usize).checked_sub(1))
+            .and_then(|i| self.textures.get(i))
+            .and_then(|t| t.as_ref());
+
+        let shading = PhongShading {
+            view_pos: self.camera_block.view_position,
+            material_ambient: shader.uniform_vec3("material.ambient"),
+            material_diffuse: shader.uniform_vec3("material.diffuse"),
+            material_specular: shader.uniform_vec3("material.specular"),
+            material_shininess: shader.uniform_float("material.shininess"),
+            transparency: shader.uniform_float("transparency"),
+            alpha_cutoff: shader.uniform_float("alphaCutoff"),
+            lights: self.lights_block.active_lights(),
+            texture,
+        };
+
+        let view_projection = self.camera_block.projection * self.camera_block.view;
+        let normal_matrix = Mat3::from_mat4(mesh.model).inverse().transpose();
+
+        let transformed: Vec<ClipVertex> = mesh.vertices.iter().map(|v| {
+            let world = mesh.model * v.position.extend(1.0);
+            ClipVertex {
+                clip: view_projection * world,
+                world: world.truncate(),
+                normal: normal_matrix * v.normal,
+                tex_coords: v.tex_coords,
+            }
+        }).collect();
+
+        let target = match self.current_screen {
+            Some(Screen::Upper) => &mut self.upper_target,
+            Some(Screen::Lower) => &mut self.lower_target,
+            None => panic!("SoftwareRenderer::begin_screen must be called before drawing meshes"),
+        };
+
+        for triangle in mesh.indices.chunks_exact(3) {
+            // Triangles with indices past the vertices are skipped rather than read out of bounds.
+            let [Some(a), Some(b), Some(c)] = [0, 1, 2].map(|i| transformed.get(triangle[i] as usize).copied()) else {
+                continue;
+            };
+            let polygon = clip_near_plane([a, b, c]);
+
+            for i in 1..polygon.len().saturating_sub(1) {
+                rasterize_triangle(target, &polygon[0], &polygon[i], &polygon[i + 1], &shading, &material.state);
+            }
+        }
+    }
+
+    fn set_camera_block(&mut self, block: &CameraBlock) {
+        self.camera_block = *block;
+    }
+
+    fn set_lights_block(&mut self, block: &LightsBlock) {
+        self.lights_block = block.clone();
+    }
+
+    fn clear_screen(&mut self, color: Vec4) {
+        self.current_target().clear(color);
+    }
+
+    fn begin_screen(&mut self, screen: Screen) {
+        self.collect_garbage();
+        self.current_screen = Some(screen);
+    }
+
+    fn end_screen(&mut self, _screen: Screen) {
+        self.current_screen = None;
+    }
+
+    fn capture_screen(&mut self, screen: Screen) -> RgbaImage {
+        self.framebuffer(screen).clone()
+    }
+}
+
+impl Shader {
+    fn uniform_vec3(&self, name: &str) -> Vec3 {
+        match self.uniforms.get(name) {
+            Some(UniformValue::Vec3(v)) => *v,
+            _ => Vec3::ZERO,
+        }
+    }
+
+    fn uniform_float(&self, name: &str) -> f32 {
+        match self.uniforms.get(name) {
+            Some(UniformValue::Float(v)) => *v,
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ClipVertex {
+    clip: Vec4,
+    world: Vec3,
+    normal: Vec3,
+    tex_coords: Vec2,
+}
+
+impl ClipVertex {
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+        ClipVertex {
+            clip: self.clip.lerp(other.clip, t),
+            world: self.world.lerp(other.world, t),
+            normal: self.normal.lerp(other.normal, t),
+            tex_coords: self.tex_coords.lerp(other.tex_coords, t),
+        }
+    }
+}
+
+struct PhongShading<'a> {
+    view_pos: Vec3,
+    material_ambient: Vec3,
+    material_diffuse: Vec3,
+    material_specular: Vec3,
+    material_shininess: f32,
+    transparency: f32,
+    alpha_cutoff: f32,
+    lights: &'a [Light],
+    texture: Option<&'a SoftwareTexture>,
+}
+
+impl PhongShading<'_> {
+    /// `None` where the fragment is discarded by the alpha cutoff.
+    fn shade(&self, frag_pos: Vec3, normal: Vec3, tex_coords: Vec2) -> Option<Vec4> {
+        let albedo = self.sample(tex_coords);
+        let alpha = albedo.w * (1.0 - self.transparency);
+        if alpha < self.alpha_cutoff {
+            return None;
+        }
+
+        let lit: Vec3 = self.lights.iter().map(|light| self.phong(light, frag_pos, normal)).sum();
+        Some((lit * albedo.truncate()).extend(alpha))
+    }
+
+    fn phong(&self, light: &Light, frag_pos: Vec3, normal: Vec3) -> Vec3 {
+        let (light_dir, mut attenuation) = match light.kind {
+            LightKind::Directional => (-light.direction.normalize_or_zero(), 1.0),
+            LightKind::Point | LightKind::Spot => {
+                ((light.position - frag_pos).normalize_or_zero(), light.attenuation(light.position.distance(frag_pos)))
+            }
+        };
+
+        if light.kind == LightKind::Spot {
+            let (inner, outer) = (light.inner_cone.cos(), light.outer_cone.cos());
+            let theta = light_dir.dot(-light.direction.normalize_or_zero());
+            attenuation *= ((theta - outer) / (inner - outer).max(0.0001)).clamp(0.0, 1.0);
+        }
+
+        let ambient = light.ambient * self.material_ambient;
+
+        // diffuse
+        let norm = normal.normalize_or_zero();
+        let diff = norm.dot(light_dir).max(0.0);
+        let diffuse = light.diffuse * (diff * self.material_diffuse);
+
+        // specular
+        let view_dir = (self.view_pos - frag_pos).normalize_or_zero();
+        let reflect_dir = -light_dir - 2.0 * norm.dot(-light_dir) * norm;
+        let spec = view_dir.dot(reflect_dir).max(0.0).powf(self.material_shininess);
+        let specular = light.specular * (spec * self.material_specular);
+
+        (ambient + diffuse + specular) * attenuation
+    }
+
+    // An unbound sampler reads as opaque black, as it does in GL.
+    fn sample(&self, tex_coords: Vec2) -> Vec4 {
+        match self.texture {
+            Some(texture) => texture.sample(tex_coords),
+            None => Vec4::W,
+        }
+    }
+}
+
+impl SoftwareTexture {
+    // There are no screen-space derivatives here, so mipmaps are never used and the
+    // magnification filter applies at every distance.
+    fn sample(&self, tex_coords: Vec2) -> Vec4 {
+        let (width, height) = (self.image.width() as i64, self.image.height() as i64);
+        let u = tex_coords.x * width as f32;
+        let v = tex_coords.y * height as f32;
+
+        match self.descriptor.mag_filter {
+            FilterMode::Nearest => self.texel(u.floor() as i64, v.floor() as i64),
+            FilterMode::Linear => {
+                let (u, v) = (u - 0.5, v - 0.5);
+                let (x, y) = (u.floor(), v.floor());
+                let (fx, fy) = (u - x, v - y);
+                let (x, y) = (x as i64, y as i64);
+
+                let top = self.texel(x, y).lerp(self.texel(x + 1, y), fx);
+                let bottom = self.texel(x, y + 1).lerp(self.texel(x + 1, y + 1), fx);
+                top.lerp(bottom, fy)
+            }
+        }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec4 {
+        let x = wrap(x, self.image.width() as i64, self.descriptor.wrap_s);
+        let y = wrap(y, self.image.height() as i64, self.descriptor.wrap_t);
+        let texel = self.image.get_pixel(x as u32, y as u32);
+        let color = Vec4::new(texel[0] as f32, texel[1] as f32, texel[2] as f32, texel[3] as f32) / 255.0;
+
+        // Alpha is always linear.
+        if self.descriptor.srgb {
+            color.truncate().map(srgb_to_linear).extend(color.w)
+        }
+        else {
+            color
+        }
+    }
+}
+
+fn wrap(coord: i64, size: i64, mode: WrapMode) -> i64 {
+    match mode {
+        WrapMode::ClampToEdge => coord.clamp(0, size - 1),
+        WrapMode::Repeat => coord.rem_euclid(size),
+        WrapMode::MirroredRepeat => {
+            let period = coord.rem_euclid(size * 2);
+            if period < size { period } else { size * 2 - 1 - period }
+        }
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    }
+    else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn to_rgba(color: Vec4) -> Rgba<u8> {
+    let c = (color.clamp(Vec4::ZERO, Vec4::ONE) * 255.0).round();
+    Rgba([c.x as u8, c.y as u8, c.z as u8, c.w as u8])
+}
+
+fn from_rgba(pixel: &Rgba<u8>) -> Vec4 {
+    Vec4::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32, pixel[3] as f32) / 255.0
+}
+
+// The same equations as the blend factors the GL backend sets.
+fn blend(mode: BlendMode, src: Vec4, dst: Vec4) -> Vec4 {
+    let (a, rgb, dst_rgb) = (src.w, src.truncate(), dst.truncate());
+    match mode {
+        BlendMode::Opaque => src,
+        BlendMode::Alpha => (rgb * a + dst_rgb * (1.0 - a)).extend(a + dst.w * (1.0 - a)),
+        BlendMode::Premultiplied => (rgb + dst_rgb * (1.0 - a)).extend(a + dst.w * (1.0 - a)),
+        BlendMode::Additive => (rgb * a + dst_rgb).extend(dst.w),
+        BlendMode::Multiply => (rgb * dst_rgb).extend(dst.w),
+    }
+}
+
+fn passes(function: CompareFunction, value: f32, stored: f32) -> bool {
+    match function {
+        CompareFunction::Never => false,
+        CompareFunction::Less => value < stored,
+        CompareFunction::Equal => value == stored,
+        CompareFunction::LessEqual => value <= stored,
+        CompareFunction::Greater => value > stored,
+        CompareFunction::NotEqual => value != stored,
+        CompareFunction::GreaterEqual => value >= stored,
+        CompareFunction::Always => true,
+    }
+}
+
+fn edge(a: Vec2, b: Vec2, p: Vec2) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+// Sutherland-Hodgman against the near plane (z >= -w); the far plane is handled by the depth test.
+fn clip_near_plane(triangle: [ClipVertex; 3]) -> Vec<ClipVertex> {
+    let mut output = Vec::with_capacity(4);
+
+    for i in 0..3 {
+        let current = triangle[i];
+        let next = triangle[(i + 1) % 3];
+        let current_distance = current.clip.z + current.clip.w;
+        let next_distance = next.clip.z + next.clip.w;
+
+        if current_distance >= 0.0 {
+            output.push(current);
+        }
+
+        if (current_distance >= 0.0) != (next_distance >= 0.0) {
+            let t = current_distance / (current_distance - next_distance);
+            output.push(current.lerp(&next, t));
+        }
+    }
+
+    output
+}
+
+fn rasterize_triangle(target: &mut SoftwareTarget, a: &ClipVertex, b: &ClipVertex, c: &ClipVertex, shading: &PhongShading, state: &RenderState) {
+    let width = target.color.width();
+    let height = target.color.height();
+
+    let to_window = |v: &ClipVertex| {
+        let inv_w = 1.0 / v.clip.w;
+        let ndc = v.clip.truncate() * inv_w;
+        (
+            Vec2::new((ndc.x + 1.0) * 0.5 * width as f32, (1.0 - ndc.y) * 0.5 * height as f32),
+            (ndc.z + 1.0) * 0.5,
+            inv_w,
+        )
+    };
+
+    let (p0, z0, w0) = to_window(a);
+    let (p1, z1, w1) = to_window(b);
+    let (p2, z2, w2) = to_window(c);
+
+    let area = edge(p0, p1, p2);
+    if area == 0.0 || !area.is_finite() {
+        return;
+    }
+
+    // Window coordinates have y pointing down, so counter-clockwise front faces have a negative area.
+    let front_facing = area < 0.0;
+    match state.cull {
+        CullMode::Back if !front_facing => return,
+        CullMode::Front if front_facing => return,
+        _ => {}
+    }
+
+    // Pixels centred exactly on an edge belong only to the triangle it is a top or left edge
+    // of, as in GL, so that blended meshes do not cover them twice.
+    let top_left = |from: Vec2, to: Vec2| {
+        let direction = (to - from) * area.signum();
+        direction.y < 0.0 || (direction.y == 0.0 && direction.x > 0.0)
+    };
+    let owned = [top_left(p1, p2), top_left(p2, p0), top_left(p0, p1)];
+
+    let min = p0.min(p1).min(p2).floor().max(Vec2::ZERO);
+    let max = p0.max(p1).max(p2).ceil().min(Vec2::new(width as f32, height as f32));
+
+    for y in min.y as u32..max.y as u32 {
+        for x in min.x as u32..max.x as u32 {
+            let p = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let b0 = edge(p1, p2, p) / area;
+            let b1 = edge(p2, p0, p) / area;
+            let b2 = edge(p0, p1, p) / area;
+            if b0 < 0.0 || b1 < 0.0 || b2 < 0.0 {
+                continue;
+            }
+            if [b0, b1, b2].iter().zip(owned).any(|(&weight, owned)| weight == 0.0 && !owned) {
+                continue;
+            }
+
+            let depth = b0 * z0 + b1 * z1 + b2 * z2;
+            let index = (y * width + x) as usize;
+            if !(0.0..=1.0).contains(&depth) || (state.depth_test && !passes(state.depth_compare, depth, target.depth[index])) {
+                continue;
+            }
+
+            // perspective-correct weights
+            let q0 = b0 * w0;
+            let q1 = b1 * w1;
+            let q2 = b2 * w2;
+            let sum = q0 + q1 + q2;
+            let (q0, q1, q2) = (q0 / sum, q1 / sum, q2 / sum);
+
+            let world = a.world * q0 + b.world * q1 + c.world * q2;
+            let normal = a.normal * q0 + b.normal * q1 + c.normal * q2;
+            let tex_coords = a.tex_coords * q0 + b.tex_coords * q1 + c.tex_coords * q2;
+
+            // Discarded fragments leave the depth buffer alone too.
+            let Some(color) = shading.shade(world, normal, tex_coords) else {
+                continue;
+            };
+
+            // GL skips depth writes without a depth test.
+            if state.depth_test && state.depth_write {
+                target.depth[index] = depth;
+            }
+            let color = blend(state.blend, color, from_rgba(target.color.get_pixel(x, y)));
+            target.color.put_pixel(x, y, to_rgba(color));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAR: Vec4 = Vec4::new(0.0, 0.0, 1.0, 1.0);
+    const LIT: Rgba<u8> = Rgba([255, 128, 64, 255]);
+
+    /// A 4x4 upper screen lit only by ambient light, and a material showing it through a
+    /// white texture.
+    fn setup() -> (SoftwareRenderer, Material) {
+        let mut config = PrismConfig::default();
+        config.upper.width = 4;
+        config.upper.height = 4;
+        let mut renderer = SoftwareRenderer::with_config(&config);
+
+        let light = Light::directional(Vec3::NEG_Z, Vec3::new(1.0, 0.5, 0.25), Vec3::ZERO, Vec3::ZERO);
+        renderer.set_lights_block(&LightsBlock::new(vec![light]));
+
+        let shader = renderer.create_shader("", "").unwrap();
+        let white = renderer.create_texture_from_rgba(1, 1, &[255; 4], &TextureDescriptor::default()).unwrap();
+        let mut material = Material::new(&shader);
+        material.set_phong(Vec3::ONE, Vec3::ZERO, Vec3::ZERO, 1.0);
+        material.set_texture("texture_0", &white);
+
+        renderer.begin_screen(Screen::Upper);
+        renderer.clear_screen(CLEAR);
+        (renderer, material)
+    }
+
+    /// Vertices at the corners of the screen, in clip space since the camera is the identity.
+    fn corners(renderer: &mut SoftwareRenderer, indices: Vec<u32>) -> Mesh {
+        let vertices = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+            .map(|(x, y)| Vertex::new(Vec3::new(x, y, 0.0), Vec3::Z, Vec2::ZERO))
+            .to_vec();
+        renderer.create_mesh(vertices, indices, Vec::new())
+    }
+
+    #[test]
+    fn triangle_matches_golden_image() {
+        let (mut renderer, mut material) = setup();
+        let mesh = corners(&mut renderer, vec![0, 1, 2]);
+        renderer.draw_mesh(&mesh, &mut material);
+
+        // The lower left half. The diagonal passes through the centres of the pixels on it,
+        // which belong to the triangle above since it is a right edge of this one.
+        let golden = RgbaImage::from_fn(4, 4, |x, y| if y > x { LIT } else { to_rgba(CLEAR) });
+        assert_eq!(renderer.capture_screen(Screen::Upper), golden);
+    }
+
+    #[test]
+    fn opacity_blends_with_the_screen() {
+        let (mut renderer, mut material) = setup();
+        material.state = RenderState::blended(BlendMode::Alpha);
+        material.set_opacity(0.5);
+        let mesh = corners(&mut renderer, vec![0, 1, 2, 2, 1, 3]);
+        renderer.draw_mesh(&mesh, &mut material);
+
+        let blended = Rgba([128, 64, 159, 255]);
+        assert!(renderer.framebuffer(Screen::Upper).pixels().all(|pixel| *pixel == blended));
+    }
+
+    #[test]
+    fn alpha_cutoff_discards_colour_and_depth() {
+        let (mut renderer, mut material) = setup();
+        material.set_opacity(0.5);
+        material.set_alpha_cutoff(0.6);
+        let mesh = corners(&mut renderer, vec![0, 1, 2, 2, 1, 3]);
+        renderer.draw_mesh(&mesh, &mut material);
+
+        assert!(renderer.framebuffer(Screen::Upper).pixels().all(|pixel| *pixel == to_rgba(CLEAR)));
+        assert!(renderer.upper_target.depth.iter().all(|&depth| depth == 1.0));
+    }
+
+    #[test]
+    fn out_of_range_indices_skip_their_triangle() {
+        let (mut renderer, mut material) = setup();
+        let mesh = corners(&mut renderer, vec![1, 3, 9, 0, 1, 2]);
+        renderer.draw_mesh(&mesh, &mut material);
+
+        assert_eq!(*renderer.framebuffer(Screen::Upper).get_pixel(0, 3), LIT);
+        assert_eq!(*renderer.framebuffer(Screen::Upper).get_pixel(3, 0), to_rgba(CLEAR));
+    }
+}
@@ -0,0 +1,454 @@
+use glam::{Mat4, Vec2, Vec4};
+
+use crate::{Rect, Texture, atlas::TextureRegion, font::TextLayout, resource::Handle};
+
+pub(crate) const SPRITE_VERTEX: &str = include_str!("shaders/sprite.vert");
+pub(crate) const SPRITE_FRAGMENT: &str = include_str!("shaders/sprite.frag");
+
+/// Floats per vertex: position, texture coordinates and colour.
+pub(crate) const SPRITE_VERTEX_FLOATS: usize = 8;
+
+/// A textured or plain coloured quad in pixel space, with a top-left origin and y pointing down.
+#[derive(Clone)]
+pub struct Sprite {
+    /// Plain `color` when `None`.
+    pub region: Option<TextureRegion>,
+    /// Where `origin` ends up on screen, in pixels.
+    pub position: Vec2,
+    pub size: Vec2,
+    /// Point the sprite is positioned and rotated around, as a fraction of its size: (0, 0)
+    /// is its top-left corner, (0.5, 0.5) its center.
+    pub origin: Vec2,
+    /// Clockwise on screen, in radians.
+    pub rotation: f32,
+    /// Multiplies the texture; white leaves it unchanged.
+    pub color: Vec4,
+    /// Sprites with a higher z are drawn over lower ones.
+    pub z: i32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl Sprite {
+    /// The region at its pixel size, with its top-left corner at the origin.
+    pub fn new(region: TextureRegion) -> Self {
+        let size = Vec2::new(region.width() as f32, region.height() as f32);
+        Self { region: Some(region), size, ..Self::colored(size, Vec4::ONE) }
+    }
+
+    pub fn colored(size: Vec2, color: Vec4) -> Self {
+        Self {
+            region: None,
+            position: Vec2::ZERO,
+            size,
+            origin: Vec2::ZERO,
+            rotation: 0.0,
+            color,
+            z: 0,
+            flip_x: false,
+            flip_y: false,
+        }
+    }
+
+    pub fn at(mut self, position: Vec2) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn sized(mut self, size: Vec2) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_origin(mut self, origin: Vec2) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn rotated(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn tinted(mut self, color: Vec4) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn layer(mut self, z: i32) -> Self {
+        self.z = z;
+        self
+    }
+
+    pub fn flipped(mut self, flip_x: bool, flip_y: bool) -> Self {
+        self.flip_x = flip_x;
+        self.flip_y = flip_y;
+        self
+    }
+}
+
+/// A region drawn as a resizable panel: the corners keep their size, the edges stretch along
+/// one axis and the center along both. Borders are in pixels of the region.
+#[derive(Clone)]
+pub struct NineSlice {
+    pub region: TextureRegion,
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+impl NineSlice {
+    pub fn new(region: TextureRegion, left: u32, right: u32, top: u32, bottom: u32) -> Self {
+        Self { region, left, right, top, bottom }
+    }
+
+    /// The same border on every side.
+    pub fn uniform(region: TextureRegion, border: u32) -> Self {
+        Self::new(region, border, border, border, border)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SpriteVertex {
+    position: Vec2,
+    tex_coords: Vec2,
+    color: Vec4,
+}
+
+struct Quad {
+    texture: Option<Texture>,
+    vertices: [SpriteVertex; 4],
+    z: i32,
+}
+
+/// Consecutive quads sharing a texture, drawn with one call.
+pub(crate) struct SpriteRun {
+    pub(crate) texture: Option<Texture>,
+    pub(crate) first_index: usize,
+    pub(crate) index_count: usize,
+}
+
+/// Collects 2D quads to draw with `PrismRenderer::draw_sprite_batch`. Quads are sorted by z,
+/// and within a z by texture, so that each texture on a layer costs one draw call; quads on
+/// the same layer that overlap and must be drawn in a particular order need different z.
+/// The batch keeps its quads until `clear`, so static UI can be drawn every frame as is.
+pub struct SpriteBatch {
+    quads: Vec<Quad>,
+    projection: Option<Mat4>,
+}
+
+impl SpriteBatch {
+    pub fn new() -> Self {
+        Self { quads: Vec::new(), projection: None }
+    }
+
+    /// Replaces the default pixel-space projection, which maps (0, 0) to the top-left
+    /// corner and the size of what is being drawn into to the bottom-right one.
+    pub fn set_projection(&mut self, projection: Option<Mat4>) {
+        self.projection = projection;
+    }
+
+    pub fn projection(&self) -> Option<Mat4> {
+        self.projection
+    }
+
+    pub fn len(&self) -> usize {
+        self.quads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quads.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.quads.clear();
+    }
+
+    pub fn draw(&mut self, sprite: &Sprite) {
+        let uv = match &sprite.region {
+            Some(region) => region.uv(),
+            None => Rect::new(0.0, 0.0, 1.0, 1.0),
+        };
+
+        // The top of the quad shows the top of the region, which has the higher v.
+        let (mut u0, mut u1) = (uv.x, uv.x + uv.width);
+        let (mut v_top, mut v_bottom) = (uv.y + uv.height, uv.y);
+        if sprite.flip_x {
+            std::mem::swap(&mut u0, &mut u1);
+        }
+        if sprite.flip_y {
+            std::mem::swap(&mut v_top, &mut v_bottom);
+        }
+
+        let pivot = sprite.origin * sprite.size;
+        // Positive angles turn clockwise, as y points down.
+        let (sin, cos) = sprite.rotation.sin_cos();
+        let place = |corner: Vec2| {
+            let local = corner - pivot;
+            sprite.position + Vec2::new(local.x * cos - local.y * sin, local.x * sin + local.y * cos)
+        };
+
+        let corners = [
+            (Vec2::ZERO, Vec2::new(u0, v_top)),
+            (Vec2::new(sprite.size.x, 0.0), Vec2::new(u1, v_top)),
+            (sprite.size, Vec2::new(u1, v_bottom)),
+            (Vec2::new(0.0, sprite.size.y), Vec2::new(u0, v_bottom)),
+        ];
+
+        self.quads.push(Quad {
+            texture: sprite.region.as_ref().map(|region| region.texture().clone()),
+            vertices: corners.map(|(corner, tex_coords)| SpriteVertex { position: place(corner), tex_coords, color: sprite.color }),
+            z: sprite.z,
+        });
+    }
+
+    /// A plain axis-aligned rectangle.
+    pub fn draw_rect(&mut self, rect: Rect, color: Vec4, z: i32) {
+        self.draw(&Sprite::colored(rect.size(), color).at(rect.origin()).layer(z));
+    }
+
+    /// Stretches `region` over `rect`.
+    pub fn draw_region(&mut self, region: &TextureRegion, rect: Rect, color: Vec4, z: i32) {
+        self.draw(&Sprite::new(region.clone()).at(rect.origin()).sized(rect.size()).tinted(color).layer(z));
+    }
+
+    /// Draws `panel` over `rect`. When `rect` is smaller than the borders, they shrink to fit.
+    pub fn draw_nine_slice(&mut self, panel: &NineSlice, rect: Rect, color: Vec4, z: i32) {
+        let region = &panel.region;
+        let (width, height) = (region.width() as f32, region.height() as f32);
+        let (left, right) = (panel.left as f32, panel.right as f32);
+        let (top, bottom) = (panel.top as f32, panel.bottom as f32);
+
+        let shrink_x = (rect.width / (left + right)).min(1.0);
+        let shrink_y = (rect.height / (top + bottom)).min(1.0);
+
+        // Column and row edges, in source pixels and on screen.
+        let source_x = [0.0, left, width - right, width];
+        let source_y = [0.0, top, height - bottom, height];
+        let dest_x = [rect.x, rect.x + left * shrink_x, rect.x + rect.width - right * shrink_x, rect.x + rect.width];
+        let dest_y = [rect.y, rect.y + top * shrink_y, rect.y + rect.height - bottom * shrink_y, rect.y + rect.height];
+
+        let uv = region.uv();
+        let u = |x: f32| uv.x + x / width * uv.width;
+        // Source rows count down from the region's top, which has the highest v.
+        let v = |y: f32| uv.y + uv.height - y / height * uv.height;
+
+        for row in 0..3 {
+            for column in 0..3 {
+                let (x0, x1) = (dest_x[column], dest_x[column + 1]);
+                let (y0, y1) = (dest_y[row], dest_y[row + 1]);
+                if x1 <= x0 || y1 <= y0 {
+                    continue;
+                }
+
+                let (u0, u1) = (u(source_x[column]), u(source_x[column + 1]));
+                let (v0, v1) = (v(source_y[row]), v(source_y[row + 1]));
+                let vertex = |position: Vec2, tex_coords: Vec2| SpriteVertex { position, tex_coords, color };
+
+                self.quads.push(Quad {
+                    texture: Some(region.texture().clone()),
+                    vertices: [
+                        vertex(Vec2::new(x0, y0), Vec2::new(u0, v0)),
+                        vertex(Vec2::new(x1, y0), Vec2::new(u1, v0)),
+                        vertex(Vec2::new(x1, y1), Vec2::new(u1, v1)),
+                        vertex(Vec2::new(x0, y1), Vec2::new(u0, v1)),
+                    ],
+                    z,
+                });
+            }
+        }
+    }
+
+    /// Draws laid out text with its top-left corner at `position`. `color` tints outline
+    /// fonts, which are white, and multiplies the colours of bitmap fonts.
+    pub fn draw_text(&mut self, text: &TextLayout, position: Vec2, color: Vec4, z: i32) {
+        for (region, rect) in &text.glyphs {
+            let rect = Rect::new(rect.x + position.x, rect.y + position.y, rect.width, rect.height);
+            self.draw_region(region, rect, color, z);
+        }
+    }
+
+    /// Interleaved vertices, indices and the runs to draw them in, sorted for drawing.
+    pub(crate) fn build(&self) -> (Vec<f32>, Vec<u32>, Vec<SpriteRun>) {
+        let key = |quad: &Quad| (quad.z, quad.texture.as_ref().map(|texture| texture.handle()));
+        let mut order: Vec<&Quad> = self.quads.iter().collect();
+        // Stable, so quads sharing a layer and texture keep their order.
+        order.sort_by_key(|quad| key(quad));
+
+        let mut vertices = Vec::with_capacity(order.len() * 4 * SPRITE_VERTEX_FLOATS);
+        let mut indices = Vec::with_capacity(order.len() * 6);
+        let mut runs: Vec<SpriteRun> = Vec::new();
+        let mut current: Option<Option<Handle>> = None;
+
+        for (i, quad) in order.iter().enumerate() {
+            for vertex in &quad.vertices {
+                vertices.extend_from_slice(&[vertex.position.x, vertex.position.y, vertex.tex_coords.x, vertex.tex_coords.y]);
+                vertices.extend_from_slice(&vertex.color.to_array());
+            }
+
+            let base = (i * 4) as u32;
+            indices.extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 3, base]);
+
+            let texture = quad.texture.as_ref().map(|texture| texture.handle());
+            match runs.last_mut() {
+                // Layers change the key too, but a texture shared across them still batches.
+                Some(run) if current == Some(texture) => run.index_count += 6,
+                _ => runs.push(SpriteRun { texture: quad.texture.clone(), first_index: i * 6, index_count: 6 }),
+            }
+            current = Some(texture);
+        }
+
+        (vertices, indices, runs)
+    }
+}
+
+impl Default for SpriteBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::resource::{GpuObject, ResourceRef, ResourceRegistry, SharedRegistry};
+
+    fn texture(registry: &SharedRegistry, id: u32, width: u32, height: u32) -> Texture {
+        Texture { resource: ResourceRef::new(registry, GpuObject::Texture(id), "texture"), width, height }
+    }
+
+    /// Positions and texture coordinates of the corners of quad `index` of a built batch,
+    /// clockwise from the top-left one.
+    fn corners(vertices: &[f32], index: usize) -> [(Vec2, Vec2); 4] {
+        std::array::from_fn(|corner| {
+            let start = (index * 4 + corner) * SPRITE_VERTEX_FLOATS;
+            let vertex = &vertices[start..start + SPRITE_VERTEX_FLOATS];
+            (Vec2::new(vertex[0], vertex[1]), Vec2::new(vertex[2], vertex[3]))
+        })
+    }
+
+    fn assert_corners(actual: [(Vec2, Vec2); 4], positions: [(f32, f32); 4], tex_coords: [(f32, f32); 4]) {
+        for ((position, uv), (expected_position, expected_uv)) in actual.into_iter().zip(positions.into_iter().zip(tex_coords)) {
+            assert!(position.abs_diff_eq(expected_position.into(), 1e-5), "position {} != {:?}", position, expected_position);
+            assert!(uv.abs_diff_eq(expected_uv.into(), 1e-5), "uv {} != {:?}", uv, expected_uv);
+        }
+    }
+
+    #[test]
+    fn quads_are_sorted_by_layer_then_texture_and_runs_merge() {
+        let registry = ResourceRegistry::new_shared();
+        let first = TextureRegion::new(texture(&registry, 1, 8, 8));
+        let second = TextureRegion::new(texture(&registry, 2, 8, 8));
+        let red = Vec4::new(1.0, 0.0, 0.0, 1.0);
+        let green = Vec4::new(0.0, 1.0, 0.0, 1.0);
+
+        let mut batch = SpriteBatch::new();
+        batch.draw(&Sprite::new(second.clone()).tinted(red));
+        batch.draw(&Sprite::new(first.clone()));
+        batch.draw(&Sprite::colored(Vec2::ONE, Vec4::ONE));
+        batch.draw(&Sprite::new(second.clone()).layer(1));
+        batch.draw(&Sprite::new(second.clone()).tinted(green));
+        assert_eq!(batch.len(), 5);
+
+        let (vertices, indices, runs) = batch.build();
+        assert_eq!(vertices.len(), 5 * 4 * SPRITE_VERTEX_FLOATS);
+        assert_eq!(&indices[6..12], &[4, 5, 6, 6, 7, 4]);
+
+        // Plain quads sort before textured ones, and the second texture's run carries on
+        // into the next layer.
+        let summary: Vec<_> = runs.iter().map(|run| (run.texture.as_ref().map(Texture::handle), run.first_index, run.index_count)).collect();
+        assert_eq!(summary, [(None, 0, 6), (Some(first.texture().handle()), 6, 6), (Some(second.texture().handle()), 12, 18)]);
+
+        // Quads sharing a layer and texture keep the order they were drawn in.
+        let color = |quad: usize| &vertices[quad * 4 * SPRITE_VERTEX_FLOATS + 4..quad * 4 * SPRITE_VERTEX_FLOATS + 8];
+        assert_eq!(color(2), red.to_array());
+        assert_eq!(color(3), green.to_array());
+        assert_eq!(color(4), Vec4::ONE.to_array());
+    }
+
+    #[test]
+    fn sprites_rotate_clockwise_around_their_origin() {
+        let mut batch = SpriteBatch::new();
+        batch.draw(&Sprite::colored(Vec2::new(4.0, 2.0), Vec4::ONE).at(Vec2::new(10.0, 10.0)).with_origin(Vec2::splat(0.5)).rotated(FRAC_PI_2));
+
+        let (vertices, _, _) = batch.build();
+        assert_corners(
+            corners(&vertices, 0),
+            [(11.0, 8.0), (11.0, 12.0), (9.0, 12.0), (9.0, 8.0)],
+            [(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)],
+        );
+    }
+
+    #[test]
+    fn flips_swap_the_texture_coordinates() {
+        let registry = ResourceRegistry::new_shared();
+        let region = TextureRegion::from_pixels(texture(&registry, 1, 4, 4), 0, 0, 2, 2, false);
+        let positions = [(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)];
+
+        let mut batch = SpriteBatch::new();
+        batch.draw(&Sprite::new(region.clone()));
+        batch.draw(&Sprite::new(region.clone()).flipped(true, false));
+        batch.draw(&Sprite::new(region).flipped(true, true));
+
+        let (vertices, _, _) = batch.build();
+        assert_corners(corners(&vertices, 0), positions, [(0.0, 0.5), (0.5, 0.5), (0.5, 0.0), (0.0, 0.0)]);
+        assert_corners(corners(&vertices, 1), positions, [(0.5, 0.5), (0.0, 0.5), (0.0, 0.0), (0.5, 0.0)]);
+        assert_corners(corners(&vertices, 2), positions, [(0.5, 0.0), (0.0, 0.0), (0.0, 0.5), (0.5, 0.5)]);
+    }
+
+    #[test]
+    fn nine_slice_corners_keep_their_size() {
+        let registry = ResourceRegistry::new_shared();
+        let panel = NineSlice::uniform(TextureRegion::new(texture(&registry, 1, 12, 12)), 4);
+
+        let mut batch = SpriteBatch::new();
+        batch.draw_nine_slice(&panel, Rect::new(0.0, 0.0, 40.0, 20.0), Vec4::ONE, 0);
+        assert_eq!(batch.len(), 9);
+
+        let (third, two_thirds) = (1.0 / 3.0, 2.0 / 3.0);
+        let (vertices, _, runs) = batch.build();
+        assert_eq!(runs.len(), 1);
+        assert_corners(
+            corners(&vertices, 0),
+            [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)],
+            [(0.0, 1.0), (third, 1.0), (third, two_thirds), (0.0, two_thirds)],
+        );
+        assert_corners(
+            corners(&vertices, 4),
+            [(4.0, 4.0), (36.0, 4.0), (36.0, 16.0), (4.0, 16.0)],
+            [(third, two_thirds), (two_thirds, two_thirds), (two_thirds, third), (third, third)],
+        );
+        assert_corners(
+            corners(&vertices, 8),
+            [(36.0, 16.0), (40.0, 16.0), (40.0, 20.0), (36.0, 20.0)],
+            [(two_thirds, third), (1.0, third), (1.0, 0.0), (two_thirds, 0.0)],
+        );
+    }
+
+    #[test]
+    fn nine_slice_borders_shrink_to_fit() {
+        let registry = ResourceRegistry::new_shared();
+        let panel = NineSlice::uniform(TextureRegion::new(texture(&registry, 1, 12, 12)), 4);
+
+        // Half as wide as the borders: they shrink by half and the middle column vanishes.
+        let mut batch = SpriteBatch::new();
+        batch.draw_nine_slice(&panel, Rect::new(0.0, 0.0, 4.0, 20.0), Vec4::ONE, 0);
+        assert_eq!(batch.len(), 6);
+
+        let (third, two_thirds) = (1.0 / 3.0, 2.0 / 3.0);
+        let (vertices, _, _) = batch.build();
+        assert_corners(
+            corners(&vertices, 0),
+            [(0.0, 0.0), (2.0, 0.0), (2.0, 4.0), (0.0, 4.0)],
+            [(0.0, 1.0), (third, 1.0), (third, two_thirds), (0.0, two_thirds)],
+        );
+        assert_corners(
+            corners(&vertices, 1),
+            [(2.0, 0.0), (4.0, 0.0), (4.0, 4.0), (2.0, 4.0)],
+            [(two_thirds, 1.0), (1.0, 1.0), (1.0, two_thirds), (two_thirds, two_thirds)],
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::Path;
+
+use image::RgbaImage;
+
+use crate::resource::{GpuObject, Handle, ResourceRef};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WrapMode {
+    ClampToEdge,
+    Repeat,
+    MirroredRepeat,
+}
+
+impl WrapMode {
+    pub(crate) fn to_gl(self) -> i32 {
+        (match self {
+            WrapMode::ClampToEdge => gl::CLAMP_TO_EDGE,
+            WrapMode::Repeat => gl::REPEAT,
+            WrapMode::MirroredRepeat => gl::MIRRORED_REPEAT,
+        }) as i32
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FilterMode {
+    Nearest,
+    Linear,
+}
+
+#[derive(Clone, Debug)]
+pub struct TextureDescriptor {
+    pub wrap_s: WrapMode,
+    pub wrap_t: WrapMode,
+    pub min_filter: FilterMode,
+    pub mag_filter: FilterMode,
+    /// Generates mipmaps and samples them when minifying, using `min_filter` between levels.
+    pub mipmaps: bool,
+    /// Treats the pixels as sRGB-encoded colour, converting to linear when sampled.
+    /// Leave off for data such as normal maps.
+    pub srgb: bool,
+    /// Flips rows so that images stored top row first map `v = 0` to their bottom edge, as GL expects.
+    pub flip_vertically: bool,
+}
+
+impl TextureDescriptor {
+    pub(crate) fn gl_min_filter(&self) -> i32 {
+        (match (self.min_filter, self.mipmaps) {
+            (FilterMode::Nearest, false) => gl::NEAREST,
+            (FilterMode::Linear, false) => gl::LINEAR,
+            (FilterMode::Nearest, true) => gl::NEAREST_MIPMAP_NEAREST,
+            (FilterMode::Linear, true) => gl::LINEAR_MIPMAP_LINEAR,
+        }) as i32
+    }
+
+    pub(crate) fn gl_mag_filter(&self) -> i32 {
+        (match self.mag_filter {
+            FilterMode::Nearest => gl::NEAREST,
+            FilterMode::Linear => gl::LINEAR,
+        }) as i32
+    }
+
+    pub(crate) fn gl_internal_format(&self) -> i32 {
+        (if self.srgb { gl::SRGB8_ALPHA8 } else { gl::RGBA8 }) as i32
+    }
+}
+
+impl Default for TextureDescriptor {
+    fn default() -> Self {
+        Self {
+            wrap_s: WrapMode::ClampToEdge,
+            wrap_t: WrapMode::ClampToEdge,
+            min_filter: FilterMode::Nearest,
+            mag_filter: FilterMode::Nearest,
+            mipmaps: false,
+            srgb: false,
+            flip_vertically: true,
+        }
+    }
+}
+
+/// Cloning a texture shares the same GPU texture; it is deleted once every clone is dropped.
+#[derive(Clone)]
+pub struct Texture {
+    pub(crate) resource: ResourceRef,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl Texture {
+    pub fn handle(&self) -> Handle {
+        self.resource.handle()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// GL texture name, or the software renderer's texture index.
+    pub(crate) fn raw_id(&self) -> u32 {
+        match self.resource.object() {
+            Some(GpuObject::Texture(id)) => id,
+            _ => 0,
+        }
+    }
+}
+
+pub(crate) fn load_file(path: &Path) -> Result<RgbaImage, String> {
+    let img = image::open(path).map_err(|e| format!("Failed to load texture {}: {}", path.to_string_lossy(), e))?;
+    Ok(img.to_rgba8())
+}
+
+pub(crate) fn load_memory(bytes: &[u8]) -> Result<RgbaImage, String> {
+    let img = image::load_from_memory(bytes).map_err(|e| format!("Failed to decode texture: {}", e))?;
+    Ok(img.to_rgba8())
+}
+
+pub(crate) fn rgba_image(width: u32, height: u32, pixels: &[u8]) -> Result<RgbaImage, String> {
+    let expected = width as usize * height as usize * 4;
+    if pixels.len() != expected {
+        return Err(format!("Expected {} bytes of RGBA pixels for a {}x{} texture, got {}", expected, width, height, pixels.len()));
+    }
+
+    RgbaImage::from_raw(width, height, pixels.to_vec()).ok_or_else(|| "Invalid RGBA pixel buffer".to_string())
+}
+
+/// Puts a top-row-first image into the row order the descriptor asks for.
+pub(crate) fn orient(img: RgbaImage, descriptor: &TextureDescriptor) -> RgbaImage {
+    if descriptor.flip_vertically {
+        image::imageops::flip_vertical(&img)
+    }
+    else {
+        img
+    }
+}
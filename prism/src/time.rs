@@ -0,0 +1,205 @@
+use std::time::{Duration, Instant};
+
+// How much of the pacing wait is left to spinning, since sleep overshoots on most platforms.
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+const FPS_SMOOTHING: f32 = 0.1;
+
+pub struct FrameClock {
+    last_frame: Option<Instant>,
+    delta: f32,
+    average_delta: f32,
+    frame_count: u64,
+    target_frame_time: Option<Duration>,
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameClock {
+    pub fn new() -> Self {
+        Self {
+            last_frame: None,
+            delta: 0.0,
+            average_delta: 0.0,
+            frame_count: 0,
+            target_frame_time: None,
+        }
+    }
+
+    /// Marks the start of a new frame, first waiting out the rest of the previous one if a
+    /// target frame rate is set.
+    pub fn tick(&mut self) {
+        if let (Some(last_frame), Some(target)) = (self.last_frame, self.target_frame_time) {
+            let deadline = last_frame + target;
+            let now = Instant::now();
+            if deadline > now + SPIN_MARGIN {
+                std::thread::sleep(deadline - now - SPIN_MARGIN);
+            }
+            while Instant::now() < deadline {
+                std::thread::yield_now();
+            }
+        }
+
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            self.delta = (now - last_frame).as_secs_f32();
+            self.average_delta = if self.frame_count == 1 {
+                self.delta
+            }
+            else {
+                self.average_delta + (self.delta - self.average_delta) * FPS_SMOOTHING
+            };
+        }
+
+        self.last_frame = Some(now);
+        self.frame_count += 1;
+    }
+
+    /// Seconds between the start of the previous frame and the start of this one.
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    /// Frames per second, smoothed over recent frames.
+    pub fn fps(&self) -> f32 {
+        if self.average_delta > 0.0 {
+            1.0 / self.average_delta
+        }
+        else {
+            0.0
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// `None` or a non-positive rate disables pacing.
+    pub fn set_target_frame_rate(&mut self, frame_rate: Option<f32>) {
+        self.target_frame_time = frame_rate
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| Duration::from_secs_f32(1.0 / rate));
+    }
+
+    pub fn target_frame_rate(&self) -> Option<f32> {
+        self.target_frame_time.map(|time| 1.0 / time.as_secs_f32())
+    }
+}
+
+/// Accumulates frame time and hands it out in fixed steps, so updates run at a steady rate
+/// regardless of how fast frames are rendered.
+pub struct FixedTimestep {
+    step: f32,
+    accumulator: f32,
+    max_steps: u32,
+}
+
+impl FixedTimestep {
+    pub fn new(updates_per_second: f32) -> Result<Self, String> {
+        // Also rejects NaN, with which no step would ever be taken.
+        if !(updates_per_second.is_finite() && updates_per_second > 0.0) {
+            return Err(format!("Fixed update rate must be positive and finite, not {}", updates_per_second));
+        }
+
+        Ok(Self {
+            step: 1.0 / updates_per_second,
+            accumulator: 0.0,
+            max_steps: 8,
+        })
+    }
+
+    /// Caps the steps taken per frame; time beyond that is dropped so a slow frame cannot
+    /// snowball into ever more updates.
+    pub fn set_max_steps(&mut self, max_steps: u32) {
+        self.max_steps = max_steps.max(1);
+    }
+
+    /// Adds a frame's delta and returns how many fixed steps should run this frame.
+    pub fn accumulate(&mut self, delta: f32) -> u32 {
+        self.accumulator += delta;
+
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_steps {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+
+        if steps == self.max_steps {
+            self.accumulator = self.accumulator.min(self.step);
+        }
+
+        steps
+    }
+
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    /// How far between the last and next fixed step the current frame is, for interpolation.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_clock_measures_paced_frames() {
+        let mut clock = FrameClock::new();
+        clock.set_target_frame_rate(Some(50.0));
+        assert_eq!(clock.target_frame_rate(), Some(50.0));
+
+        clock.tick();
+        assert_eq!((clock.frame_count(), clock.delta(), clock.fps()), (1, 0.0, 0.0));
+
+        clock.tick();
+        assert_eq!(clock.frame_count(), 2);
+        assert!(clock.delta() >= 0.02);
+        assert_eq!(clock.fps(), 1.0 / clock.delta());
+    }
+
+    #[test]
+    fn non_positive_frame_rates_disable_pacing() {
+        let mut clock = FrameClock::new();
+        for rate in [None, Some(0.0), Some(-30.0), Some(f32::NAN)] {
+            clock.set_target_frame_rate(rate);
+            assert_eq!(clock.target_frame_rate(), None);
+        }
+    }
+
+    #[test]
+    fn fixed_timestep_carries_the_remainder_over() {
+        let mut timestep = FixedTimestep::new(4.0).unwrap();
+        assert_eq!(timestep.step(), 0.25);
+
+        assert_eq!(timestep.accumulate(0.625), 2);
+        assert_eq!(timestep.alpha(), 0.5);
+        assert_eq!(timestep.accumulate(0.125), 1);
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.accumulate(0.125), 0);
+    }
+
+    #[test]
+    fn fixed_timestep_drops_time_beyond_max_steps() {
+        let mut timestep = FixedTimestep::new(4.0).unwrap();
+        timestep.set_max_steps(3);
+
+        assert_eq!(timestep.accumulate(10.0), 3);
+        assert!(timestep.alpha() <= 1.0);
+        // What is left is at most one step, not the rest of the long frame.
+        assert_eq!(timestep.accumulate(0.0), 1);
+        assert_eq!(timestep.accumulate(0.0), 0);
+    }
+
+    #[test]
+    fn fixed_timestep_rejects_invalid_rates() {
+        for rate in [0.0, -60.0, f32::NAN, f32::INFINITY] {
+            assert!(FixedTimestep::new(rate).is_err());
+        }
+    }
+}
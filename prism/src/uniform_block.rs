@@ -0,0 +1,470 @@
+use std::marker::PhantomData;
+
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+
+use crate::{Camera, resource::{GpuObject, Handle, ResourceRef}};
+
+/// Binding point of the built-in `Camera` block.
+pub const CAMERA_BINDING: u32 = 0;
+/// Binding point of the built-in `Lights` block.
+pub const LIGHTS_BINDING: u32 = 1;
+/// Binding point of the built-in `Shadow` block.
+pub const SHADOW_BINDING: u32 = 2;
+/// First binding point free for application blocks.
+pub const FIRST_USER_BINDING: u32 = 3;
+
+/// Serializes values with the std140 layout rules, so the bytes match a `layout(std140)` block.
+pub struct Std140Writer {
+    data: Vec<u8>,
+}
+
+impl Std140Writer {
+    pub(crate) fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    pub(crate) fn into_bytes(mut self) -> Vec<u8> {
+        // A block's size is rounded up to a vec4, like a struct's.
+        self.align(16);
+        self.data
+    }
+
+    fn align(&mut self, alignment: usize) {
+        let padded = self.data.len().next_multiple_of(alignment);
+        self.data.resize(padded, 0);
+    }
+
+    fn push(&mut self, alignment: usize, values: &[f32]) {
+        self.align(alignment);
+        for value in values {
+            self.data.extend_from_slice(&value.to_ne_bytes());
+        }
+    }
+
+    pub fn float(&mut self, value: f32) {
+        self.push(4, &[value]);
+    }
+
+    pub fn int(&mut self, value: i32) {
+        self.align(4);
+        self.data.extend_from_slice(&value.to_ne_bytes());
+    }
+
+    /// GLSL bools are 4 bytes in a block.
+    pub fn bool(&mut self, value: bool) {
+        self.int(value as i32);
+    }
+
+    pub fn vec2(&mut self, value: Vec2) {
+        self.push(8, &value.to_array());
+    }
+
+    /// Aligned like a vec4; a following scalar may fill the fourth component.
+    pub fn vec3(&mut self, value: Vec3) {
+        self.push(16, &value.to_array());
+    }
+
+    pub fn vec4(&mut self, value: Vec4) {
+        self.push(16, &value.to_array());
+    }
+
+    /// Stored as three vec4 columns.
+    pub fn mat3(&mut self, value: Mat3) {
+        for column in [value.x_axis, value.y_axis, value.z_axis] {
+            self.push(16, &column.extend(0.0).to_array());
+        }
+    }
+
+    pub fn mat4(&mut self, value: Mat4) {
+        self.push(16, &value.to_cols_array());
+    }
+
+    /// Call before and after each struct, and each element of an array of structs or
+    /// scalars, whose std140 alignment is always that of a vec4.
+    pub fn align_struct(&mut self) {
+        self.align(16);
+    }
+}
+
+/// A Rust-side mirror of a `layout(std140) uniform` block. Programs get the block bound to
+/// `BINDING` when linked, if they declare a block called `NAME`.
+pub trait UniformBlock {
+    const NAME: &'static str;
+    const BINDING: u32;
+
+    fn write_std140(&self, writer: &mut Std140Writer);
+}
+
+/// The built-in `Camera` block, see `shaders/camera.glsl`.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraBlock {
+    pub view: Mat4,
+    pub projection: Mat4,
+    pub view_position: Vec3,
+}
+
+impl CameraBlock {
+    pub fn new(camera: &Camera, aspect_ratio: f32) -> Self {
+        Self {
+            view: camera.get_view_matrix(),
+            projection: camera.get_projection_matrix(aspect_ratio),
+            view_position: camera.get_position(),
+        }
+    }
+}
+
+impl Default for CameraBlock {
+    fn default() -> Self {
+        Self {
+            view: Mat4::IDENTITY,
+            projection: Mat4::IDENTITY,
+            view_position: Vec3::ZERO,
+        }
+    }
+}
+
+impl UniformBlock for CameraBlock {
+    const NAME: &'static str = "Camera";
+    const BINDING: u32 = CAMERA_BINDING;
+
+    fn write_std140(&self, writer: &mut Std140Writer) {
+        writer.mat4(self.view);
+        writer.mat4(self.projection);
+        writer.vec3(self.view_position);
+    }
+}
+
+/// Lights the `Lights` block holds; must match `MAX_LIGHTS` in `shaders/lighting.glsl`.
+pub const MAX_LIGHTS: usize = 8;
+
+/// Stored as an int in the block, see the `LIGHT_*` constants in `shaders/lighting.glsl`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LightKind {
+    /// Infinitely far away, shining along `direction`, like the sun.
+    Directional,
+    /// Shines from `position` in every direction.
+    Point,
+    /// Shines from `position` along `direction`, within a cone.
+    Spot,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Unused by directional lights.
+    pub position: Vec3,
+    /// Unused by point lights.
+    pub direction: Vec3,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    /// Coefficients of the `1 / (constant + linear * d + quadratic * d²)` falloff of point
+    /// and spot lights.
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+    /// Half-angles of a spot light's cone in radians: full intensity inside `inner_cone`,
+    /// fading out towards `outer_cone`.
+    pub inner_cone: f32,
+    pub outer_cone: f32,
+    /// Whether the screen's shadow map darkens this light. Only directional and spot lights
+    /// can cast shadows, and only the one the shadow pass was rendered from should be marked.
+    pub casts_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, ambient: Vec3, diffuse: Vec3, specular: Vec3) -> Self {
+        Self { kind: LightKind::Directional, direction, ambient, diffuse, specular, ..Self::default() }
+    }
+
+    /// A point light without falloff; see `with_attenuation`.
+    pub fn point(position: Vec3, ambient: Vec3, diffuse: Vec3, specular: Vec3) -> Self {
+        Self { kind: LightKind::Point, position, ambient, diffuse, specular, ..Self::default() }
+    }
+
+    pub fn spot(position: Vec3, direction: Vec3, inner_cone: f32, outer_cone: f32, ambient: Vec3, diffuse: Vec3, specular: Vec3) -> Self {
+        Self { kind: LightKind::Spot, position, direction, inner_cone, outer_cone, ambient, diffuse, specular, ..Self::default() }
+    }
+
+    pub fn with_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    pub fn with_attenuation(mut self, constant: f32, linear: f32, quadratic: f32) -> Self {
+        self.constant = constant;
+        self.linear = linear;
+        self.quadratic = quadratic;
+        self
+    }
+
+    /// Falloff at `distance`; always 1 for directional lights.
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        match self.kind {
+            LightKind::Directional => 1.0,
+            LightKind::Point | LightKind::Spot => {
+                let falloff = self.constant + self.linear * distance + self.quadratic * distance * distance;
+                if falloff > 0.0 { (1.0 / falloff).min(1.0) } else { 1.0 }
+            }
+        }
+    }
+
+    /// Rough brightness the light contributes at `position`, for picking the lights that
+    /// matter most to an object. Ignores spot cones, which an object may only partly be in.
+    pub fn relevance(&self, position: Vec3) -> f32 {
+        let brightness = (self.ambient + self.diffuse + self.specular).max_element();
+        brightness * self.attenuation(self.position.distance(position))
+    }
+
+    fn kind_id(&self) -> i32 {
+        match self.kind {
+            LightKind::Directional => 0,
+            LightKind::Point => 1,
+            LightKind::Spot => 2,
+        }
+    }
+}
+
+impl Default for Light {
+    /// A black point light at the origin, without falloff.
+    fn default() -> Self {
+        Self {
+            kind: LightKind::Point,
+            position: Vec3::ZERO,
+            direction: Vec3::NEG_Z,
+            ambient: Vec3::ZERO,
+            diffuse: Vec3::ZERO,
+            specular: Vec3::ZERO,
+            constant: 1.0,
+            linear: 0.0,
+            quadratic: 0.0,
+            inner_cone: 12.5f32.to_radians(),
+            outer_cone: 17.5f32.to_radians(),
+            casts_shadows: false,
+        }
+    }
+}
+
+/// The built-in `Lights` block, see `shaders/lighting.glsl`. Holds up to `MAX_LIGHTS`
+/// lights; any beyond that are not uploaded.
+#[derive(Clone, Debug, Default)]
+pub struct LightsBlock {
+    pub lights: Vec<Light>,
+}
+
+impl LightsBlock {
+    pub fn new(lights: Vec<Light>) -> Self {
+        Self { lights }
+    }
+
+    /// Picks the `count` lights, at most `MAX_LIGHTS`, most relevant to something at
+    /// `position`. Lights that contribute nothing there are left out.
+    pub fn gather(lights: &[Light], position: Vec3, count: usize) -> Self {
+        let mut ranked: Vec<(f32, &Light)> = lights
+            .iter()
+            .map(|light| (light.relevance(position), light))
+            .filter(|(relevance, _)| *relevance > 0.0)
+            .collect();
+        ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        Self { lights: ranked.into_iter().take(count.min(MAX_LIGHTS)).map(|(_, light)| *light).collect() }
+    }
+
+    /// The lights that are uploaded.
+    pub fn active_lights(&self) -> &[Light] {
+        &self.lights[..self.lights.len().min(MAX_LIGHTS)]
+    }
+
+    /// A copy in which only `caster`, the light the shadow map was rendered from, casts
+    /// shadows. The shaders sample the one map for every light marked, so any other would
+    /// get shadows cast from the wrong place.
+    pub(crate) fn with_shadow_caster(&self, caster: Option<&Light>) -> Self {
+        let mut caster = caster.map(|caster| Light { casts_shadows: true, ..*caster });
+        let lights = self
+            .lights
+            .iter()
+            .map(|light| {
+                // Only the first copy of the caster, should it be in the block twice.
+                let casts_shadows = caster.is_some_and(|caster| caster == *light);
+                if casts_shadows {
+                    caster = None;
+                }
+                Light { casts_shadows, ..*light }
+            })
+            .collect();
+        Self { lights }
+    }
+}
+
+impl UniformBlock for LightsBlock {
+    const NAME: &'static str = "Lights";
+    const BINDING: u32 = LIGHTS_BINDING;
+
+    fn write_std140(&self, writer: &mut Std140Writer) {
+        let lights = self.active_lights();
+        let unused = Light::default();
+
+        for i in 0..MAX_LIGHTS {
+            let light = lights.get(i).unwrap_or(&unused);
+            writer.align_struct();
+            writer.vec3(light.position);
+            writer.int(light.kind_id());
+            writer.vec3(light.direction.normalize_or_zero());
+            writer.float(light.constant);
+            writer.vec3(light.ambient);
+            writer.float(light.linear);
+            writer.vec3(light.diffuse);
+            writer.float(light.quadratic);
+            writer.vec3(light.specular);
+            // Cosines, so the shader compares them with a dot product directly.
+            writer.float(light.inner_cone.cos());
+            writer.float(light.outer_cone.cos());
+            writer.bool(light.casts_shadows);
+            writer.align_struct();
+        }
+        writer.int(lights.len() as i32);
+    }
+}
+
+/// The built-in `Shadow` block, see `shaders/shadow.glsl`. Set by the shadow pass.
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowBlock {
+    /// Takes world space to the shadow-casting light's clip space.
+    pub light_space: Mat4,
+    pub depth_bias: f32,
+    pub slope_bias: f32,
+    pub pcf_radius: u32,
+    /// False until a shadow pass has been rendered this frame, so nothing samples the map.
+    pub enabled: bool,
+}
+
+impl Default for ShadowBlock {
+    fn default() -> Self {
+        Self {
+            light_space: Mat4::IDENTITY,
+            depth_bias: 0.0,
+            slope_bias: 0.0,
+            pcf_radius: 0,
+            enabled: false,
+        }
+    }
+}
+
+impl UniformBlock for ShadowBlock {
+    const NAME: &'static str = "Shadow";
+    const BINDING: u32 = SHADOW_BINDING;
+
+    fn write_std140(&self, writer: &mut Std140Writer) {
+        writer.mat4(self.light_space);
+        writer.float(self.depth_bias);
+        writer.float(self.slope_bias);
+        writer.int(self.pcf_radius as i32);
+        writer.bool(self.enabled);
+    }
+}
+
+/// GPU buffer holding one `T`. Updated with `PrismRenderer::upload_uniform_block`.
+pub struct UniformBuffer<T: UniformBlock> {
+    pub(crate) resource: ResourceRef,
+    _block: PhantomData<T>,
+}
+
+impl<T: UniformBlock> UniformBuffer<T> {
+    pub(crate) fn new(resource: ResourceRef) -> Self {
+        Self { resource, _block: PhantomData }
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.resource.handle()
+    }
+
+    pub(crate) fn raw_id(&self) -> u32 {
+        match self.resource.object() {
+            Some(GpuObject::Buffer(id)) => id,
+            _ => 0,
+        }
+    }
+}
+
+pub(crate) fn to_std140<T: UniformBlock>(block: &T) -> Vec<u8> {
+    let mut writer = Std140Writer::new();
+    block.write_std140(&mut writer);
+    writer.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn float_at(bytes: &[u8], offset: usize) -> f32 {
+        f32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn int_at(bytes: &[u8], offset: usize) -> i32 {
+        i32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn scalars_fill_the_end_of_a_vec3() {
+        let mut writer = Std140Writer::new();
+        writer.float(1.0);
+        writer.vec3(Vec3::splat(2.0));
+        writer.float(3.0);
+        writer.vec2(Vec2::splat(4.0));
+        let bytes = writer.into_bytes();
+
+        assert_eq!(bytes.len(), 48);
+        assert_eq!(float_at(&bytes, 16), 2.0);
+        assert_eq!(float_at(&bytes, 28), 3.0);
+        assert_eq!(float_at(&bytes, 32), 4.0);
+    }
+
+    #[test]
+    fn lights_are_96_bytes_apart_with_the_count_after() {
+        let light = Light::spot(Vec3::new(1.0, 2.0, 3.0), Vec3::NEG_Y, 0.0, 0.5, Vec3::ZERO, Vec3::ONE, Vec3::ONE)
+            .with_attenuation(1.0, 0.25, 0.5)
+            .with_shadows(true);
+        let bytes = to_std140(&LightsBlock::new(vec![Light::default(), light]));
+
+        assert_eq!(MAX_LIGHTS * 96, 768);
+        assert_eq!(bytes.len(), 784);
+
+        let second = 96;
+        assert_eq!(float_at(&bytes, second), 1.0);
+        assert_eq!(int_at(&bytes, second + 12), 2);
+        assert_eq!(float_at(&bytes, second + 20), -1.0);
+        assert_eq!(float_at(&bytes, second + 44), 0.25);
+        assert_eq!(float_at(&bytes, second + 60), 0.5);
+        assert_eq!(float_at(&bytes, second + 76), 1.0);
+        assert_eq!(float_at(&bytes, second + 80), 0.5f32.cos());
+        assert_eq!(int_at(&bytes, second + 84), 1);
+        assert_eq!(int_at(&bytes, 768), 2);
+    }
+
+    #[test]
+    fn extra_lights_are_not_counted() {
+        let bytes = to_std140(&LightsBlock::new(vec![Light::default(); MAX_LIGHTS + 3]));
+        assert_eq!(bytes.len(), 784);
+        assert_eq!(int_at(&bytes, 768), MAX_LIGHTS as i32);
+    }
+
+    #[test]
+    fn only_the_shadow_caster_keeps_its_flag() {
+        let sun = Light::directional(Vec3::NEG_Y, Vec3::ZERO, Vec3::ONE, Vec3::ONE).with_shadows(true);
+        let spot = Light::spot(Vec3::Y, Vec3::NEG_Y, 0.2, 0.4, Vec3::ZERO, Vec3::ONE, Vec3::ONE).with_shadows(true);
+        let lamp = Light::point(Vec3::X, Vec3::ZERO, Vec3::ONE, Vec3::ONE).with_shadows(true);
+        let block = LightsBlock::new(vec![spot, sun, lamp]);
+
+        let flags = |block: LightsBlock| block.lights.iter().map(|light| light.casts_shadows).collect::<Vec<_>>();
+        assert_eq!(flags(block.with_shadow_caster(Some(&sun))), [false, true, false]);
+        // The pass may be begun with a copy that is not marked itself.
+        assert_eq!(flags(block.with_shadow_caster(Some(&spot.with_shadows(false)))), [true, false, false]);
+        assert_eq!(flags(block.with_shadow_caster(None)), [false, false, false]);
+    }
+
+    #[test]
+    fn built_in_block_sizes() {
+        assert_eq!(to_std140(&CameraBlock::default()).len(), 144);
+        assert_eq!(to_std140(&ShadowBlock::default()).len(), 80);
+    }
+}
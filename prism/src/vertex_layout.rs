@@ -0,0 +1,345 @@
+use glam::{Vec2, Vec3, Vec4};
+
+use crate::{Vertex, bounds::Aabb};
+
+/// Attribute locations the built-in shaders and `VertexLayout`'s helpers use. Shaders read
+/// them with `layout (location = N) in ...`.
+pub const POSITION: u32 = 0;
+pub const NORMAL: u32 = 1;
+pub const TEX_COORDS: u32 = 2;
+pub const COLOR: u32 = 3;
+/// Tangent in xyz and the handedness of the bitangent, 1 or -1, in w.
+pub const TANGENT: u32 = 4;
+/// A second set of texture coordinates, e.g. for lightmaps.
+pub const TEX_COORDS_1: u32 = 5;
+pub const BONE_INDICES: u32 = 6;
+pub const BONE_WEIGHTS: u32 = 7;
+/// Per-instance transform of instanced draws, a `mat4` taking this location and the next three.
+pub const INSTANCE_TRANSFORM: u32 = 8;
+pub const INSTANCE_COLOR: u32 = 12;
+/// Locations from here up are free for custom attributes.
+pub const FIRST_CUSTOM_LOCATION: u32 = 13;
+/// Every GL 3.3 and GLES 3.0 driver has at least this many.
+pub const MAX_ATTRIBUTES: u32 = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AttributeFormat {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    /// Four bytes the shader reads as a `vec4` from 0 to 1, e.g. compact RGBA colours.
+    Unorm8x4,
+    /// Four bytes the shader reads as a `uvec4`, e.g. bone indices.
+    Uint8x4,
+    /// Read as an `int`.
+    Int,
+}
+
+impl AttributeFormat {
+    /// Size in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            AttributeFormat::Float | AttributeFormat::Unorm8x4 | AttributeFormat::Uint8x4 | AttributeFormat::Int => 4,
+            AttributeFormat::Vec2 => 8,
+            AttributeFormat::Vec3 => 12,
+            AttributeFormat::Vec4 => 16,
+        }
+    }
+
+    /// Component count, component type, whether it is normalized and whether the shader
+    /// reads it as integers.
+    pub(crate) fn to_gl(self) -> (i32, u32, bool, bool) {
+        match self {
+            AttributeFormat::Float => (1, gl::FLOAT, false, false),
+            AttributeFormat::Vec2 => (2, gl::FLOAT, false, false),
+            AttributeFormat::Vec3 => (3, gl::FLOAT, false, false),
+            AttributeFormat::Vec4 => (4, gl::FLOAT, false, false),
+            AttributeFormat::Unorm8x4 => (4, gl::UNSIGNED_BYTE, true, false),
+            AttributeFormat::Uint8x4 => (4, gl::UNSIGNED_BYTE, false, true),
+            AttributeFormat::Int => (1, gl::INT, false, true),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub format: AttributeFormat,
+    /// Bytes from the start of a vertex.
+    pub offset: usize,
+}
+
+/// Which attributes each vertex of a mesh has and where they sit, interleaved in one buffer.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct VertexLayout {
+    attributes: Vec<VertexAttribute>,
+    stride: usize,
+}
+
+impl VertexLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Position, normal and texture coordinates, the layout of `Vertex`.
+    pub fn standard() -> Self {
+        Self::new()
+            .with(POSITION, AttributeFormat::Vec3)
+            .with(NORMAL, AttributeFormat::Vec3)
+            .with(TEX_COORDS, AttributeFormat::Vec2)
+    }
+
+    /// Appends an attribute after the others.
+    pub fn with(mut self, location: u32, format: AttributeFormat) -> Self {
+        if location >= MAX_ATTRIBUTES {
+            panic!("Vertex attribute location {} is above the {} every driver supports", location, MAX_ATTRIBUTES - 1);
+        }
+        if (INSTANCE_TRANSFORM..FIRST_CUSTOM_LOCATION).contains(&location) {
+            panic!("Vertex attribute locations {} to {} are reserved for per-instance data", INSTANCE_TRANSFORM, FIRST_CUSTOM_LOCATION - 1);
+        }
+        if self.attribute(location).is_some() {
+            panic!("Vertex layout already has an attribute at location {}", location);
+        }
+
+        self.attributes.push(VertexAttribute { location, format, offset: self.stride });
+        self.stride += format.size();
+        self
+    }
+
+    pub fn with_color(self) -> Self {
+        self.with(COLOR, AttributeFormat::Vec4)
+    }
+
+    pub fn with_tangents(self) -> Self {
+        self.with(TANGENT, AttributeFormat::Vec4)
+    }
+
+    pub fn with_tex_coords_1(self) -> Self {
+        self.with(TEX_COORDS_1, AttributeFormat::Vec2)
+    }
+
+    /// Indices of up to four bones and how much each moves the vertex.
+    pub fn with_skinning(self) -> Self {
+        self.with(BONE_INDICES, AttributeFormat::Uint8x4).with(BONE_WEIGHTS, AttributeFormat::Vec4)
+    }
+
+    pub fn with_custom(self, location: u32, format: AttributeFormat) -> Self {
+        if location < FIRST_CUSTOM_LOCATION {
+            panic!("Custom vertex attributes start at location {}, got {}", FIRST_CUSTOM_LOCATION, location);
+        }
+        self.with(location, format)
+    }
+
+    pub fn attributes(&self) -> &[VertexAttribute] {
+        &self.attributes
+    }
+
+    pub fn attribute(&self, location: u32) -> Option<&VertexAttribute> {
+        self.attributes.iter().find(|attribute| attribute.location == location)
+    }
+
+    /// Bytes per vertex.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+}
+
+/// A value for one attribute of one vertex.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AttributeValue {
+    Float(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Vec4(Vec4),
+    /// For `Unorm8x4` and `Uint8x4`.
+    Bytes([u8; 4]),
+    Int(i32),
+}
+
+impl AttributeValue {
+    fn fits(&self, format: AttributeFormat) -> bool {
+        matches!(
+            (self, format),
+            (AttributeValue::Float(_), AttributeFormat::Float)
+                | (AttributeValue::Vec2(_), AttributeFormat::Vec2)
+                | (AttributeValue::Vec3(_), AttributeFormat::Vec3)
+                | (AttributeValue::Vec4(_), AttributeFormat::Vec4)
+                | (AttributeValue::Bytes(_), AttributeFormat::Unorm8x4 | AttributeFormat::Uint8x4)
+                | (AttributeValue::Int(_), AttributeFormat::Int)
+        )
+    }
+
+    fn write(&self, out: &mut [u8]) {
+        let mut floats = |values: &[f32]| {
+            for (chunk, value) in out.chunks_exact_mut(4).zip(values) {
+                chunk.copy_from_slice(&value.to_ne_bytes());
+            }
+        };
+
+        match self {
+            AttributeValue::Float(value) => floats(&[*value]),
+            AttributeValue::Vec2(value) => floats(&value.to_array()),
+            AttributeValue::Vec3(value) => floats(&value.to_array()),
+            AttributeValue::Vec4(value) => floats(&value.to_array()),
+            AttributeValue::Bytes(bytes) => out.copy_from_slice(bytes),
+            AttributeValue::Int(value) => out.copy_from_slice(&value.to_ne_bytes()),
+        }
+    }
+}
+
+impl From<f32> for AttributeValue {
+    fn from(value: f32) -> Self {
+        AttributeValue::Float(value)
+    }
+}
+
+impl From<Vec2> for AttributeValue {
+    fn from(value: Vec2) -> Self {
+        AttributeValue::Vec2(value)
+    }
+}
+
+impl From<Vec3> for AttributeValue {
+    fn from(value: Vec3) -> Self {
+        AttributeValue::Vec3(value)
+    }
+}
+
+impl From<Vec4> for AttributeValue {
+    fn from(value: Vec4) -> Self {
+        AttributeValue::Vec4(value)
+    }
+}
+
+impl From<[u8; 4]> for AttributeValue {
+    fn from(value: [u8; 4]) -> Self {
+        AttributeValue::Bytes(value)
+    }
+}
+
+impl From<i32> for AttributeValue {
+    fn from(value: i32) -> Self {
+        AttributeValue::Int(value)
+    }
+}
+
+/// Vertices packed in a `VertexLayout`, ready to upload with
+/// `PrismRenderer::create_mesh_with_layout` or `update_mesh`. New vertices are zeroed.
+#[derive(Clone, Debug)]
+pub struct VertexBuffer {
+    layout: VertexLayout,
+    data: Vec<u8>,
+}
+
+impl VertexBuffer {
+    pub fn new(layout: VertexLayout, count: usize) -> Self {
+        let data = vec![0; layout.stride() * count];
+        Self { layout, data }
+    }
+
+    pub fn from_vertices(vertices: &[Vertex]) -> Self {
+        let mut buffer = Self::new(VertexLayout::standard(), vertices.len());
+        for (i, vertex) in vertices.iter().enumerate() {
+            buffer.set(i, POSITION, vertex.position);
+            buffer.set(i, NORMAL, vertex.normal);
+            buffer.set(i, TEX_COORDS, vertex.tex_coords);
+        }
+        buffer
+    }
+
+    pub fn layout(&self) -> &VertexLayout {
+        &self.layout
+    }
+
+    pub fn len(&self) -> usize {
+        match self.layout.stride() {
+            0 => 0,
+            stride => self.data.len() / stride,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn resize(&mut self, count: usize) {
+        self.data.resize(self.layout.stride() * count, 0);
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    /// Appends a zeroed vertex and returns its index.
+    pub fn push(&mut self) -> usize {
+        self.resize(self.len() + 1);
+        self.len() - 1
+    }
+
+    /// Sets the attribute at `location` of vertex `index`. Panics if the layout has no such
+    /// attribute or it has a different format.
+    pub fn set(&mut self, index: usize, location: u32, value: impl Into<AttributeValue>) {
+        let value = value.into();
+        let attribute = match self.layout.attribute(location) {
+            Some(attribute) => *attribute,
+            None => panic!("Vertex layout has no attribute at location {}", location),
+        };
+
+        if !value.fits(attribute.format) {
+            panic!("{:?} does not fit the {:?} attribute at location {}", value, attribute.format, location);
+        }
+        if index >= self.len() {
+            panic!("Vertex {} is out of range for a buffer of {}", index, self.len());
+        }
+
+        let start = index * self.layout.stride() + attribute.offset;
+        value.write(&mut self.data[start..start + attribute.format.size()]);
+    }
+
+    /// The box around the positions, or `None` if there are no vertices or the positions
+    /// are not floats.
+    pub fn bounds(&self) -> Option<Aabb> {
+        let attribute = self.layout.attribute(POSITION)?;
+        let components = match attribute.format {
+            AttributeFormat::Vec2 => 2,
+            AttributeFormat::Vec3 | AttributeFormat::Vec4 => 3,
+            _ => return None,
+        };
+
+        let stride = self.layout.stride();
+        Aabb::from_points((0..self.len()).map(|index| {
+            let start = index * stride + attribute.offset;
+            let mut position = Vec3::ZERO;
+            for (i, bytes) in self.data[start..start + components * 4].chunks_exact(4).enumerate() {
+                position[i] = f32::from_ne_bytes(bytes.try_into().unwrap());
+            }
+            position
+        }))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// How often a mesh's buffers are expected to change, a hint to the driver.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BufferUsage {
+    /// Uploaded once.
+    Static,
+    /// Updated now and then, e.g. procedural terrain being edited.
+    Dynamic,
+    /// Rewritten every frame, e.g. particles and trails.
+    Stream,
+}
+
+impl BufferUsage {
+    pub(crate) fn to_gl(self) -> u32 {
+        match self {
+            BufferUsage::Static => gl::STATIC_DRAW,
+            BufferUsage::Dynamic => gl::DYNAMIC_DRAW,
+            BufferUsage::Stream => gl::STREAM_DRAW,
+        }
+    }
+}
@@ -1,9 +1,10 @@
-use prism::{Camera, glm::Vec3};
+use prism::{Camera, glm::Vec3, render_target::RenderTarget};
 
 use crate::components::Component;
 
 pub struct CameraComponent {
-    prism_camera: Camera
+    prism_camera: Camera,
+    render_target: Option<RenderTarget>,
 }
 
 impl Component for CameraComponent {
@@ -17,10 +18,21 @@ impl CameraComponent {
 
     pub fn new() -> Self {
         Self {
-            prism_camera: Camera::new(Vec3::ZERO, Vec3::Y, -90.0f32.to_radians(), 0.0f32.to_radians())
+            prism_camera: Camera::new(Vec3::ZERO, Vec3::Y, -90.0f32.to_radians(), 0.0f32.to_radians()),
+            render_target: None,
         }
     }
 
+    /// Renders what the camera sees into `target` every frame, before its screen is drawn.
+    /// Show `target.color_texture()` on another object's material for a mirror or monitor.
+    pub fn set_render_target(&mut self, target: Option<RenderTarget>) {
+        self.render_target = target;
+    }
+
+    pub fn render_target(&self) -> Option<&RenderTarget> {
+        self.render_target.as_ref()
+    }
+
     pub fn translate(&mut self, vector: Vec3) {
         self.prism_camera.adjust_position(vector);
     }
@@ -0,0 +1,37 @@
+use prism::{glm::Mat4, uniform_block::Light};
+
+use crate::components::Component;
+
+/// Makes an object light the other objects on its screen. The light's position and direction
+/// are relative to the object, so the light moves and turns with the object's mesh, or with
+/// its camera if it has no mesh. On an object with neither they are in world space.
+pub struct LightComponent {
+    pub(crate) light: Light,
+}
+
+impl Component for LightComponent {
+
+}
+
+impl LightComponent {
+    pub fn new(light: Light) -> Self {
+        Self { light }
+    }
+
+    pub fn light(&self) -> &Light {
+        &self.light
+    }
+
+    pub fn light_mut(&mut self) -> &mut Light {
+        &mut self.light
+    }
+
+    /// The light placed by `transform`, the transform of the object carrying it.
+    pub(crate) fn world_light(&self, transform: Mat4) -> Light {
+        Light {
+            position: transform.transform_point3(self.light.position),
+            direction: transform.transform_vector3(self.light.direction).normalize_or_zero(),
+            ..self.light
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use prism::material::Material;
+
+use crate::components::Component;
+
+/// Material an object's mesh is drawn with. Objects without one use the engine's default
+/// material.
+pub struct MaterialComponent {
+    pub(crate) material: Material,
+}
+
+impl Component for MaterialComponent {
+
+}
+
+impl MaterialComponent {
+    pub fn new(material: Material) -> Self {
+        Self { material }
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+}
@@ -1,4 +1,4 @@
-use prism::mesh::Mesh;
+use prism::{PrismRenderer, glm::Vec2, mesh::{Geometry, Mesh}};
 
 use crate::components::Component;
 
@@ -11,5 +11,57 @@ impl Component for MeshComponent {
 }
 
 impl MeshComponent {
+    pub fn new(mesh: Mesh) -> Self {
+        Self { mesh }
+    }
 
-}
\ No newline at end of file
+    /// Uploads `geometry` with a white texture, so that it shows its material's colours.
+    pub fn from_geometry(renderer: &mut PrismRenderer, geometry: Geometry) -> Self {
+        let white = renderer.white_texture().expect("PrismRenderer must be initialized before creating meshes");
+        Self::new(renderer.create_mesh_from_geometry(geometry, vec![white]))
+    }
+
+    pub fn cube(renderer: &mut PrismRenderer, size: f32) -> Self {
+        Self::from_geometry(renderer, Geometry::cube(size))
+    }
+
+    pub fn sphere(renderer: &mut PrismRenderer, radius: f32) -> Self {
+        Self::from_geometry(renderer, Geometry::uv_sphere(radius, 32, 16))
+    }
+
+    pub fn icosphere(renderer: &mut PrismRenderer, radius: f32, subdivisions: u32) -> Self {
+        Self::from_geometry(renderer, Geometry::icosphere(radius, subdivisions))
+    }
+
+    pub fn plane(renderer: &mut PrismRenderer, size: Vec2) -> Self {
+        Self::from_geometry(renderer, Geometry::plane(size))
+    }
+
+    pub fn grid(renderer: &mut PrismRenderer, size: Vec2, columns: u32, rows: u32) -> Self {
+        Self::from_geometry(renderer, Geometry::grid(size, columns, rows))
+    }
+
+    pub fn cylinder(renderer: &mut PrismRenderer, radius: f32, height: f32) -> Self {
+        Self::from_geometry(renderer, Geometry::cylinder(radius, height, 32))
+    }
+
+    pub fn cone(renderer: &mut PrismRenderer, radius: f32, height: f32) -> Self {
+        Self::from_geometry(renderer, Geometry::cone(radius, height, 32))
+    }
+
+    pub fn capsule(renderer: &mut PrismRenderer, radius: f32, height: f32) -> Self {
+        Self::from_geometry(renderer, Geometry::capsule(radius, height, 32, 8))
+    }
+
+    pub fn torus(renderer: &mut PrismRenderer, major_radius: f32, minor_radius: f32) -> Self {
+        Self::from_geometry(renderer, Geometry::torus(major_radius, minor_radius, 48, 16))
+    }
+
+    pub fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+
+    pub fn mesh_mut(&mut self) -> &mut Mesh {
+        &mut self.mesh
+    }
+}
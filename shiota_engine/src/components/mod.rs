@@ -1,4 +1,6 @@
 pub mod camera;
+pub mod light;
+pub mod material;
 pub mod mesh;
 
 pub trait Component {
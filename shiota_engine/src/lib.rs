@@ -1,6 +1,6 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
-use prism::{PrismRenderer, Shader, glm::Vec4};
+use prism::{PrismRenderer, backend::Screen, post::PostProcessStack, config::{GraphicsApi, PrismConfig}, glm::{Vec3, Vec4}, material::Material, model::{Model, ModelData}, layout::ScreenLayout, shader::{PHONG_FRAGMENT, PHONG_VERTEX}, sprite::SpriteBatch, time::FixedTimestep, uniform_block::MAX_LIGHTS};
 use uuid::{Uuid, uuid};
 
 use crate::scene::Scene;
@@ -10,90 +10,21 @@ pub mod objects;
 pub mod scene;
 
 pub use prism::glm;
+pub use prism::uniform_block::{Light, LightKind};
 
 pub struct Engine {
     application_name: String,
-    rendering_context: PrismRenderer,
     scenes: HashMap<Uuid, Scene>,
     main_scene_id: Option<Uuid>,
     current_scene: Option<Uuid>,
-    shader: Option<Shader>
+    default_material: Material,
+    fixed_timestep: FixedTimestep,
+    // Declared last so that scene resources are dropped before the renderer shuts down.
+    rendering_context: PrismRenderer,
 }
 
 impl Engine {
     pub fn run(&mut self) -> Result<(), String> {
-        let vert_shader = 
-        r#"#version 330 core
-
-        layout (location = 0) in vec3 aPos;
-        layout (location = 1) in vec3 aNormal;
-        layout (location = 2) in vec2 aTexCoords;
-
-        out vec3 FragPos;
-        out vec3 Normal;
-        out vec2 TexCoords;
-
-        uniform mat4 model;
-        uniform mat4 view;
-        uniform mat4 projection;
-
-        void main() {
-            FragPos = vec3(model * vec4(aPos, 1.0));
-            Normal = mat3(transpose(inverse(model))) * aNormal;  
-            TexCoords = aTexCoords;
-
-            gl_Position = projection * view * vec4(FragPos, 1.0);
-        }"#;
-
-        let frag_shader = 
-        r#"#version 330 core
-        out vec4 FragColor;
-
-        in vec3 FragPos;
-        in vec3 Normal;
-        in vec2 TexCoords;
-
-        uniform sampler2D texture_0;
-        uniform vec3 viewPos;
-
-        struct Light {
-            vec3 position;
-
-            vec3 ambient;
-            vec3 diffuse;
-            vec3 specular;
-        };
-
-        struct Material {
-            vec3 ambient;
-            vec3 diffuse;
-            vec3 specular;
-            float shininess;
-        };
-
-        uniform Material material;
-        uniform Light light;
-
-        void main() {
-            vec3 ambient = light.ambient * material.ambient;
-
-            // diffuse
-            vec3 norm = normalize(Normal);
-            vec3 lightDir = normalize(light.position - FragPos);
-            float diff = max(dot(norm, lightDir), 0.0);
-            vec3 diffuse = light.diffuse * (diff * material.diffuse);
-
-            // specular
-            vec3 viewDir = normalize(viewPos - FragPos);
-            vec3 reflectDir = reflect(-lightDir, norm);
-            float spec = pow(max(dot(viewDir, reflectDir), 0.0), material.shininess);
-            vec3 specular = light.specular * (spec * material.specular);
-
-            vec3 result = (ambient + diffuse + specular) * texture(texture_0, TexCoords).rgb;
-            FragColor = vec4(result, 1.0);
-        }
-        "#;
-
         if let Some(id) = self.main_scene_id {
             if let Some(_) = self.scenes.get(&id) {
                 self.current_scene = Some(id);
@@ -105,19 +36,21 @@ impl Engine {
 
         let ctx = &mut self.rendering_context;
 
-        let shader = ctx.create_shader_from_source(vert_shader, frag_shader).unwrap();
-        self.shader = Some(shader);
-
         while !ctx.should_close() {
             let current_scene = self.scenes.get_mut(&self.current_scene.expect("The scene should have been loaded.")).unwrap();
             ctx.handle_events();
+
+            // Updates run at a fixed rate; rendering uses the real frame delta.
+            for _ in 0..self.fixed_timestep.accumulate(ctx.get_delta()) {
+                current_scene.update_upper(self.fixed_timestep.step());
+                current_scene.update_lower(self.fixed_timestep.step());
+            }
+
             ctx.begin_upper_screen();
             ctx.clear_screen(Vec4::new(0.0, 0.0, 0.0, 1.0));
-            current_scene.update_upper(ctx.get_delta());
-            current_scene.render_upper(ctx.get_delta(), ctx, &mut self.shader.as_mut().unwrap());
+            current_scene.render_upper(ctx.get_delta(), ctx, &mut self.default_material);
             ctx.end_upper_screen();
             ctx.begin_lower_screen();
-            current_scene.update_lower(ctx.get_delta());
             current_scene.render_lower(ctx.get_delta(), ctx);
             ctx.end_lower_screen();
         }
@@ -125,6 +58,33 @@ impl Engine {
         Ok(())
     }
 
+    /// Material of objects without a `MaterialComponent`. Clone it as a starting point for
+    /// custom materials using the built-in Phong shader.
+    pub fn default_material(&self) -> &Material {
+        &self.default_material
+    }
+
+    pub fn default_material_mut(&mut self) -> &mut Material {
+        &mut self.default_material
+    }
+
+    /// The renderer, for creating meshes, textures and render targets.
+    pub fn rendering_context(&mut self) -> &mut PrismRenderer {
+        &mut self.rendering_context
+    }
+
+    /// Loads an OBJ or glTF model whose materials use the built-in Phong shader. Add it to a
+    /// scene with `Scene::add_model_upper`.
+    pub fn load_model(&mut self, path: &Path) -> Result<Model, String> {
+        let data = ModelData::load(path)?;
+        self.rendering_context.create_model(&data, self.default_material.shader())
+    }
+
+    /// Sets the effects a screen is post-processed with; an empty stack turns them off.
+    pub fn set_post_processing(&mut self, screen: Screen, stack: PostProcessStack) -> Result<(), String> {
+        self.rendering_context.set_post_processing(screen, stack)
+    }
+
     pub fn create_scene(&mut self) -> &mut Scene {
         let scene = Scene {
             id: Uuid::new_v4(),
@@ -132,6 +92,12 @@ impl Engine {
             lower_screen_camera_id: None,
             upper_screen_objects: HashMap::new(),
             lower_screen_objects: HashMap::new(),
+            lights_per_object: MAX_LIGHTS,
+            shadow_center: Vec3::ZERO,
+            shadow_radius: 20.0,
+            lower_sprites: SpriteBatch::new(),
+            instancing: true,
+            instance_buffer: None,
         };
         let id = scene.id;
         self.scenes.insert(id, scene);
@@ -146,12 +112,16 @@ impl Engine {
 
 pub struct EngineBuilder {
     application_name: String,
+    prism_config: PrismConfig,
+    fixed_update_rate: f32,
 }
 
 impl EngineBuilder {
     pub fn new() -> Self {
         Self {
             application_name: String::from("Shiota Application"),
+            prism_config: PrismConfig::default(),
+            fixed_update_rate: 60.0,
         }
     }
 
@@ -160,17 +130,50 @@ impl EngineBuilder {
         self
     }
 
+    pub fn screen_layout(mut self, layout: ScreenLayout) -> Self {
+        self.prism_config.layout = layout;
+        self
+    }
+
+    pub fn graphics_api(mut self, api: GraphicsApi) -> Self {
+        self.prism_config.graphics_api = api;
+        self
+    }
+
+    pub fn prism_config(mut self, config: PrismConfig) -> Self {
+        self.prism_config = config;
+        self
+    }
+
+    /// How many times per second scenes are updated, independent of the frame rate.
+    pub fn fixed_update_rate(mut self, updates_per_second: f32) -> Self {
+        self.fixed_update_rate = updates_per_second;
+        self
+    }
+
+    pub fn target_frame_rate(mut self, frame_rate: Option<f32>) -> Self {
+        self.prism_config.target_frame_rate = frame_rate;
+        self
+    }
+
     pub fn build(self) -> Result<Engine, String> {
-        let mut context = PrismRenderer::new();
+        let fixed_timestep = FixedTimestep::new(self.fixed_update_rate)?;
+
+        let mut context = PrismRenderer::with_config(self.prism_config);
         match context.init() {
             Ok(()) => {
+                let shader = context.create_shader_from_source(PHONG_VERTEX, PHONG_FRAGMENT)?;
+                let mut default_material = Material::new(&shader);
+                default_material.set_phong(Vec3::new(0.725, 0.949, 1.0), Vec3::new(0.745, 0.949, 1.0), Vec3::new(0.5, 0.5, 0.5), 32.0);
+
                 Ok(Engine {
                     application_name: self.application_name,
                     rendering_context: context,
                     scenes: HashMap::new(),
                     main_scene_id: None,
                     current_scene: None,
-                    shader: None
+                    default_material,
+                    fixed_timestep,
                 })
             },
             Err(s) => {
@@ -1,14 +1,16 @@
 use std::{any::{Any, TypeId}, collections::HashMap};
 
-use prism::{Shader, glm::{Mat4, Vec3}};
+use prism::{glm::{Mat4, Vec3}, instancing::InstanceBuffer, material::Material, mesh::Mesh, uniform_block::{Light, LightsBlock}};
 use uuid::Uuid;
 
-use crate::components::{Component, mesh::MeshComponent};
+use crate::components::{Component, camera::CameraComponent, material::MaterialComponent, mesh::MeshComponent};
 
 pub struct Object {
     pub(crate) id: Uuid,
     name: String,
     components: HashMap<TypeId, Box<dyn Any>>,
+    casts_shadows: bool,
+    receives_shadows: bool,
 }
 
 impl Object {
@@ -20,12 +22,35 @@ impl Object {
         self.name = String::from(name);
     }
 
+    pub fn casts_shadows(&self) -> bool {
+        self.casts_shadows
+    }
+
+    /// Whether the object's mesh is drawn into the shadow map of its screen.
+    pub fn set_casts_shadows(&mut self, casts_shadows: bool) {
+        self.casts_shadows = casts_shadows;
+    }
+
+    pub fn receives_shadows(&self) -> bool {
+        self.receives_shadows
+    }
+
+    pub fn set_receives_shadows(&mut self, receives_shadows: bool) {
+        self.receives_shadows = receives_shadows;
+    }
+
     pub fn try_get_component<T: Component + 'static>(&self) -> Option<&T> {
         self.components
             .get(&TypeId::of::<T>())
             .and_then(|component| Some(component.as_ref().downcast_ref::<T>().expect(format!("ShiotaEngine: Type Error: Type {} was found in Component Table. Component Table should only contain dyn Components.", std::any::type_name::<T>()).as_str())))
     }
 
+    pub fn try_get_component_mut<T: Component + 'static>(&mut self) -> Option<&mut T> {
+        self.components
+            .get_mut(&TypeId::of::<T>())
+            .map(|component| component.as_mut().downcast_mut::<T>().unwrap_or_else(|| panic!("ShiotaEngine: Type Error: Type {} was found in Component Table. Component Table should only contain dyn Components.", std::any::type_name::<T>())))
+    }
+
     pub fn add_component<T: Component + 'static>(&mut self, component: T) {
         self.components
             .insert(TypeId::of::<T>(), Box::new(component));
@@ -35,21 +60,76 @@ impl Object {
         todo!("Implement Object Update Logic");
     }
 
-    pub(crate) fn render(&self, delta_time: f32, renderer: &mut prism::PrismRenderer, camera: &prism::Camera, shader: &mut Shader) {
+    /// The camera is set once per screen by the scene; the object picks the `light_count` of
+    /// the screen's `lights` most relevant to it.
+    pub(crate) fn render(&mut self, delta_time: f32, renderer: &mut prism::PrismRenderer, default_material: &mut Material, lights: &[Light], light_count: usize) {
+        self.draw(renderer, default_material, lights, light_count, None);
+    }
+
+    /// Draws the object's mesh and material once per instance in `instances`, standing in
+    /// for the whole group of objects they place. Every instance is lit by `lights_block`.
+    pub(crate) fn render_instanced(&mut self, renderer: &mut prism::PrismRenderer, default_material: &mut Material, lights_block: &LightsBlock, instances: &InstanceBuffer) {
+        self.draw(renderer, default_material, &[], 0, Some((instances, lights_block)));
+    }
+
+    fn draw(&mut self, renderer: &mut prism::PrismRenderer, default_material: &mut Material, lights: &[Light], light_count: usize, instanced: Option<(&InstanceBuffer, &LightsBlock)>) {
+        let [mesh_component, material_component] = self.components.get_disjoint_mut([&TypeId::of::<MeshComponent>(), &TypeId::of::<MaterialComponent>()]);
+
+        if let Some(mesh_component) = mesh_component.and_then(|component| component.downcast_ref::<MeshComponent>()) {
+            let material = match material_component.and_then(|component| component.downcast_mut::<MaterialComponent>()) {
+                Some(material_component) => &mut material_component.material,
+                None => default_material,
+            };
+            material.state.receive_shadows = self.receives_shadows;
+
+            match instanced {
+                Some((instances, lights_block)) => {
+                    renderer.set_lights_block(lights_block);
+                    renderer.draw_mesh_instanced(&mesh_component.mesh, material, instances);
+                }
+                None => {
+                    renderer.set_lights_block(&LightsBlock::gather(lights, mesh_component.mesh.translation(), light_count));
+                    renderer.draw_mesh(&mesh_component.mesh, material);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn mesh(&self) -> Option<&Mesh> {
+        self.try_get_component::<MeshComponent>().map(|mesh_component| &mesh_component.mesh)
+    }
+
+    /// Where the object is: its mesh's model matrix, or its camera's placement if it has no
+    /// mesh, or the identity.
+    pub(crate) fn transform(&self) -> Mat4 {
+        match (self.mesh(), self.try_get_component::<CameraComponent>()) {
+            (Some(mesh), _) => mesh.model_matrix(),
+            (None, Some(camera_component)) => camera_component.get_prism_camera().get_view_matrix().inverse(),
+            (None, None) => Mat4::IDENTITY,
+        }
+    }
+
+    /// Distance from `eye` to the centre of the object's mesh, for sorting draws.
+    pub(crate) fn view_distance(&self, eye: Vec3) -> f32 {
+        let center = self.mesh().map_or(Vec3::ZERO, |mesh| match mesh.bounding_sphere() {
+            Some(sphere) => sphere.center,
+            None => mesh.translation(),
+        });
+        center.distance(eye)
+    }
+
+    /// The material the object is drawn with: its own, or `default_material` if it has none.
+    pub(crate) fn material<'a>(&'a self, default_material: &'a Material) -> &'a Material {
+        self.try_get_component::<MaterialComponent>().map_or(default_material, |material_component| &material_component.material)
+    }
+
+    pub(crate) fn render_shadow(&self, renderer: &mut prism::PrismRenderer) {
+        if !self.casts_shadows {
+            return;
+        }
+
         if let Some(mesh_component) = self.try_get_component::<MeshComponent>() {
-            let projection_transform = Mat4::perspective_rh(45.0f32.to_radians(), 800.0 / 480.0, 0.1, 100.0);
-            shader.set_uniform_mat4("view", camera.get_view_matrix());
-            shader.set_uniform_mat4("projection", projection_transform);
-            shader.set_uniform_vec3("viewPos", camera.get_position());
-            shader.set_uniform_vec3("material.ambient", Vec3::new(0.725, 0.949, 1.0));
-            shader.set_uniform_vec3("material.diffuse", Vec3::new(0.745, 0.949, 1.0));
-            shader.set_uniform_vec3("material.specular", Vec3::new(0.5, 0.5, 0.5));
-            shader.set_uniform_float("material.shininess", 32.0);
-            shader.set_uniform_vec3("light.position", Vec3::new(1.2, 1.0, 8.0));
-            shader.set_uniform_vec3("light.ambient", Vec3::new(0.2, 0.2, 0.2));
-            shader.set_uniform_vec3("light.diffuse", Vec3::new(0.5, 0.5, 0.5));
-            shader.set_uniform_vec3("light.specular", Vec3::new(1.0, 1.0, 1.0));
-            renderer.draw_mesh(&mesh_component.mesh, shader);
+            renderer.draw_shadow_caster(&mesh_component.mesh);
         }
     }
 
@@ -58,6 +138,8 @@ impl Object {
             id: Uuid::new_v4(),
             name: "New Object".to_string(),
             components: HashMap::new(),
+            casts_shadows: true,
+            receives_shadows: true,
         }
     }
 
@@ -1,9 +1,9 @@
 use std::collections::HashMap;
 
-use prism::{Camera, PrismRenderer, Shader};
+use prism::{Camera, PrismRenderer, bounds::Frustum, instancing::{Instance, InstanceBuffer}, material::{BlendMode, Material}, backend::Screen, glm::{Mat4, Vec3, Vec4}, model::Model, render_target::RenderTarget, sprite::SpriteBatch, uniform_block::{CameraBlock, Light, LightKind, LightsBlock, MAX_LIGHTS}};
 use uuid::Uuid;
 
-use crate::{components::camera::CameraComponent, objects::Object};
+use crate::{components::{camera::CameraComponent, light::LightComponent, material::MaterialComponent, mesh::MeshComponent}, objects::Object};
 
 pub struct Scene {
     pub(crate) id: Uuid,
@@ -11,6 +11,13 @@ pub struct Scene {
     pub(crate) lower_screen_camera_id: Option<Uuid>, // Camera is optional for bottom screen if you don't want to render anything there
     pub(crate) upper_screen_objects: HashMap<Uuid, Object>,
     pub(crate) lower_screen_objects: HashMap<Uuid, Object>,
+    pub(crate) lights_per_object: usize,
+    pub(crate) shadow_center: Vec3,
+    pub(crate) shadow_radius: f32,
+    pub(crate) lower_sprites: SpriteBatch,
+    pub(crate) instancing: bool,
+    /// Refilled for every instanced group drawn.
+    pub(crate) instance_buffer: Option<InstanceBuffer>,
 }
 
 impl Scene {
@@ -18,11 +25,192 @@ impl Scene {
         //todo!()
     }
 
-    pub(crate) fn render_upper(&mut self, delta_time: f32, renderer: &mut PrismRenderer, shader: &mut Shader) {
+    pub(crate) fn render_upper(&mut self, delta_time: f32, renderer: &mut PrismRenderer, default_material: &mut Material) {
         let camera_object = self.upper_screen_objects.get(&self.upper_screen_camera_id.unwrap()).unwrap();
         let prism_camera: Camera = camera_object.try_get_component::<CameraComponent>().unwrap().get_prism_camera();
-        for object in self.upper_screen_objects.values_mut() {
-            object.render(delta_time, renderer, &prism_camera, shader);
+
+        // Gathered once per screen; each object then picks the ones most relevant to it.
+        let mut lights: Vec<Light> = self.upper_screen_objects
+            .values()
+            .filter_map(|object| Some(object.try_get_component::<LightComponent>()?.world_light(object.transform())))
+            .collect();
+
+        // Without any lights everything would be black.
+        if lights.is_empty() {
+            lights.push(fallback_light());
+        }
+
+        // The first directional or spot light marked as casting shadows gets the shadow map;
+        // the renderer clears the mark on the others when uploading their lights.
+        if let Some(light) = lights.iter().find(|light| light.casts_shadows && light.kind != LightKind::Point) {
+            match renderer.begin_shadow_pass(light, self.shadow_center, self.shadow_radius) {
+                Ok(()) => {
+                    self.render_shadow_casters(renderer);
+                    renderer.end_shadow_pass();
+                }
+                Err(e) => eprintln!("ShiotaEngine: skipping shadows: {}", e),
+            }
+        }
+
+        // Cameras with a render target draw the scene into their texture first, leaving out
+        // the object they belong to so that it does not sample the texture being drawn.
+        let targets: Vec<(Uuid, Camera, RenderTarget)> = self.upper_screen_objects
+            .values()
+            .filter_map(|object| {
+                let camera_component = object.try_get_component::<CameraComponent>()?;
+                let target = camera_component.render_target()?.clone();
+                Some((object.id, camera_component.get_prism_camera(), target))
+            })
+            .collect();
+
+        for (owner, camera, target) in targets {
+            renderer.begin_render_target(&target);
+            renderer.clear_screen(Vec4::new(0.0, 0.0, 0.0, 1.0));
+            self.render_objects(delta_time, renderer, default_material, &lights, &CameraBlock::new(&camera, target.aspect_ratio()), Some(owner));
+            renderer.end_render_target();
+        }
+
+        let camera = CameraBlock::new(&prism_camera, renderer.aspect_ratio(Screen::Upper));
+        self.render_objects(delta_time, renderer, default_material, &lights, &camera, None);
+    }
+
+    /// Draws the upper screen's objects `camera` can see, except `skip`. Opaque objects go
+    /// first, by shader and then nearest first so that hidden pixels fail the depth test
+    /// early; transparent ones follow, furthest first so that each blends over what is behind
+    /// it. Objects with the same mesh and an opaque material are drawn instanced.
+    fn render_objects(&mut self, delta_time: f32, renderer: &mut PrismRenderer, default_material: &mut Material, lights: &[Light], camera: &CameraBlock, skip: Option<Uuid>) {
+        renderer.set_camera_block(camera);
+
+        let frustum = Frustum::from_matrix(camera.projection * camera.view);
+        let visible: Vec<Uuid> = self.upper_screen_objects
+            .values()
+            .filter(|object| Some(object.id) != skip)
+            .filter(|object| object.mesh().is_some_and(|mesh| mesh.bounds().is_none_or(|bounds| frustum.intersects_aabb(&bounds))))
+            .map(|object| object.id)
+            .collect();
+
+        // Instances share one lights block, so only objects picking the same lights are drawn
+        // together; otherwise outlying instances would lose the lights nearest to them.
+        let object_lights: HashMap<Uuid, LightsBlock> = visible
+            .iter()
+            .filter_map(|id| Some((*id, LightsBlock::gather(lights, self.upper_screen_objects[id].mesh()?.translation(), self.lights_per_object))))
+            .collect();
+
+        let groups = self.instancing_groups(&visible, |first, object| {
+            let material = object.material(default_material);
+            material.state.blend == BlendMode::Opaque
+                && first.receives_shadows() == object.receives_shadows()
+                && first.material(default_material).draws_like(material)
+                && same_lights(&object_lights[&first.id], &object_lights[&object.id])
+        });
+
+        // Groups whose materials draw alike share a key, so that they are drawn one after
+        // another and their textures and parameters are only switched to once.
+        let mut materials: Vec<&Material> = Vec::new();
+        let (mut opaque, mut transparent): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .map(|group| {
+                let material = self.upper_screen_objects[&group[0]].material(default_material);
+                let material_key = match materials.iter().position(|other| other.draws_like(material)) {
+                    Some(key) => key,
+                    None => {
+                        materials.push(material);
+                        materials.len() - 1
+                    }
+                };
+                let distance = group
+                    .iter()
+                    .map(|id| self.upper_screen_objects[id].view_distance(camera.view_position))
+                    .fold(f32::INFINITY, f32::min);
+                (material.shader().handle(), material_key, material.state.blend, distance, group)
+            })
+            .partition(|(_, _, blend, _, _)| *blend == BlendMode::Opaque);
+
+        opaque.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)).then(a.3.total_cmp(&b.3)));
+        transparent.sort_by(|a, b| b.3.total_cmp(&a.3));
+        let groups = opaque.into_iter().chain(transparent).map(|(_, _, _, _, group)| group);
+
+        for group in groups {
+            if group.len() > 1 && self.upload_instances(renderer, &group) {
+                let instances = self.instance_buffer.as_ref().unwrap();
+                let first = self.upper_screen_objects.get_mut(&group[0]).unwrap();
+                first.render_instanced(renderer, default_material, &object_lights[&group[0]], instances);
+                continue;
+            }
+
+            for id in &group {
+                let object = self.upper_screen_objects.get_mut(id).unwrap();
+                object.render(delta_time, renderer, default_material, lights, self.lights_per_object);
+            }
+        }
+    }
+
+    /// Groups the objects in `ids` that have a mesh by which ones can be drawn together:
+    /// those with the same mesh for which `same_group` holds. Every object gets a group of its
+    /// own when instancing is off.
+    fn instancing_groups(&self, ids: &[Uuid], same_group: impl Fn(&Object, &Object) -> bool) -> Vec<Vec<Uuid>> {
+        let mut groups: Vec<Vec<Uuid>> = Vec::new();
+
+        for object in ids.iter().map(|id| &self.upper_screen_objects[id]) {
+            let Some(mesh) = object.mesh() else {
+                continue;
+            };
+
+            let group = groups.iter_mut().filter(|_| self.instancing).find(|group| {
+                let first = &self.upper_screen_objects[&group[0]];
+                first.mesh().is_some_and(|first_mesh| first_mesh.draws_like(mesh)) && same_group(first, object)
+            });
+
+            match group {
+                Some(group) => group.push(object.id),
+                None => groups.push(vec![object.id]),
+            }
+        }
+
+        groups
+    }
+
+    /// Fills the instance buffer with the transforms of `group`. Returns false if it could not
+    /// be created, in which case the objects are drawn one by one.
+    fn upload_instances(&mut self, renderer: &mut PrismRenderer, group: &[Uuid]) -> bool {
+        let instances: Vec<Instance> = group
+            .iter()
+            .filter_map(|id| self.upper_screen_objects[id].mesh())
+            .map(|mesh| Instance::new(mesh.model_matrix()))
+            .collect();
+
+        match &mut self.instance_buffer {
+            Some(buffer) => renderer.update_instance_buffer(buffer, &instances),
+            None => match renderer.create_instance_buffer(&instances) {
+                Ok(buffer) => self.instance_buffer = Some(buffer),
+                Err(e) => {
+                    eprintln!("ShiotaEngine: drawing objects without instancing: {}", e);
+                    return false;
+                }
+            },
+        }
+
+        true
+    }
+
+    fn render_shadow_casters(&mut self, renderer: &mut PrismRenderer) {
+        // Not culled against the camera, as objects out of view can still shadow what is in it.
+        let casters: Vec<Uuid> = self.upper_screen_objects
+            .values()
+            .filter(|object| object.casts_shadows())
+            .map(|object| object.id)
+            .collect();
+
+        for group in self.instancing_groups(&casters, |_, _| true) {
+            if group.len() > 1 && self.upload_instances(renderer, &group) {
+                let mesh = self.upper_screen_objects[&group[0]].mesh().unwrap();
+                renderer.draw_shadow_caster_instanced(mesh, self.instance_buffer.as_ref().unwrap());
+                continue;
+            }
+
+            for id in &group {
+                self.upper_screen_objects[id].render_shadow(renderer);
+            }
         }
     }
 
@@ -31,7 +219,7 @@ impl Scene {
     }
 
     pub(crate) fn render_lower(&mut self, delta_time: f32, renderer: &mut PrismRenderer) {
-        //todo!()
+        renderer.draw_sprite_batch(&self.lower_sprites);
     }
     
     pub fn get_id(&self) -> Uuid {
@@ -42,7 +230,61 @@ impl Scene {
         self.upper_screen_objects.insert(object.id, object);
     }
 
+    /// Adds an object for every mesh of `model`, named after its node and placed by the node
+    /// hierarchy on top of `transform`. Returns the ids of the new objects.
+    pub fn add_model_upper(&mut self, model: &Model, transform: Mat4) -> Vec<Uuid> {
+        model
+            .instances(transform)
+            .into_iter()
+            .map(|instance| {
+                let mut object = Object::new();
+                object.set_name(&instance.name);
+                object.add_component(MeshComponent::new(instance.mesh));
+                object.add_component(MaterialComponent::new(instance.material));
+                let id = object.get_id();
+                self.add_object_upper(object);
+                id
+            })
+            .collect()
+    }
+
+    /// How many of the scene's lights each object is lit by, at most `MAX_LIGHTS`.
+    pub fn set_lights_per_object(&mut self, count: usize) {
+        self.lights_per_object = count.min(MAX_LIGHTS);
+    }
+
+    /// Region the upper screen's shadow map covers. Keep it close around the objects casting
+    /// shadows: the larger it is, the blurrier they get.
+    pub fn set_shadow_bounds(&mut self, center: Vec3, radius: f32) {
+        self.shadow_center = center;
+        self.shadow_radius = radius;
+    }
+
+    /// 2D content drawn on the lower screen every frame, in pixels. It is kept between
+    /// frames, so clear it before redrawing anything that changes.
+    pub fn lower_sprites(&mut self) -> &mut SpriteBatch {
+        &mut self.lower_sprites
+    }
+
+    /// Whether objects sharing a mesh and an equivalent material, and lit by the same lights,
+    /// are drawn with one instanced draw call. On by default. Clone a mesh to share it
+    /// between objects.
+    pub fn set_instancing(&mut self, enabled: bool) {
+        self.instancing = enabled;
+    }
+
     pub fn set_upper_camera(&mut self, id: Uuid) {
         self.upper_screen_camera_id = Some(id);
     }
-}
\ No newline at end of file
+}
+
+/// Lights scenes without a `LightComponent`, as the engine lit every scene before lights were
+/// components.
+fn fallback_light() -> Light {
+    Light::point(Vec3::new(1.2, 1.0, 8.0), Vec3::splat(0.2), Vec3::splat(0.5), Vec3::ONE)
+}
+
+/// Whether both blocks hold the same lights, in any order; the shaders add them up.
+fn same_lights(a: &LightsBlock, b: &LightsBlock) -> bool {
+    a.lights.len() == b.lights.len() && a.lights.iter().all(|light| b.lights.contains(light))
+}
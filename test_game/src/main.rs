@@ -1,8 +1,10 @@
-use shiota_engine::{EngineBuilder, components::camera::CameraComponent, glm::Vec3, objects::Object};
+use shiota_engine::{EngineBuilder, Light, components::{camera::CameraComponent, light::LightComponent, mesh::MeshComponent}, glm::Vec3, objects::Object};
 
 fn main() {
     let mut engine = EngineBuilder::new().application_name("Test Application").build().expect("Failed to build engine");
 
+    let cube_mesh = MeshComponent::cube(engine.rendering_context(), 1.0);
+
     let id;
     {
         let main_scene = engine.create_scene();
@@ -15,6 +17,26 @@ fn main() {
         camera.add_component(camera_component);
         main_scene.add_object_upper(camera);
         main_scene.set_upper_camera(camera_id);
+
+        let mut light = Object::new();
+        light.set_name("Light");
+        light.add_component(LightComponent::new(Light::point(Vec3::new(1.2, 1.0, 8.0), Vec3::splat(0.2), Vec3::splat(0.5), Vec3::ONE)));
+        main_scene.add_object_upper(light);
+
+        // Clones share the cube's buffers, so the row is drawn with one instanced draw.
+        for i in 1..=4 {
+            let mut mesh = cube_mesh.mesh().clone();
+            mesh.set_translation(Vec3::new(i as f32 * 2.0, 0.0, 0.0));
+            let mut copy = Object::new();
+            copy.set_name(&format!("Cube {}", i));
+            copy.add_component(MeshComponent::new(mesh));
+            main_scene.add_object_upper(copy);
+        }
+
+        let mut cube = Object::new();
+        cube.set_name("Cube");
+        cube.add_component(cube_mesh);
+        main_scene.add_object_upper(cube);
     }
     engine.set_main_scene(id);
 